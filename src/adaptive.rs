@@ -0,0 +1,146 @@
+use super::camera::Camera;
+use super::canvas::{Canvas, Colour};
+use super::random::Rng;
+use super::render::{self, Settings};
+use super::world::World;
+
+// Renders one sample per pixel, then goes back over pixels that differ from
+// a neighbour by more than threshold, sampling the corners of the pixel and
+// splitting it into quarters while the corners disagree, up to max_level.
+#[derive(Debug, Clone, Copy)]
+pub struct Adaptive {
+    pub threshold: f64,
+    pub max_level: usize,
+}
+
+fn contrast(a: Colour, b: Colour) -> f64 {
+    (a.r - b.r).abs().max((a.g - b.g).abs()).max((a.b - b.b).abs())
+}
+
+fn average(c: &[Colour]) -> Colour {
+    c.iter().fold(Colour::new(0.0, 0.0, 0.0), |t, &c| t + c) * (1.0 / c.len() as f64)
+}
+
+impl Adaptive {
+    pub fn new() -> Adaptive {
+        Adaptive { threshold: 0.1, max_level: 3 }
+    }
+
+    fn differs(&self, corners: &[Colour; 4]) -> bool {
+        let (lo, hi) = corners.iter().fold((corners[0], corners[0]), |(lo, hi), c| {
+            (Colour::new(lo.r.min(c.r), lo.g.min(c.g), lo.b.min(c.b)),
+             Colour::new(hi.r.max(c.r), hi.g.max(c.g), hi.b.max(c.b)))
+        });
+        contrast(lo, hi) > self.threshold
+    }
+
+    // corners are top left, top right, bottom left, bottom right of the square
+    // at (x, y); returns its colour and the deepest level it was split to
+    fn refine<F>(&self, sample: &mut F, x: f64, y: f64, size: f64, corners: [Colour; 4], level: usize) -> (Colour, usize)
+        where F: FnMut(f64, f64) -> Colour {
+        if level >= self.max_level || !self.differs(&corners) {
+            return (average(&corners), level);
+        }
+        let half = size / 2.0;
+        let top = sample(x + half, y);
+        let left = sample(x, y + half);
+        let centre = sample(x + half, y + half);
+        let right = sample(x + size, y + half);
+        let bottom = sample(x + half, y + size);
+
+        let quarters = [
+            self.refine(sample, x, y, half, [corners[0], top, left, centre], level + 1),
+            self.refine(sample, x + half, y, half, [top, corners[1], centre, right], level + 1),
+            self.refine(sample, x, y + half, half, [left, centre, corners[2], bottom], level + 1),
+            self.refine(sample, x + half, y + half, half, [centre, right, bottom, corners[3]], level + 1),
+        ];
+        let colours: Vec<Colour> = quarters.iter().map(|q| q.0).collect();
+        (average(&colours), quarters.iter().map(|q| q.1).max().unwrap_or(level))
+    }
+
+    // Returns the image and a debug image that is brighter where pixels were
+    // refined further, black where the single sample was kept.
+    pub fn render(&self, camera: &Camera, world: &World, settings: &Settings) -> (Canvas, Canvas) {
+        let (w, h) = (camera.hsize, camera.vsize);
+        let mut single = *settings;
+        single.samples = 1;
+        let first = render::render(camera, world, &single);
+
+        let needs_refining = |x: usize, y: usize| {
+            let c = first.read(x, y);
+            let neighbours = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+            neighbours.iter().any(|&(nx, ny)| nx < w && ny < h && contrast(c, first.read(nx, ny)) > self.threshold)
+        };
+
+        let refined = render::parallel_pixels(w, h, settings.threads, |x, y| {
+            if self.max_level == 0 || !needs_refining(x, y) {
+                return (first.read(x, y), 0);
+            }
+            let (ox, oy) = camera.offset();
+            let mut rng = Rng::keyed(settings.seed, &[(x + ox) as u64, (y + oy) as u64, 1]);
+            let mut sample = |px: f64, py: f64| render::sample(camera, world, settings, px, py, &mut rng);
+            let (x0, y0) = (x as f64, y as f64);
+            let corners = [sample(x0, y0), sample(x0 + 1.0, y0), sample(x0, y0 + 1.0), sample(x0 + 1.0, y0 + 1.0)];
+            self.refine(&mut sample, x0, y0, 1.0, corners, 1)
+        });
+
+        let mut image = Canvas::new(w, h);
+        let mut debug = Canvas::new(w, h);
+        for (x, column) in refined.into_iter().enumerate() {
+            for (y, (c, level)) in column.into_iter().enumerate() {
+                image.write(x, y, c);
+                let t = level as f64 / self.max_level.max(1) as f64;
+                debug.write(x, y, Colour::new(t, t, t));
+            }
+        }
+        (image, debug)
+    }
+}
+
+#[cfg(test)]
+mod adaptive_tests {
+    use super::*;
+    use light::Light;
+    use sphere::Sphere;
+    use transformation::Vector;
+
+    #[test]
+    fn refines_until_corners_agree() {
+        let a = Adaptive { threshold: 0.1, max_level: 4 };
+        // a vertical edge at x = 0.3
+        let mut samples = 0;
+        let mut sample = |x: f64, _y: f64| {
+            samples += 1;
+            if x < 0.3 { Colour::new(1.0, 1.0, 1.0) } else { Colour::new(0.0, 0.0, 0.0) }
+        };
+        let white = Colour::new(1.0, 1.0, 1.0);
+        let black = Colour::new(0.0, 0.0, 0.0);
+        let (c, level) = a.refine(&mut sample, 0.0, 0.0, 1.0, [white, black, white, black], 1);
+        assert_eq!(level, 4);
+        assert!((c.r - 0.3).abs() < 0.1);
+
+        let (c, level) = a.refine(&mut sample, 0.0, 0.0, 1.0, [white, white, white, white], 1);
+        assert_eq!((c, level), (white, 1));
+        assert!(samples > 0);
+    }
+
+    #[test]
+    fn only_edges_are_refined() {
+        let mut world = World::new();
+        world.objects.push(Box::new(Sphere::new()));
+        world.lights.push(Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(-10.0, 10.0, -10.0)));
+        let camera = Camera::new(20, 20, 0.8)
+                         .look_at(Vector::new(0.0, 0.0, -5.0), Vector::new(0.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0));
+        let adaptive = Adaptive { threshold: 0.5, max_level: 3 };
+        let (image, debug) = adaptive.render(&camera, &world, &Settings::new());
+
+        // background corners and the middle of the sphere are left alone
+        assert_eq!(debug.read(0, 0), Colour::new(0.0, 0.0, 0.0));
+        assert_eq!(debug.read(10, 10), Colour::new(0.0, 0.0, 0.0));
+        let refined = (0..20).flat_map(|x| (0..20).map(move |y| (x, y)))
+                             .filter(|&(x, y)| debug.read(x, y).r > 0.0)
+                             .count();
+        assert!(refined > 0 && refined < 200);
+        assert_eq!(image.read(0, 0), Colour::new(0.0, 0.0, 0.0));
+    }
+}
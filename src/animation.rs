@@ -0,0 +1,242 @@
+use std::path::Path;
+
+// Keyframe curves that change values in a scene file from frame to frame. A
+// curve's target is a dotted path into the scene's toml, such as
+// `lights.0.at` or `materials.glass.ior`, with numbers indexing arrays, and
+// its keys give the value there at some frames. Values are numbers or arrays,
+// which are blended number by number; anything else in them, such as the name
+// of a transform operation, has to match from key to key.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    // holds each key's value until the next
+    Step,
+    Linear,
+    // Cubic Bézier segments between keys, with handles that make the curve
+    // smooth through each key and level at the first and last.
+    Bezier,
+}
+
+impl Interpolation {
+    pub fn from_name(name: &str) -> Option<Interpolation> {
+        match name {
+            "step" => Some(Interpolation::Step),
+            "linear" => Some(Interpolation::Linear),
+            "bezier" => Some(Interpolation::Bezier),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Curve {
+    target: Vec<String>,
+    // in order of frame, no two at the same frame
+    keys: Vec<(f64, toml::Value)>,
+    interpolation: Interpolation,
+}
+
+// The weighted sum of values that share a shape, wherever they're numbers.
+// Sums of whole numbers that come out whole stay whole numbers, so counts can
+// be animated with step curves, or keys that land on them.
+fn blend(values: &[(&toml::Value, f64)]) -> Result<toml::Value, String> {
+    let number = |v: &toml::Value| match *v {
+        toml::Value::Integer(i) => Some(i as f64),
+        toml::Value::Float(f) => Some(f),
+        _ => None,
+    };
+    let first = values[0].0;
+    if values.iter().all(|&(v, _)| number(v).is_some()) {
+        let sum = values.iter().map(|&(v, w)| number(v).unwrap() * w).sum::<f64>();
+        if values.iter().all(|&(v, _)| v.is_integer()) && (sum - sum.round()).abs() < 1e-9 {
+            return Ok(toml::Value::Integer(sum.round() as i64));
+        }
+        return Ok(toml::Value::Float(sum));
+    }
+    if let toml::Value::Array(ref a) = *first {
+        let mut blended = vec!();
+        for i in 0..a.len() {
+            let mut items = vec!();
+            for &(v, w) in values {
+                match *v {
+                    toml::Value::Array(ref b) if b.len() == a.len() => items.push((&b[i], w)),
+                    _ => return Err("keys are arrays of different lengths".to_string()),
+                }
+            }
+            blended.push(blend(&items)?);
+        }
+        return Ok(toml::Value::Array(blended));
+    }
+    if values.iter().all(|&(v, _)| v == first) {
+        return Ok(first.clone());
+    }
+    Err(format!("keys differ in something that isn't a number, `{}`", first))
+}
+
+impl Curve {
+    // Keys are (frame, value) pairs in any order. Fails if there are none, if
+    // two share a frame, or if their values can't be blended.
+    pub fn new(target: &str, keys: &[(f64, toml::Value)], interpolation: Interpolation) -> Result<Curve, String> {
+        if target.is_empty() || target.split('.').any(|part| part.is_empty()) {
+            return Err(format!("`{}` isn't a path into the scene", target));
+        }
+        if keys.is_empty() {
+            return Err("curves need at least one key".to_string());
+        }
+        let mut keys = keys.to_vec();
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        if keys.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Err("two keys are at the same frame".to_string());
+        }
+        let even = 1.0 / keys.len() as f64;
+        blend(&keys.iter().map(|(_, v)| (v, even)).collect::<Vec<_>>())?;
+        Ok(Curve { target: target.split('.').map(|s| s.to_string()).collect(), keys, interpolation })
+    }
+
+    pub fn target(&self) -> String {
+        self.target.join(".")
+    }
+
+    // the curve's value at frame, which is the first or last key's before or
+    // after them all
+    pub fn value_at(&self, frame: f64) -> toml::Value {
+        let keys = &self.keys;
+        let next = keys.partition_point(|&(f, _)| f <= frame);
+        if next == 0 {
+            return keys[0].1.clone();
+        }
+        if next == keys.len() || self.interpolation == Interpolation::Step {
+            return keys[next - 1].1.clone();
+        }
+        let (i, j) = (next - 1, next);
+        let (f0, f1) = (keys[i].0, keys[j].0);
+        let t = (frame - f0) / (f1 - f0);
+        let weights = match self.interpolation {
+            Interpolation::Bezier => {
+                // the handles are a third of the way along the segment in
+                // the direction of the neighbouring keys' slope
+                let h = f1 - f0;
+                let a = if i == 0 { 0.0 } else { h / (3.0 * (f1 - keys[i - 1].0)) };
+                let c = if j + 1 == keys.len() { 0.0 } else { h / (3.0 * (keys[j + 1].0 - f0)) };
+                let s = 1.0 - t;
+                let (b0, b1, b2, b3) = (s * s * s, 3.0 * s * s * t, 3.0 * s * t * t, t * t * t);
+                vec!((i.saturating_sub(1), -a * b1), (i, b0 + b1 + c * b2), (j, a * b1 + b2 + b3), ((j + 1).min(keys.len() - 1), -c * b2))
+            },
+            _ => vec!((i, 1.0 - t), (j, t)),
+        };
+        let values: Vec<(&toml::Value, f64)> = weights.iter().map(|&(k, w)| (&keys[k].1, w)).collect();
+        // keys were checked to blend when the curve was made
+        blend(&values).unwrap()
+    }
+
+    // Sets the curve's target in doc, a parsed scene file, to its value at
+    // frame. Tables may gain the target's key, but everything leading to it
+    // must already be there.
+    pub fn apply(&self, doc: &mut toml::Value, frame: f64) -> Result<(), String> {
+        let missing = || format!("the scene has nothing at `{}` to animate", self.target());
+        let (last, path) = self.target.split_last().unwrap();
+        let mut v = doc;
+        for part in path {
+            v = match *v {
+                toml::Value::Table(ref mut t) => t.get_mut(part),
+                toml::Value::Array(ref mut a) => part.parse::<usize>().ok().and_then(move |i| a.get_mut(i)),
+                _ => None,
+            }.ok_or_else(missing)?;
+        }
+        match *v {
+            toml::Value::Table(ref mut t) => {
+                t.insert(last.clone(), self.value_at(frame));
+            },
+            toml::Value::Array(ref mut a) => match last.parse::<usize>().ok().and_then(|i| a.get_mut(i)) {
+                Some(item) => *item = self.value_at(frame),
+                None => return Err(missing()),
+            },
+            _ => return Err(missing()),
+        }
+        Ok(())
+    }
+}
+
+// Where frame is written for an animation rendered to filename: frame 12 of
+// out/shot.png is out/shot_0012.png.
+pub fn frame_filename(filename: &str, frame: usize) -> String {
+    let path = Path::new(filename);
+    let stem = path.file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{:04}.{}", stem, frame, ext.to_string_lossy()),
+        None => format!("{}_{:04}", stem, frame),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod animation_tests {
+    use super::*;
+
+    fn float(v: &toml::Value) -> f64 {
+        v.as_float().unwrap()
+    }
+
+    #[test]
+    fn interpolations() {
+        let keys = [(10.0, toml::Value::Float(2.0)), (0.0, toml::Value::Float(0.0)), (20.0, toml::Value::Float(2.0))];
+        let step = Curve::new("camera.field-of-view", &keys, Interpolation::Step).unwrap();
+        assert_eq!((float(&step.value_at(-5.0)), float(&step.value_at(9.9)), float(&step.value_at(10.0))), (0.0, 0.0, 2.0));
+
+        let linear = Curve::new("camera.field-of-view", &keys, Interpolation::Linear).unwrap();
+        assert_eq!(float(&linear.value_at(2.5)), 0.5);
+        assert_eq!(float(&linear.value_at(15.0)), 2.0);
+        assert_eq!(float(&linear.value_at(30.0)), 2.0);
+
+        // bezier curves pass through the keys, easing out of the first and
+        // into the last, and smooth through the rest
+        let bezier = Curve::new("camera.field-of-view", &keys, Interpolation::Bezier).unwrap();
+        for &(f, v) in &[(0.0, 0.0), (10.0, 2.0), (20.0, 2.0)] {
+            assert!((float(&bezier.value_at(f)) - v).abs() < 1e-12);
+        }
+        assert!(float(&bezier.value_at(1.0)) < float(&linear.value_at(1.0)));
+        let slope = |f: f64| (float(&bezier.value_at(f + 1e-6)) - float(&bezier.value_at(f - 1e-6))) / 2e-6;
+        assert!(slope(1e-3).abs() < 0.01);
+        assert!((slope(10.0 - 1e-3) - slope(10.0 + 1e-3)).abs() < 0.01 && slope(10.0) > 0.0);
+        // overshooting past the middle key on its way to the level last one
+        assert!(float(&bezier.value_at(12.0)) > 2.0);
+    }
+
+    #[test]
+    fn arrays_and_integers() {
+        let parse = |s: &str| s.parse::<toml::Table>().unwrap()["v"].clone();
+        let keys = [(0.0, parse("v = [\"translate\", 0, 0, 0]")), (4.0, parse("v = [\"translate\", 2, 4.0, -8]"))];
+        let c = Curve::new("objects.0.transform.0", &keys, Interpolation::Linear).unwrap();
+        assert_eq!(c.value_at(1.0), parse("v = [\"translate\", 0.5, 1.0, -2]"));
+        assert_eq!(c.value_at(2.0), parse("v = [\"translate\", 1, 2.0, -4]"));
+
+        let clash = [(0.0, parse("v = [\"translate\", 0, 0, 0]")), (1.0, parse("v = [\"scale\", 1, 1, 1]"))];
+        assert!(Curve::new("a", &clash, Interpolation::Linear).is_err());
+        assert!(Curve::new("a", &[(0.0, parse("v = [1, 2]")), (1.0, parse("v = [1, 2, 3]"))], Interpolation::Linear).is_err());
+        assert!(Curve::new("a", &[(0.0, parse("v = 1")), (0.0, parse("v = 2"))], Interpolation::Linear).is_err());
+        assert!(Curve::new("a..b", &[(0.0, parse("v = 1"))], Interpolation::Linear).is_err());
+        assert!(Curve::new("a", &[], Interpolation::Linear).is_err());
+    }
+
+    #[test]
+    fn applying() {
+        let mut doc: toml::Value = toml::from_str("[camera]\nfrom = [0, 0, -5]\n\n[[lights]]\nat = [1, 2, 3]\n").unwrap();
+        let keys = [(1.0, toml::Value::Float(0.0)), (3.0, toml::Value::Float(1.0))];
+        Curve::new("camera.aperture", &keys, Interpolation::Linear).unwrap().apply(&mut doc, 2.0).unwrap();
+        assert_eq!(doc["camera"]["aperture"].as_float(), Some(0.5));
+        Curve::new("lights.0.at.1", &keys, Interpolation::Linear).unwrap().apply(&mut doc, 3.0).unwrap();
+        assert_eq!(doc["lights"][0]["at"][1].as_float(), Some(1.0));
+
+        let missing = Curve::new("lights.1.at", &keys, Interpolation::Linear).unwrap().apply(&mut doc, 1.0);
+        assert_eq!(missing, Err("the scene has nothing at `lights.1.at` to animate".to_string()));
+        assert!(Curve::new("sky.sun", &keys, Interpolation::Linear).unwrap().apply(&mut doc, 1.0).is_err());
+        assert!(Curve::new("camera.from.x", &keys, Interpolation::Linear).unwrap().apply(&mut doc, 1.0).is_err());
+    }
+
+    #[test]
+    fn frame_filenames() {
+        assert_eq!(frame_filename("shot.png", 1), "shot_0001.png");
+        assert_eq!(frame_filename("out/shot.ppm", 12345), "out/shot_12345.ppm");
+        assert_eq!(frame_filename("shot", 7), "shot_0007");
+    }
+}
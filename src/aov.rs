@@ -0,0 +1,133 @@
+use super::camera::Camera;
+use super::canvas::{Canvas, Colour};
+use super::ray;
+use super::render;
+use super::world::World;
+
+// Arbitrary output variables: what the camera sees first through the centre
+// of each pixel, written alongside the image for compositing, denoising or
+// finding out what went wrong. Pixels where the camera hits nothing are black
+// in every output. Only pfm keeps values outside 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aov {
+    // the distance from the camera, the same in each channel
+    Depth,
+    // the shading normal in world space, turned to face the camera
+    Normal,
+    // the surface's colour before any lighting
+    Albedo,
+    // the point hit, in world space
+    Position,
+    // one more than the object's index in the scene, or in the world for
+    // worlds not built from one
+    ObjectId,
+    // 1 and up for the scene's named materials, in order of name, then the
+    // materials written out in objects; 0 for objects without a material
+    MaterialId,
+}
+
+impl Aov {
+    pub fn from_name(name: &str) -> Option<Aov> {
+        match name {
+            "depth" => Some(Aov::Depth),
+            "normal" => Some(Aov::Normal),
+            "albedo" => Some(Aov::Albedo),
+            "position" => Some(Aov::Position),
+            "object-id" => Some(Aov::ObjectId),
+            "material-id" => Some(Aov::MaterialId),
+            _ => None,
+        }
+    }
+}
+
+// the value of each of aovs at (px, py), black where the camera sees nothing
+fn pixel(camera: &Camera, world: &World, aovs: &[Aov], px: f64, py: f64) -> Vec<Colour> {
+    let black = Colour::new(0.0, 0.0, 0.0);
+    let r = match camera.ray_for_pixel(px, py) {
+        Some(r) => r.with_time(camera.shutter_open),
+        None => return vec![black; aovs.len()],
+    };
+    let hit = match ray::get_hit(world.intersect(r)) {
+        Some(hit) => hit,
+        None => return vec![black; aovs.len()],
+    };
+    let point = r.position(hit.t);
+    let grey = |v: f64| Colour::new(v, v, v);
+    aovs.iter().map(|aov| match *aov {
+        Aov::Depth => grey(hit.t),
+        Aov::Normal => {
+            let n = hit.object.normal(point, r.time);
+            let n = if n.dot(&r.dir) > 0.0 { -n } else { n };
+            Colour::new(n.x(), n.y(), n.z())
+        },
+        Aov::Albedo => hit.object.colour_at(point, r.time),
+        Aov::Position => Colour::new(point.x(), point.y(), point.z()),
+        Aov::ObjectId => grey(world.object_id(hit.object) as f64),
+        Aov::MaterialId => grey(hit.object.material().id as f64),
+    }).collect()
+}
+
+// an image for each of aovs, in the same order
+pub fn render(camera: &Camera, world: &World, aovs: &[Aov], threads: usize) -> Vec<Canvas> {
+    let pixels = render::parallel_pixels(camera.hsize, camera.vsize, threads, |x, y| {
+        pixel(camera, world, aovs, x as f64 + 0.5, y as f64 + 0.5)
+    });
+    let mut images: Vec<Canvas> = aovs.iter().map(|_| Canvas::new(camera.hsize, camera.vsize)).collect();
+    for (x, column) in pixels.into_iter().enumerate() {
+        for (y, values) in column.into_iter().enumerate() {
+            for (image, c) in images.iter_mut().zip(values) {
+                image.write(x, y, c);
+            }
+        }
+    }
+    images
+}
+
+#[cfg(test)]
+mod aov_tests {
+    use super::*;
+    use plane::Plane;
+    use scene_object::Material;
+    use sphere::Sphere;
+    use transformation::{Transformation, Vector};
+
+    const ALL: [Aov; 6] = [Aov::Depth, Aov::Normal, Aov::Albedo, Aov::Position, Aov::ObjectId, Aov::MaterialId];
+
+    #[test]
+    fn first_hits() {
+        let mut world = World::new();
+        let mut floor = Plane { material: Material::new(), trans: Transformation::new().translate(0.0, -1.0, 0.0), motion: None };
+        floor.material.id = 3;
+        let mut ball = Sphere::new();
+        ball.material.colour = Colour::new(0.8, 0.2, 0.1);
+        world.objects.push(Box::new(floor));
+        world.objects.push(Box::new(ball));
+        let camera = Camera::new(11, 11, 0.8)
+                         .look_at(Vector::new(0.0, 0.0, -5.0), Vector::new(0.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0));
+        let images = render(&camera, &world, &ALL, 2);
+        let at = |aov: Aov, x: usize, y: usize| images[ALL.iter().position(|&a| a == aov).unwrap()].read(x, y);
+
+        // straight ahead is the front of the sphere
+        assert_eq!(at(Aov::Depth, 5, 5), Colour::new(4.0, 4.0, 4.0));
+        assert_eq!(at(Aov::Normal, 5, 5), Colour::new(0.0, 0.0, -1.0));
+        assert_eq!(at(Aov::Albedo, 5, 5), Colour::new(0.8, 0.2, 0.1));
+        assert_eq!(at(Aov::Position, 5, 5), Colour::new(0.0, 0.0, -1.0));
+        assert_eq!(at(Aov::ObjectId, 5, 5), Colour::new(2.0, 2.0, 2.0));
+        assert_eq!(at(Aov::MaterialId, 5, 5), Colour::new(0.0, 0.0, 0.0));
+
+        // the floor at the bottom, and nothing at the top
+        assert_eq!(at(Aov::ObjectId, 5, 10), Colour::new(1.0, 1.0, 1.0));
+        assert_eq!(at(Aov::MaterialId, 5, 10), Colour::new(3.0, 3.0, 3.0));
+        assert_eq!(at(Aov::Normal, 5, 10), Colour::new(0.0, 1.0, 0.0));
+        assert!((at(Aov::Position, 5, 10).g + 1.0).abs() < 1e-9);
+        for &aov in &ALL {
+            assert_eq!(at(aov, 5, 0), Colour::new(0.0, 0.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Aov::from_name("object-id"), Some(Aov::ObjectId));
+        assert_eq!(Aov::from_name("beauty"), None);
+    }
+}
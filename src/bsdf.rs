@@ -0,0 +1,586 @@
+use std::f64::consts::PI;
+use std::fmt::Debug;
+
+use super::canvas::Colour;
+use super::random::Rng;
+use super::sampling;
+use super::transformation::Vector;
+
+// below this roughness conductors and dielectrics are perfectly smooth
+const SMOOTH: f64 = 1e-3;
+
+// A direction chosen by a bsdf. weight is the bsdf times the cosine of wi to
+// the normal over pdf, which is what the light arriving along wi is scaled by.
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    pub wi: Vector,
+    pub weight: Colour,
+    pub pdf: f64,
+    // chosen from a perfectly smooth lobe, which eval and pdf can't see
+    pub specular: bool,
+}
+
+// How a surface scatters light. wo points back along the ray that found the
+// surface and wi towards where light arrives from, both away from the surface;
+// n faces out of the object, so directions on its other side are inside.
+// colour is the material's colour at the point, which each model is tinted by.
+pub trait Bsdf: Debug + Send + Sync {
+    fn eval(&self, wo: Vector, wi: Vector, n: Vector, colour: Colour) -> Colour;
+    fn sample(&self, wo: Vector, n: Vector, colour: Colour, rng: &mut Rng) -> Option<BsdfSample>;
+    // the probability density of sample choosing wi, ignoring smooth lobes
+    fn pdf(&self, wo: Vector, wi: Vector, n: Vector, colour: Colour) -> f64;
+}
+
+fn black() -> Colour {
+    Colour::new(0.0, 0.0, 0.0)
+}
+
+fn white() -> Colour {
+    Colour::new(1.0, 1.0, 1.0)
+}
+
+fn mix(a: Colour, b: Colour, t: f64) -> Colour {
+    a * (1.0 - t) + b * t
+}
+
+// the normal is z in local coordinates
+struct Frame {
+    t: Vector,
+    b: Vector,
+    n: Vector,
+}
+
+impl Frame {
+    fn new(n: Vector) -> Frame {
+        let (t, b) = n.orthonormal_basis();
+        Frame { t, b, n }
+    }
+
+    fn local(&self, v: Vector) -> Vector {
+        Vector::new(v.dot(&self.t), v.dot(&self.b), v.dot(&self.n))
+    }
+
+    fn world(&self, v: Vector) -> Vector {
+        self.t * v.x() + self.b * v.y() + self.n * v.z()
+    }
+}
+
+// the value over the density of a sampled direction, times its cosine
+fn sampled(wi: Vector, f: Colour, pdf: f64, cos_theta: f64) -> Option<BsdfSample> {
+    if pdf <= 0.0 {
+        return None;
+    }
+    Some(BsdfSample { wi, weight: f * (cos_theta.abs() / pdf), pdf, specular: false })
+}
+
+// roughness is perceptual, GGX's alpha is its square
+fn alpha(roughness: f64) -> f64 {
+    roughness * roughness
+}
+
+// Trowbridge-Reitz distribution of microfacet normals
+fn ggx(h: Vector, alpha: f64) -> f64 {
+    let cos2 = h.z() * h.z();
+    if cos2 <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let d = cos2 * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+// Smith's masking of the microfacets seen from v
+fn smith_g1(v: Vector, alpha: f64) -> f64 {
+    let cos2 = v.z() * v.z();
+    if cos2 <= 0.0 {
+        return 0.0;
+    }
+    let tan2 = (1.0 - cos2) / cos2;
+    2.0 / (1.0 + (1.0 + alpha * alpha * tan2).sqrt())
+}
+
+// a microfacet normal, distributed as ggx(h) * h.z
+fn sample_ggx(alpha: f64, u: f64, v: f64) -> Vector {
+    let tan2 = alpha * alpha * u / (1.0 - u).max(1e-12);
+    let cos = 1.0 / (1.0 + tan2).sqrt();
+    let sin = (1.0 - cos * cos).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    Vector::new(sin * phi.cos(), sin * phi.sin(), cos)
+}
+
+fn schlick(f0: Colour, cos: f64) -> Colour {
+    f0 + (white() - f0) * (1.0 - cos.clamp(0.0, 1.0)).powi(5)
+}
+
+// The fraction of light reflected by a smooth boundary, where cos is to the
+// normal on the incoming side and eta is the index beyond it over the index
+// before it.
+fn fresnel_dielectric(cos: f64, eta: f64) -> f64 {
+    let cos = cos.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos * cos) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos - eta * cos_t) / (cos + eta * cos_t);
+    let rp = (eta * cos - cos_t) / (eta * cos + cos_t);
+    (rs * rs + rp * rp) / 2.0
+}
+
+// wi mirrored about h, in local coordinates
+fn reflect(wo: Vector, h: Vector) -> Vector {
+    -wo.reflect(&h)
+}
+
+// wo bent through a boundary with normal h on its side, or None if it is
+// totally internally reflected
+fn refract(wo: Vector, h: Vector, eta: f64) -> Option<Vector> {
+    let cos = wo.dot(&h);
+    let sin2_t = (1.0 - cos * cos) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-wo * (1.0 / eta) + h * (cos / eta - cos_t))
+}
+
+// GGX reflection without fresnel for local directions above the surface
+fn microfacet(wo: Vector, wi: Vector, alpha: f64) -> f64 {
+    if wo.z() <= 0.0 || wi.z() <= 0.0 {
+        return 0.0;
+    }
+    let h = (wo + wi).normalize();
+    ggx(h, alpha) * smith_g1(wo, alpha) * smith_g1(wi, alpha) / (4.0 * wo.z() * wi.z())
+}
+
+fn microfacet_pdf(wo: Vector, wi: Vector, alpha: f64) -> f64 {
+    if wo.z() <= 0.0 || wi.z() <= 0.0 {
+        return 0.0;
+    }
+    let h = (wo + wi).normalize();
+    ggx(h, alpha) * h.z() / (4.0 * wo.dot(&h).abs())
+}
+
+// Both directions turned to the outside when wo is inside, for models that
+// scatter the same way from either side. None when they are on opposite sides.
+fn same_side(frame: &Frame, wo: Vector, wi: Vector) -> Option<(Vector, Vector)> {
+    let (lo, li) = (frame.local(wo), frame.local(wi));
+    if lo.z() * li.z() <= 0.0 {
+        None
+    } else if lo.z() < 0.0 {
+        Some((-lo, -li))
+    } else {
+        Some((lo, li))
+    }
+}
+
+// a local frame whose normal is turned towards wo, and wo in it
+fn outside(n: Vector, wo: Vector) -> (Frame, Vector) {
+    let n = if wo.dot(&n) < 0.0 { -n } else { n };
+    let frame = Frame::new(n);
+    let lo = frame.local(wo);
+    (frame, lo)
+}
+
+// ideal diffuse reflection
+#[derive(Debug, Clone, Copy)]
+pub struct Lambert;
+
+impl Bsdf for Lambert {
+    fn eval(&self, wo: Vector, wi: Vector, n: Vector, colour: Colour) -> Colour {
+        match same_side(&Frame::new(n), wo, wi) {
+            Some(_) => colour * (1.0 / PI),
+            None => black(),
+        }
+    }
+
+    fn sample(&self, wo: Vector, n: Vector, colour: Colour, rng: &mut Rng) -> Option<BsdfSample> {
+        let n = if wo.dot(&n) < 0.0 { -n } else { n };
+        let wi = sampling::cosine_hemisphere(n, rng.next_f64(), rng.next_f64());
+        let pdf = wi.dot(&n) / PI;
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { wi, weight: colour, pdf, specular: false })
+    }
+
+    fn pdf(&self, wo: Vector, wi: Vector, n: Vector, _colour: Colour) -> f64 {
+        match same_side(&Frame::new(n), wo, wi) {
+            Some((_, li)) => li.z() / PI,
+            None => 0.0,
+        }
+    }
+}
+
+// Metal: GGX microfacets with a schlick fresnel term that reflects f0 head on,
+// or the surface colour when f0 is None.
+#[derive(Debug, Clone, Copy)]
+pub struct Conductor {
+    pub roughness: f64,
+    pub f0: Option<Colour>,
+}
+
+impl Conductor {
+    fn f0(&self, colour: Colour) -> Colour {
+        self.f0.unwrap_or(colour)
+    }
+}
+
+impl Bsdf for Conductor {
+    fn eval(&self, wo: Vector, wi: Vector, n: Vector, colour: Colour) -> Colour {
+        if self.roughness < SMOOTH {
+            return black();
+        }
+        match same_side(&Frame::new(n), wo, wi) {
+            Some((lo, li)) => {
+                let h = (lo + li).normalize();
+                schlick(self.f0(colour), lo.dot(&h)) * microfacet(lo, li, alpha(self.roughness))
+            },
+            None => black(),
+        }
+    }
+
+    fn sample(&self, wo: Vector, n: Vector, colour: Colour, rng: &mut Rng) -> Option<BsdfSample> {
+        let (frame, lo) = outside(n, wo);
+        if self.roughness < SMOOTH {
+            let wi = frame.world(Vector::new(-lo.x(), -lo.y(), lo.z()));
+            return Some(BsdfSample { wi, weight: schlick(self.f0(colour), lo.z()), pdf: 1.0, specular: true });
+        }
+        let h = sample_ggx(alpha(self.roughness), rng.next_f64(), rng.next_f64());
+        let li = reflect(lo, h);
+        if li.z() <= 0.0 {
+            return None;
+        }
+        let wi = frame.world(li);
+        sampled(wi, self.eval(wo, wi, n, colour), self.pdf(wo, wi, n, colour), li.z())
+    }
+
+    fn pdf(&self, wo: Vector, wi: Vector, n: Vector, _colour: Colour) -> f64 {
+        if self.roughness < SMOOTH {
+            return 0.0;
+        }
+        match same_side(&Frame::new(n), wo, wi) {
+            Some((lo, li)) => microfacet_pdf(lo, li, alpha(self.roughness)),
+            None => 0.0,
+        }
+    }
+}
+
+// Glass and water: light is reflected or transmitted in proportion to the
+// exact fresnel equations, through GGX microfacets when rough. Transmitted
+// light is tinted by the surface colour.
+#[derive(Debug, Clone, Copy)]
+pub struct Dielectric {
+    pub ior: f64,
+    pub roughness: f64,
+}
+
+impl Dielectric {
+    // the index beyond the boundary over the index before it, seen from lo
+    fn eta(&self, lo: Vector) -> f64 {
+        if lo.z() > 0.0 { self.ior } else { 1.0 / self.ior }
+    }
+
+    // the microfacet normal taking lo to li, on lo's side, and whether li is
+    // reflected; None if no microfacet could
+    fn half_vector(&self, lo: Vector, li: Vector) -> Option<(Vector, bool)> {
+        let reflected = lo.z() * li.z() > 0.0;
+        let h = if reflected { lo + li } else { lo + li * self.eta(lo) };
+        if h.norm() < 1e-12 {
+            return None;
+        }
+        let mut h = h.normalize();
+        if h.z() * lo.z() < 0.0 {
+            h = -h;
+        }
+        if lo.dot(&h) <= 0.0 || (!reflected && li.dot(&h) >= 0.0) {
+            return None;
+        }
+        Some((h, reflected))
+    }
+}
+
+impl Bsdf for Dielectric {
+    fn eval(&self, wo: Vector, wi: Vector, n: Vector, colour: Colour) -> Colour {
+        if self.roughness < SMOOTH {
+            return black();
+        }
+        let frame = Frame::new(n);
+        let (lo, li) = (frame.local(wo), frame.local(wi));
+        let (h, reflected) = match self.half_vector(lo, li) {
+            Some(h) => h,
+            None => return black(),
+        };
+        let a = alpha(self.roughness);
+        let eta = self.eta(lo);
+        let f = fresnel_dielectric(lo.dot(&h), eta);
+        let dg = ggx(h, a) * smith_g1(lo, a) * smith_g1(li, a);
+        if reflected {
+            white() * (f * dg / (4.0 * lo.z().abs() * li.z().abs()))
+        } else {
+            // radiance is compressed by eta squared going into the denser
+            // side, which cancels the eta squared of the change of variables
+            let denom = lo.dot(&h) + eta * li.dot(&h);
+            colour * ((1.0 - f) * dg * (li.dot(&h) * lo.dot(&h)).abs() / (lo.z() * li.z() * denom * denom).abs())
+        }
+    }
+
+    fn sample(&self, wo: Vector, n: Vector, colour: Colour, rng: &mut Rng) -> Option<BsdfSample> {
+        let frame = Frame::new(n);
+        let lo = frame.local(wo);
+        let eta = self.eta(lo);
+        if self.roughness < SMOOTH {
+            let side = Vector::new(0.0, 0.0, lo.z().signum());
+            let f = fresnel_dielectric(lo.z().abs(), eta);
+            if rng.next_f64() < f {
+                let wi = frame.world(Vector::new(-lo.x(), -lo.y(), lo.z()));
+                return Some(BsdfSample { wi, weight: white(), pdf: f, specular: true });
+            }
+            let li = refract(lo, side, eta)?;
+            let weight = colour * (1.0 / (eta * eta));
+            return Some(BsdfSample { wi: frame.world(li), weight, pdf: 1.0 - f, specular: true });
+        }
+
+        let mut h = sample_ggx(alpha(self.roughness), rng.next_f64(), rng.next_f64());
+        if lo.z() < 0.0 {
+            h = -h;
+        }
+        if lo.dot(&h) <= 0.0 {
+            return None;
+        }
+        let (li, reflected) = if rng.next_f64() < fresnel_dielectric(lo.dot(&h), eta) {
+            (reflect(lo, h), true)
+        } else {
+            (refract(lo, h, eta)?, false)
+        };
+        // a microfacet can send light to the wrong side of the surface itself
+        if (lo.z() * li.z() > 0.0) != reflected {
+            return None;
+        }
+        let wi = frame.world(li);
+        sampled(wi, self.eval(wo, wi, n, colour), self.pdf(wo, wi, n, colour), li.z())
+    }
+
+    fn pdf(&self, wo: Vector, wi: Vector, n: Vector, _colour: Colour) -> f64 {
+        if self.roughness < SMOOTH {
+            return 0.0;
+        }
+        let frame = Frame::new(n);
+        let (lo, li) = (frame.local(wo), frame.local(wi));
+        let (h, reflected) = match self.half_vector(lo, li) {
+            Some(h) => h,
+            None => return 0.0,
+        };
+        let eta = self.eta(lo);
+        let f = fresnel_dielectric(lo.dot(&h), eta);
+        let pdf_h = ggx(h, alpha(self.roughness)) * h.z().abs();
+        if reflected {
+            f * pdf_h / (4.0 * lo.dot(&h))
+        } else {
+            let denom = lo.dot(&h) + eta * li.dot(&h);
+            (1.0 - f) * pdf_h * eta * eta * li.dot(&h).abs() / (denom * denom)
+        }
+    }
+}
+
+// A layered material: a GGX specular coat over a diffuse base. Metals have no
+// base and colour their reflections; the coat of everything else reflects
+// 8% of specular head on, so the default of 0.5 is 4%, like most plastics.
+#[derive(Debug, Clone, Copy)]
+pub struct Principled {
+    pub metallic: f64,
+    pub roughness: f64,
+    pub specular: f64,
+}
+
+impl Principled {
+    fn f0(&self, colour: Colour) -> Colour {
+        mix(white() * (0.08 * self.specular), colour, self.metallic)
+    }
+
+    fn alpha(&self) -> f64 {
+        // a perfectly smooth coat would only ever be seen in reflections
+        alpha(self.roughness.max(0.02))
+    }
+
+    // how often the coat is sampled rather than the base
+    fn coat_probability(&self, lo: Vector, colour: Colour) -> f64 {
+        let coat = schlick(self.f0(colour), lo.z()).luminance();
+        let base = (1.0 - self.metallic) * (1.0 - coat) * colour.luminance();
+        if coat + base <= 0.0 { 1.0 } else { (coat / (coat + base)).clamp(0.1, 1.0) }
+    }
+}
+
+impl Bsdf for Principled {
+    fn eval(&self, wo: Vector, wi: Vector, n: Vector, colour: Colour) -> Colour {
+        let (lo, li) = match same_side(&Frame::new(n), wo, wi) {
+            Some(l) => l,
+            None => return black(),
+        };
+        let h = (lo + li).normalize();
+        let f0 = self.f0(colour);
+        let coat = schlick(f0, lo.dot(&h)) * microfacet(lo, li, self.alpha());
+        // light that gets through the coat reaches the base
+        let base = (white() - schlick(f0, lo.z())) * colour * ((1.0 - self.metallic) / PI);
+        coat + base
+    }
+
+    fn sample(&self, wo: Vector, n: Vector, colour: Colour, rng: &mut Rng) -> Option<BsdfSample> {
+        let (frame, lo) = outside(n, wo);
+        let li = if rng.next_f64() < self.coat_probability(lo, colour) {
+            reflect(lo, sample_ggx(self.alpha(), rng.next_f64(), rng.next_f64()))
+        } else {
+            frame.local(sampling::cosine_hemisphere(frame.n, rng.next_f64(), rng.next_f64()))
+        };
+        if li.z() <= 0.0 {
+            return None;
+        }
+        let wi = frame.world(li);
+        sampled(wi, self.eval(wo, wi, n, colour), self.pdf(wo, wi, n, colour), li.z())
+    }
+
+    fn pdf(&self, wo: Vector, wi: Vector, n: Vector, colour: Colour) -> f64 {
+        match same_side(&Frame::new(n), wo, wi) {
+            Some((lo, li)) => {
+                let p = self.coat_probability(lo, colour);
+                p * microfacet_pdf(lo, li, self.alpha()) + (1.0 - p) * li.z() / PI
+            },
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod bsdf_tests {
+    use super::*;
+
+    fn n() -> Vector {
+        Vector::new(0.0, 1.0, 0.0)
+    }
+
+    // a direction at angle theta from the normal in the xy plane
+    fn at(theta: f64) -> Vector {
+        Vector::new(theta.sin(), theta.cos(), 0.0)
+    }
+
+    fn grey() -> Colour {
+        Colour::new(0.5, 0.5, 0.5)
+    }
+
+    // the integral of pdf over the whole sphere, on a fine grid of directions
+    fn total_pdf(bsdf: &dyn Bsdf, wo: Vector) -> f64 {
+        let (rows, columns) = (400, 100);
+        let mut total = 0.0;
+        for i in 0..rows {
+            let theta = (i as f64 + 0.5) / rows as f64 * PI;
+            for j in 0..columns {
+                let phi = (j as f64 + 0.5) / columns as f64 * 2.0 * PI;
+                let wi = Vector::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                total += bsdf.pdf(wo, wi, n(), grey()) * theta.sin();
+            }
+        }
+        total * (PI / rows as f64) * (2.0 * PI / columns as f64)
+    }
+
+    // how often sample finds a direction at all
+    fn success_rate(bsdf: &dyn Bsdf, wo: Vector) -> f64 {
+        let mut rng = Rng::new(5);
+        (0..20000).filter(|_| bsdf.sample(wo, n(), grey(), &mut rng).is_some()).count() as f64 / 20000.0
+    }
+
+    #[test]
+    fn lambert() {
+        let (wo, wi) = (at(0.3), at(-1.0));
+        assert_eq!(Lambert.eval(wo, wi, n(), grey()), grey() * (1.0 / PI));
+        // from either side, but not through the surface
+        assert_eq!(Lambert.eval(-wo, -wi, n(), grey()), grey() * (1.0 / PI));
+        assert_eq!(Lambert.eval(wo, -wi, n(), grey()), black());
+        let s = Lambert.sample(-wo, n(), grey(), &mut Rng::new(1)).unwrap();
+        assert!(s.wi.dot(&n()) < 0.0 && s.weight == grey());
+    }
+
+    #[test]
+    fn samples_agree_with_eval_and_pdf() {
+        let models: Vec<Box<dyn Bsdf>> = vec!(
+            Box::new(Conductor { roughness: 0.4, f0: None }),
+            Box::new(Dielectric { ior: 1.5, roughness: 0.4 }),
+            Box::new(Principled { metallic: 0.3, roughness: 0.5, specular: 0.5 }),
+        );
+        let mut rng = Rng::new(2);
+        for bsdf in &models {
+            for &wo in &[at(0.4), at(-2.5)] {
+                for _ in 0..100 {
+                    let s = match bsdf.sample(wo, n(), grey(), &mut rng) {
+                        Some(s) => s,
+                        None => continue,
+                    };
+                    let pdf = bsdf.pdf(wo, s.wi, n(), grey());
+                    assert!((s.pdf - pdf).abs() <= 1e-9 * pdf, "{:?}", bsdf);
+                    let expected = bsdf.eval(wo, s.wi, n(), grey()) * (s.wi.dot(&n()).abs() / pdf);
+                    assert!((s.weight.r - expected.r).abs() < 1e-9, "{:?}", bsdf);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pdfs_match_sampling() {
+        // the pdf accounts for every direction sample can choose, which for
+        // glass seen from inside is mostly reflections
+        let models: Vec<Box<dyn Bsdf>> = vec!(
+            Box::new(Lambert),
+            Box::new(Conductor { roughness: 0.5, f0: None }),
+            Box::new(Dielectric { ior: 1.5, roughness: 0.5 }),
+            Box::new(Principled { metallic: 0.0, roughness: 0.6, specular: 0.5 }),
+        );
+        for bsdf in &models {
+            for &wo in &[at(0.5), at(2.0), at(2.8)] {
+                let (total, expected) = (total_pdf(&**bsdf, wo), success_rate(&**bsdf, wo));
+                assert!((total - expected).abs() < 0.02, "{:?} {} {}", bsdf, total, expected);
+            }
+        }
+        assert!((total_pdf(&Lambert, at(0.3)) - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn conductors() {
+        let rough = Conductor { roughness: 0.3, f0: Some(Colour::new(0.9, 0.6, 0.3)) };
+        let (wo, wi) = (at(0.2), at(-0.7));
+        // reciprocal, and brightest in the mirror direction
+        assert!((rough.eval(wo, wi, n(), grey()).r - rough.eval(wi, wo, n(), grey()).r).abs() < 1e-12);
+        assert!(rough.eval(wo, at(-0.2), n(), grey()).r > rough.eval(wo, wi, n(), grey()).r);
+
+        let smooth = Conductor { roughness: 0.0, f0: None };
+        let s = smooth.sample(at(0.3), n(), grey(), &mut Rng::new(0)).unwrap();
+        assert!(s.specular && (s.wi - at(-0.3)).norm() < 1e-12);
+        assert!((s.weight.r - schlick(grey(), 0.3_f64.cos()).r).abs() < 1e-12);
+        assert_eq!(smooth.eval(at(0.3), at(-0.3), n(), grey()), black());
+    }
+
+    #[test]
+    fn smooth_dielectrics() {
+        let glass = Dielectric { ior: 1.5, roughness: 0.0 };
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-12);
+        // past the critical angle all light is reflected
+        assert_eq!(fresnel_dielectric(1.0_f64.cos(), 1.0 / 1.5), 1.0);
+
+        let mut rng = Rng::new(4);
+        let wo = at(PI / 4.0);
+        let (mut reflected, mut refracted) = (0, 0);
+        for _ in 0..1000 {
+            let s = glass.sample(wo, n(), grey(), &mut rng).unwrap();
+            assert!(s.specular);
+            if s.wi.y() > 0.0 {
+                reflected += 1;
+                assert_eq!(s.weight, white());
+            } else {
+                refracted += 1;
+                // snell's law, and tinted and compressed into the denser glass
+                assert!((s.wi.x() + (PI / 4.0).sin() / 1.5).abs() < 1e-12);
+                assert_eq!(s.weight, grey() * (1.0 / 2.25));
+            }
+        }
+        let f = fresnel_dielectric((PI / 4.0).cos(), 1.5);
+        assert!((reflected as f64 / 1000.0 - f).abs() < 0.02 && refracted > 900);
+    }
+}
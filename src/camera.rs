@@ -0,0 +1,484 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+use super::random::Rng;
+use super::ray::Ray;
+use super::transformation::Vector;
+
+// The shape of the opening rays pass through, which out of focus highlights
+// take on: round, or a polygon made by the aperture's blades.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aperture {
+    Disc,
+    // rotation turns the polygon about the view direction, in radians
+    Blades { count: usize, rotation: f64 },
+}
+
+impl Aperture {
+    // a point spread evenly over the opening, within radius 1 of its centre,
+    // picked with u and v in [0, 1)
+    pub fn sample(&self, u: f64, v: f64) -> (f64, f64) {
+        match *self {
+            Aperture::Disc => {
+                let (r, theta) = (u.sqrt(), 2.0 * PI * v);
+                (r * theta.cos(), r * theta.sin())
+            },
+            Aperture::Blades { count, rotation } => {
+                // one of the triangles fanning out from the centre, then a
+                // point in it
+                let n = count as f64;
+                let i = (v * n).floor().min(n - 1.0);
+                let t = v * n - i;
+                let corner = |k: f64| {
+                    let angle = rotation + 2.0 * PI * k / n;
+                    (angle.cos(), angle.sin())
+                };
+                let (a, b) = (corner(i), corner(i + 1.0));
+                let s = u.sqrt();
+                (s * ((1.0 - t) * a.0 + t * b.0), s * ((1.0 - t) * a.1 + t * b.1))
+            },
+        }
+    }
+}
+
+// How a camera turns points on its image into rays. Rays are in the camera's
+// own space, where it sits at the origin looking along z, with x to the
+// right of the image and y up it.
+pub trait Projection: fmt::Debug + Sync + Send {
+    // The origin and direction of the ray through (x, y) on the image, each
+    // running from -1 to 1, left to right and bottom to top, where aspect is
+    // the image's width over its height. None where the image shows nothing.
+    fn ray(&self, x: f64, y: f64, aspect: f64) -> Option<(Vector, Vector)>;
+}
+
+// half the width and height of the image, scaled so its longer side is 1
+fn half_extent(aspect: f64) -> (f64, f64) {
+    if aspect >= 1.0 { (1.0, 1.0 / aspect) } else { (aspect, 1.0) }
+}
+
+// a pinhole, field_of_view radians across the image's longer side, which
+// must be less than pi
+#[derive(Debug, Clone, Copy)]
+pub struct Perspective {
+    pub field_of_view: f64,
+}
+
+impl Projection for Perspective {
+    fn ray(&self, x: f64, y: f64, aspect: f64) -> Option<(Vector, Vector)> {
+        if self.field_of_view >= PI {
+            return None;
+        }
+        let half_view = (self.field_of_view / 2.0).tan();
+        let (w, h) = half_extent(aspect);
+        Some((Vector::new(0.0, 0.0, 0.0), Vector::new(x * w * half_view, y * h * half_view, 1.0)))
+    }
+}
+
+// parallel rays, size across the image's longer side, for views without
+// perspective such as technical drawings
+#[derive(Debug, Clone, Copy)]
+pub struct Orthographic {
+    pub size: f64,
+}
+
+impl Projection for Orthographic {
+    fn ray(&self, x: f64, y: f64, aspect: f64) -> Option<(Vector, Vector)> {
+        let (w, h) = half_extent(aspect);
+        Some((Vector::new(x * w * self.size / 2.0, y * h * self.size / 2.0, 0.0), Vector::new(0.0, 0.0, 1.0)))
+    }
+}
+
+// An equidistant fisheye lens, whose angle from the view direction grows
+// evenly out to field_of_view / 2 at the edge of a circle filling the image's
+// shorter side. Outside the circle is black.
+#[derive(Debug, Clone, Copy)]
+pub struct Fisheye {
+    pub field_of_view: f64,
+}
+
+impl Projection for Fisheye {
+    fn ray(&self, x: f64, y: f64, aspect: f64) -> Option<(Vector, Vector)> {
+        let (w, h) = half_extent(aspect);
+        let (fx, fy) = (x * w / w.min(h), y * h / w.min(h));
+        let r = (fx * fx + fy * fy).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+        let theta = r * self.field_of_view / 2.0;
+        let (cx, cy) = if r > 0.0 { (fx / r, fy / r) } else { (0.0, 0.0) };
+        Some((Vector::new(0.0, 0.0, 0.0), Vector::new(theta.sin() * cx, theta.sin() * cy, theta.cos())))
+    }
+}
+
+// Everything around the camera, turning all the way round across the image
+// and from straight down to straight up it, best at an aspect of 2.
+#[derive(Debug, Clone, Copy)]
+pub struct Equirectangular;
+
+impl Projection for Equirectangular {
+    fn ray(&self, x: f64, y: f64, _aspect: f64) -> Option<(Vector, Vector)> {
+        let (longitude, latitude) = (x * PI, y * PI / 2.0);
+        let d = Vector::new(latitude.cos() * longitude.sin(), latitude.sin(), latitude.cos() * longitude.cos());
+        Some((Vector::new(0.0, 0.0, 0.0), d))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoLayout {
+    // the left eye's view on the left of the image
+    SideBySide,
+    // the left eye's view on the top
+    TopBottom,
+}
+
+// Both eyes' views in one image, each through eye. The eyes are separation
+// apart, side by side at right angles to each ray, so that panoramas keep
+// their depth all the way round.
+#[derive(Debug, Clone)]
+pub struct Stereo {
+    pub eye: Arc<dyn Projection>,
+    pub separation: f64,
+    pub layout: StereoLayout,
+}
+
+impl Projection for Stereo {
+    fn ray(&self, x: f64, y: f64, aspect: f64) -> Option<(Vector, Vector)> {
+        let (left, x, y, aspect) = match self.layout {
+            StereoLayout::SideBySide if x < 0.0 => (true, 2.0 * x + 1.0, y, aspect / 2.0),
+            StereoLayout::SideBySide => (false, 2.0 * x - 1.0, y, aspect / 2.0),
+            StereoLayout::TopBottom if y >= 0.0 => (true, x, 2.0 * y - 1.0, aspect * 2.0),
+            StereoLayout::TopBottom => (false, x, 2.0 * y + 1.0, aspect * 2.0),
+        };
+        let (origin, dir) = self.eye.ray(x, y, aspect)?;
+        let side = Vector::new(dir.z(), 0.0, -dir.x());
+        if side.norm() < 1e-12 {
+            return Some((origin, dir));
+        }
+        let offset = side.normalize() * (self.separation / 2.0);
+        Some((if left { origin - offset } else { origin + offset }, dir))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub hsize: usize,
+    pub vsize: usize,
+    pub projection: Arc<dyn Projection>,
+    pub from: Vector,
+    // the radius of the lens, 0 for a pinhole that keeps everything in focus
+    pub aperture_radius: f64,
+    pub aperture: Aperture,
+    // how far in front of the camera things are sharp
+    pub focal_distance: f64,
+    // rays set off at times spread evenly between these, so things that move
+    // while the shutter is open are blurred along their path
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+    // for cropped cameras, which render part of a larger image: the size of
+    // the whole image, and where in it this part's top left pixel is
+    frame: (usize, usize),
+    offset: (usize, usize),
+    forward: Vector,
+    left: Vector,
+    up: Vector,
+}
+
+impl Camera {
+    // a perspective camera, field_of_view radians across the longer side
+    pub fn new(hsize: usize, vsize: usize, field_of_view: f64) -> Camera {
+        Camera {
+            hsize,
+            vsize,
+            projection: Arc::new(Perspective { field_of_view }),
+            from: Vector::new(0.0, 0.0, 0.0),
+            aperture_radius: 0.0,
+            aperture: Aperture::Disc,
+            focal_distance: 1.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            frame: (hsize, vsize),
+            offset: (0, 0),
+            forward: Vector::new(0.0, 0.0, -1.0),
+            left: Vector::new(1.0, 0.0, 0.0),
+            up: Vector::new(0.0, 1.0, 0.0),
+        }
+    }
+
+    pub fn look_at(&self, from: Vector, to: Vector, up: Vector) -> Camera {
+        let forward = (to - from).normalize();
+        let left = forward.cross(&up.normalize());
+        Camera {
+            from,
+            forward,
+            left,
+            up: left.cross(&forward),
+            ..self.clone()
+        }
+    }
+
+    // sets the size of the whole image, undoing any crop
+    pub fn resize(&mut self, hsize: usize, vsize: usize) {
+        self.hsize = hsize;
+        self.vsize = vsize;
+        self.frame = (hsize, vsize);
+        self.offset = (0, 0);
+    }
+
+    // the camera for the pixels from (x0, y0) up to but not including
+    // (x1, y1), which should be inside the image, seeing just what they see in
+    // the whole image
+    pub fn crop(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> Camera {
+        Camera {
+            hsize: x1 - x0,
+            vsize: y1 - y0,
+            offset: (self.offset.0 + x0, self.offset.1 + y0),
+            ..self.clone()
+        }
+    }
+
+    // where pixel (0, 0) is in the whole image
+    pub fn offset(&self) -> (usize, usize) {
+        self.offset
+    }
+
+    // camera space to world space
+    fn world(&self, v: Vector) -> Vector {
+        self.forward * v.z() - self.left * v.x() + self.up * v.y()
+    }
+
+    // the projection's ray through a pixel, in camera space
+    fn local_ray(&self, px: f64, py: f64) -> Option<(Vector, Vector)> {
+        let (w, h) = (self.frame.0 as f64, self.frame.1 as f64);
+        let x = 2.0 * (px + self.offset.0 as f64) / w - 1.0;
+        let y = 1.0 - 2.0 * (py + self.offset.1 as f64) / h;
+        self.projection.ray(x, y, w / h)
+    }
+
+    // px and py are continuous pixel coordinates, the centre of pixel (0, 0) is
+    // (0.5, 0.5), counted from the top left of the crop when there is one
+    pub fn ray_for_pixel(&self, px: f64, py: f64) -> Option<Ray> {
+        let (origin, dir) = self.local_ray(px, py)?;
+        Some(Ray::new(self.from + self.world(origin), self.world(dir).normalize()))
+    }
+
+    // A ray through the pixel from the point on the lens u and v pick, bent to
+    // cross the ray through the lens's centre at the focal distance. Only
+    // rays heading forwards pass through the lens.
+    pub fn ray_through_lens(&self, px: f64, py: f64, u: f64, v: f64) -> Option<Ray> {
+        let (origin, dir) = self.local_ray(px, py)?;
+        if dir.z() <= 1e-9 {
+            return self.ray_for_pixel(px, py);
+        }
+        let focus = origin + dir * (self.focal_distance / dir.z());
+        let (x, y) = self.aperture.sample(u, v);
+        let lens = origin + Vector::new(-x, y, 0.0) * self.aperture_radius;
+        Some(Ray::new(self.from + self.world(lens), self.world(focus - lens).normalize()))
+    }
+
+    // a ray through the pixel, from somewhere on the lens picked with rng if
+    // the camera has one, at a time picked with rng if the shutter is open
+    // for any time at all; None where the image shows nothing
+    pub fn ray(&self, px: f64, py: f64, rng: &mut Rng) -> Option<Ray> {
+        let r = if self.aperture_radius <= 0.0 {
+            self.ray_for_pixel(px, py)
+        } else {
+            let (u, v) = (rng.next_f64(), rng.next_f64());
+            self.ray_through_lens(px, py, u, v)
+        }?;
+        if self.shutter_close <= self.shutter_open {
+            return Some(r.with_time(self.shutter_open));
+        }
+        Some(r.with_time(self.shutter_open + rng.next_f64() * (self.shutter_close - self.shutter_open)))
+    }
+}
+
+#[cfg(test)]
+mod camera_tests {
+    use super::*;
+    use approx::abs_diff_eq;
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    #[test]
+    fn pixel_size() {
+        // neighbouring pixels are 0.01 apart one unit in front of the camera
+        for &(w, h) in &[(200, 125), (125, 200)] {
+            let c = Camera::new(w, h, FRAC_PI_2);
+            let at = |px: f64| {
+                let d = c.ray_for_pixel(px, 10.5).unwrap().dir;
+                d * (-1.0 / d.z())
+            };
+            assert!(((at(10.5) - at(11.5)).norm() - 0.01).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn rays() {
+        let c = Camera::new(201, 101, FRAC_PI_2);
+        let r = c.ray_for_pixel(100.5, 50.5).unwrap();
+        assert!(abs_diff_eq!(r.origin, Vector::new(0.0, 0.0, 0.0)));
+        assert!(abs_diff_eq!(r.dir, Vector::new(0.0, 0.0, -1.0), epsilon = 1e-10));
+
+        let r = c.ray_for_pixel(0.5, 0.5).unwrap();
+        assert!(abs_diff_eq!(r.dir, Vector::new(0.66519, 0.33259, -0.66851), epsilon = 1e-5));
+
+        // a pinhole can't see half of everything or more
+        assert!(Camera::new(201, 101, PI).ray_for_pixel(100.5, 50.5).is_none());
+    }
+
+    #[test]
+    fn look_at() {
+        let h = 2.0_f64.sqrt() / 2.0;
+        let c = Camera::new(201, 101, FRAC_PI_2)
+                    .look_at(Vector::new(0.0, 2.0, -5.0), Vector::new(0.0, 2.0, 0.0), Vector::new(0.0, 1.0, 0.0));
+        let r = c.ray_for_pixel(100.5, 50.5).unwrap();
+        assert!(abs_diff_eq!(r.origin, Vector::new(0.0, 2.0, -5.0)));
+        assert!(abs_diff_eq!(r.dir, Vector::new(0.0, 0.0, 1.0), epsilon = 1e-10));
+
+        let c = Camera::new(201, 101, FRAC_PI_2)
+                    .look_at(Vector::new(0.0, 0.0, 0.0), Vector::new(h, 0.0, -h), Vector::new(0.0, 1.0, 0.0));
+        let r = c.ray_for_pixel(100.5, 50.5).unwrap();
+        assert!(abs_diff_eq!(r.dir, Vector::new(h, 0.0, -h), epsilon = 1e-10));
+    }
+
+    #[test]
+    fn apertures() {
+        let mut rng = Rng::new(0);
+        for _ in 0..100 {
+            let (x, y) = Aperture::Disc.sample(rng.next_f64(), rng.next_f64());
+            assert!(x * x + y * y <= 1.0);
+            // a square turned to have its corners on the axes
+            let (x, y) = Aperture::Blades { count: 4, rotation: 0.0 }.sample(rng.next_f64(), rng.next_f64());
+            assert!(x.abs() + y.abs() <= 1.0 + 1e-12);
+        }
+        let hexagon = Aperture::Blades { count: 6, rotation: 0.5 };
+        assert_eq!(hexagon.sample(0.0, 0.3), (0.0, 0.0));
+        let (x, y) = hexagon.sample(1.0, 0.0);
+        assert!((x - 0.5_f64.cos()).abs() < 1e-12 && (y - 0.5_f64.sin()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn thin_lens() {
+        let mut c = Camera::new(201, 101, FRAC_PI_2)
+                        .look_at(Vector::new(0.0, 0.0, -5.0), Vector::new(0.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0));
+        let mut rng = Rng::new(1);
+        // without an aperture every ray is the pinhole's
+        let (r, pinhole) = (c.ray(30.5, 20.5, &mut rng).unwrap(), c.ray_for_pixel(30.5, 20.5).unwrap());
+        assert_eq!((r.origin, r.dir), (pinhole.origin, pinhole.dir));
+
+        // rays leave from all over the lens, but meet at the focal distance
+        c.aperture_radius = 0.5;
+        c.focal_distance = 4.0;
+        let pinhole = c.ray_for_pixel(30.5, 20.5).unwrap();
+        let focus = pinhole.position(4.0 / pinhole.dir.z());
+        for _ in 0..20 {
+            let r = c.ray(30.5, 20.5, &mut rng).unwrap();
+            assert!((r.origin - c.from).norm() <= 0.5 && r.origin.z() == -5.0);
+            let t = (focus.z() - r.origin.z()) / r.dir.z();
+            assert!(abs_diff_eq!(r.position(t), focus, epsilon = 1e-9));
+        }
+        let r = c.ray_through_lens(30.5, 20.5, 1.0, 0.0).unwrap();
+        assert!(abs_diff_eq!(r.origin, Vector::new(-0.5, 0.0, -5.0), epsilon = 1e-12));
+    }
+
+    #[test]
+    fn shutter() {
+        let mut c = Camera::new(11, 11, FRAC_PI_2);
+        let mut rng = Rng::new(4);
+        assert_eq!(c.ray(5.5, 5.5, &mut rng).unwrap().time, 0.0);
+        // an instant shutter takes every ray at the same time
+        c.shutter_open = 0.25;
+        c.shutter_close = 0.25;
+        assert_eq!(c.ray(5.5, 5.5, &mut rng).unwrap().time, 0.25);
+
+        c.shutter_close = 0.75;
+        let times: Vec<f64> = (0..200).map(|_| c.ray(5.5, 5.5, &mut rng).unwrap().time).collect();
+        assert!(times.iter().all(|&t| (0.25..0.75).contains(&t)));
+        let mean = times.iter().sum::<f64>() / times.len() as f64;
+        assert!((mean - 0.5).abs() < 0.03);
+        assert!(times.iter().any(|&t| t < 0.3) && times.iter().any(|&t| t > 0.7));
+    }
+
+    #[test]
+    fn orthographic() {
+        let mut c = Camera::new(20, 10, FRAC_PI_2);
+        c.projection = Arc::new(Orthographic { size: 4.0 });
+        // every ray heads the same way, from points across a 4 by 2 window
+        let corner = c.ray_for_pixel(0.0, 0.0).unwrap();
+        let centre = c.ray_for_pixel(10.0, 5.0).unwrap();
+        assert!(abs_diff_eq!(corner.dir, centre.dir, epsilon = 1e-12));
+        assert!(abs_diff_eq!(centre.dir, Vector::new(0.0, 0.0, -1.0), epsilon = 1e-12));
+        assert!(abs_diff_eq!(corner.origin, Vector::new(2.0, 1.0, 0.0), epsilon = 1e-12));
+        assert!(abs_diff_eq!(centre.origin, Vector::new(0.0, 0.0, 0.0), epsilon = 1e-12));
+    }
+
+    #[test]
+    fn fisheye() {
+        let mut c = Camera::new(20, 10, FRAC_PI_2);
+        c.projection = Arc::new(Fisheye { field_of_view: PI });
+        assert!(abs_diff_eq!(c.ray_for_pixel(10.0, 5.0).unwrap().dir, Vector::new(0.0, 0.0, -1.0), epsilon = 1e-12));
+        // the top of the circle looks straight up, its corners beyond it see nothing
+        assert!(abs_diff_eq!(c.ray_for_pixel(10.0, 0.0).unwrap().dir, Vector::new(0.0, 1.0, 0.0), epsilon = 1e-12));
+        let r = c.ray_for_pixel(15.0, 5.0).unwrap();
+        assert!(abs_diff_eq!(r.dir, Vector::new(-1.0, 0.0, 0.0), epsilon = 1e-12));
+        assert!(c.ray_for_pixel(0.5, 0.5).is_none());
+        assert!(c.ray_for_pixel(2.0, 5.0).is_none());
+        assert!(c.ray(0.5, 0.5, &mut Rng::new(0)).is_none());
+    }
+
+    #[test]
+    fn equirectangular() {
+        let mut c = Camera::new(40, 20, FRAC_PI_2);
+        c.projection = Arc::new(Equirectangular);
+        assert!(abs_diff_eq!(c.ray_for_pixel(20.0, 10.0).unwrap().dir, Vector::new(0.0, 0.0, -1.0), epsilon = 1e-12));
+        assert!(abs_diff_eq!(c.ray_for_pixel(30.0, 10.0).unwrap().dir, Vector::new(-1.0, 0.0, 0.0), epsilon = 1e-12));
+        assert!(abs_diff_eq!(c.ray_for_pixel(0.0, 10.0).unwrap().dir, Vector::new(0.0, 0.0, 1.0), epsilon = 1e-12));
+        assert!(abs_diff_eq!(c.ray_for_pixel(7.0, 0.0).unwrap().dir, Vector::new(0.0, 1.0, 0.0), epsilon = 1e-12));
+        assert!(abs_diff_eq!(c.ray_for_pixel(33.0, 20.0).unwrap().dir, Vector::new(0.0, -1.0, 0.0), epsilon = 1e-12));
+    }
+
+    #[test]
+    fn stereo() {
+        let eye: Arc<dyn Projection> = Arc::new(Perspective { field_of_view: FRAC_PI_2 });
+        let mut c = Camera::new(40, 10, FRAC_PI_2);
+        c.projection = Arc::new(Stereo { eye: eye.clone(), separation: 0.2, layout: StereoLayout::SideBySide });
+        // each half is a whole view, from eyes either side of the camera
+        let left = c.ray_for_pixel(10.0, 5.0).unwrap();
+        let right = c.ray_for_pixel(30.0, 5.0).unwrap();
+        assert!(abs_diff_eq!(left.dir, Vector::new(0.0, 0.0, -1.0), epsilon = 1e-12));
+        assert!(abs_diff_eq!(right.dir, left.dir, epsilon = 1e-12));
+        assert!(abs_diff_eq!(left.origin, Vector::new(0.1, 0.0, 0.0), epsilon = 1e-12));
+        assert!(abs_diff_eq!(right.origin, Vector::new(-0.1, 0.0, 0.0), epsilon = 1e-12));
+        let single = Camera::new(20, 10, FRAC_PI_2).ray_for_pixel(0.5, 0.5).unwrap();
+        assert!(abs_diff_eq!(c.ray_for_pixel(20.5, 0.5).unwrap().dir, single.dir, epsilon = 1e-12));
+
+        // panoramas keep the eyes at right angles to each ray
+        let mut c = Camera::new(40, 40, FRAC_PI_2);
+        c.projection = Arc::new(Stereo { eye: Arc::new(Equirectangular), separation: 0.2, layout: StereoLayout::TopBottom });
+        let top = c.ray_for_pixel(30.0, 10.0).unwrap();
+        let bottom = c.ray_for_pixel(30.0, 30.0).unwrap();
+        assert!(abs_diff_eq!(top.dir, Vector::new(-1.0, 0.0, 0.0), epsilon = 1e-12));
+        assert!(abs_diff_eq!(top.dir, bottom.dir, epsilon = 1e-12));
+        assert!(abs_diff_eq!(top.origin, Vector::new(0.0, 0.0, -0.1), epsilon = 1e-12));
+        assert!(abs_diff_eq!(bottom.origin, Vector::new(0.0, 0.0, 0.1), epsilon = 1e-12));
+    }
+
+    #[test]
+    fn crops() {
+        let c = Camera::new(40, 20, FRAC_PI_2)
+                    .look_at(Vector::new(0.0, 1.0, -5.0), Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0));
+        let part = c.crop(10, 5, 30, 12);
+        assert_eq!((part.hsize, part.vsize, part.offset()), (20, 7, (10, 5)));
+        let (a, b) = (part.ray_for_pixel(2.5, 3.5).unwrap(), c.ray_for_pixel(12.5, 8.5).unwrap());
+        assert!(abs_diff_eq!(a.origin, b.origin) && abs_diff_eq!(a.dir, b.dir));
+        // crops of crops add up
+        let corner = part.crop(1, 1, 2, 2);
+        assert_eq!((corner.hsize, corner.vsize, corner.offset()), (1, 1, (11, 6)));
+        assert!(abs_diff_eq!(corner.ray_for_pixel(0.5, 0.5).unwrap().dir, c.ray_for_pixel(11.5, 6.5).unwrap().dir));
+
+        let mut resized = part.clone();
+        resized.resize(8, 4);
+        assert_eq!(resized.offset(), (0, 0));
+        assert!(abs_diff_eq!(resized.ray_for_pixel(4.0, 2.0).unwrap().dir, c.ray_for_pixel(20.0, 10.0).unwrap().dir, epsilon = 1e-12));
+    }
+}
@@ -1,8 +1,9 @@
 use std::fs;
 use std::f64;
+use std::io;
 use std::ops::{Add, Sub, Mul};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Colour {
     pub r: f64, 
     pub g: f64, 
@@ -11,13 +12,18 @@ pub struct Colour {
 
 impl Colour {
     pub fn new(r: f64, g: f64, b: f64) -> Colour {
-        Colour {r: r, g: g, b: b}
+        Colour {r, g, b}
+    }
+
+    // perceived brightness, using the Rec. 709 weights
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
     }
 
     pub fn rgb_string(&self) -> String {
-        format!("{} {} {}", (self.r * 255.0) as i32, 
-                            (self.g * 255.0) as i32, 
-                            (self.b * 255.0) as i32)
+        // ppm can't hold values over its maximum, so they're clamped
+        let byte = |v: f64| (v.clamp(0.0, 1.0) * 255.0) as i32;
+        format!("{} {} {}", byte(self.r), byte(self.g), byte(self.b))
     }
 }
 
@@ -102,18 +108,30 @@ pub struct Canvas {
 impl Canvas {
     pub fn new(width: usize, height: usize) -> Canvas {
         Canvas {
-            height: height, 
-            width: width,
+            height,
+            width,
             data: vec![vec![Colour::new(0.0, 0.0, 0.0); height]; width]
         } 
     }
     
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
     pub fn write(&mut self, x: usize, y: usize, c: Colour) {
-        self.data[x][y] = c.clone();
+        self.data[x][y] = c;
+    }
+
+    pub fn read(&self, x: usize, y: usize) -> Colour {
+        self.data[x][y]
     }
 
     pub fn ppm_header(&self) -> String {
-        format!("P3\n{} {}\n256\n", self.width, self.height)
+        format!("P3\n{} {}\n255\n", self.width, self.height)
     }
 
     pub fn ppm_data(&self) -> String {
@@ -121,7 +139,7 @@ impl Canvas {
         for y in 0..self.height {
             for x in 0..self.width {
                 ppm_str.push_str(&self.data[x][y].rgb_string());
-                ppm_str.push_str("\n");
+                ppm_str.push('\n');
             }
         }
         ppm_str
@@ -131,6 +149,53 @@ impl Canvas {
         let data = format!("{}{}", self.ppm_header(), self.ppm_data());
         fs::write(filename, data).expect("Unable to write file");
     }
+
+    pub fn from_ppm(ppm: &str) -> io::Result<Canvas> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let mut tokens = ppm.lines()
+                            .map(|l| l.split('#').next().unwrap_or(""))
+                            .flat_map(|l| l.split_whitespace());
+
+        if tokens.next() != Some("P3") {
+            return Err(invalid("not a plain (P3) ppm"));
+        }
+        let mut number = || -> io::Result<usize> {
+            tokens.next()
+                  .and_then(|t| t.parse().ok())
+                  .ok_or_else(|| invalid("truncated or malformed ppm"))
+        };
+        let width = number()?;
+        let height = number()?;
+        let max = number()?;
+        if max == 0 || max > 65535 {
+            return Err(invalid("maximum value must be from 1 to 65535"));
+        }
+        // each pixel takes at least six characters, "0 0 0 ", so a header
+        // claiming more than that can't be allocated for
+        match width.checked_mul(height) {
+            Some(pixels) if pixels <= ppm.len() / 6 + 1 => {},
+            _ => return Err(invalid("truncated or malformed ppm")),
+        }
+
+        let mut canvas = Canvas::new(width, height);
+        let mut sample = || -> io::Result<f64> {
+            match number()? {
+                v if v > max => Err(invalid("value over the maximum")),
+                v => Ok(v as f64 / max as f64),
+            }
+        };
+        for y in 0..height {
+            for x in 0..width {
+                let (r, g, b) = (sample()?, sample()?, sample()?);
+                canvas.write(x, y, Colour::new(r, g, b));
+            }
+        }
+        Ok(canvas)
+    }
+
+    pub fn load_ppm(filename: &str) -> io::Result<Canvas> {
+        Canvas::from_ppm(&fs::read_to_string(filename)?)
+    }
 }
 
 #[cfg(test)]
@@ -157,8 +222,39 @@ mod canvas_tests {
         let header = canvas.ppm_header();
         let data = canvas.ppm_data();
 
-        assert_eq!(header, "P3\n5 3\n256\n");
+        assert_eq!(header, "P3\n5 3\n255\n");
         assert_eq!(data, ppm_str);
 
     }
+
+    #[test]
+    fn input_ppm() {
+        let mut canvas = Canvas::new(5,3); 
+        canvas.write(2,2, Colour::new(1.0, 0.0, 0.0));
+        canvas.write(4,0, Colour::new(0.0, 1.0, 1.0));
+
+        let ppm = format!("{}{}", canvas.ppm_header(), canvas.ppm_data());
+        let loaded = Canvas::from_ppm(&ppm).unwrap();
+        assert_eq!(loaded.width(), 5);
+        assert_eq!(loaded.height(), 3);
+        assert_eq!(loaded.data, canvas.data);
+
+        let commented = "P3\n# a comment\n1 1\n255\n0 51 255 # trailing\n";
+        assert_eq!(Canvas::from_ppm(commented).unwrap().read(0, 0), Colour::new(0.0, 0.2, 1.0));
+
+        assert!(Canvas::from_ppm("P6\n1 1\n255\n").is_err());
+        assert!(Canvas::from_ppm("P3\n2 2\n255\n0 0 0\n").is_err());
+
+        let deep = "P3\n1 1\n65535\n0 13107 65535\n";
+        assert_eq!(Canvas::from_ppm(deep).unwrap().read(0, 0), Colour::new(0.0, 0.2, 1.0));
+        let shallow = "P3\n1 1\n15\n0 3 15\n";
+        assert_eq!(Canvas::from_ppm(shallow).unwrap().read(0, 0), Colour::new(0.0, 0.2, 1.0));
+        assert!(Canvas::from_ppm("P3\n1 1\n0\n0 0 0\n").is_err());
+        assert!(Canvas::from_ppm("P3\n1 1\n65536\n0 0 0\n").is_err());
+        let declared = "P3\n1 1\n256\n0 64 256\n";
+        assert_eq!(Canvas::from_ppm(declared).unwrap().read(0, 0), Colour::new(0.0, 0.25, 1.0));
+        assert!(Canvas::from_ppm("P3\n1 1\n255\n0 0 256\n").is_err());
+        assert!(Canvas::from_ppm("P3\n100000 100000\n255\n0 0 0\n").is_err());
+        assert!(Canvas::from_ppm("P3\n18446744073709551615 2\n255\n0 0 0\n").is_err());
+    }
 }
@@ -0,0 +1,206 @@
+use super::aov::{self, Aov};
+use super::camera::Camera;
+use super::canvas::{Canvas, Colour};
+use super::render;
+use super::world::World;
+
+// The B3 spline, which spread out over wider and wider steps approximates a
+// large gaussian with only 25 taps a pass.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// what the camera sees first in each pixel, which the noise isn't in
+pub struct Guides {
+    pub albedo: Canvas,
+    pub normal: Canvas,
+    pub depth: Canvas,
+}
+
+impl Guides {
+    pub fn render(camera: &Camera, world: &World, threads: usize) -> Guides {
+        let mut images = aov::render(camera, world, &[Aov::Albedo, Aov::Normal, Aov::Depth], threads).into_iter();
+        Guides { albedo: images.next().unwrap(), normal: images.next().unwrap(), depth: images.next().unwrap() }
+    }
+}
+
+// An edge-avoiding à-trous filter, after Schied et al.'s spatiotemporal
+// variance-guided filtering without the temporal part. Lighting is
+// separated from the albedo so textures stay sharp, then blurred over wider
+// steps each pass, but not across changes in the normal or depth, or in
+// brightness beyond what the noise estimated around each pixel explains.
+#[derive(Debug, Clone, Copy)]
+pub struct Denoiser {
+    pub passes: usize,
+    // how many standard deviations of noise brightness may differ by and still be blurred
+    pub colour_sigma: f64,
+    // how sharply the weight falls away as normals turn apart
+    pub normal_power: f64,
+    // how far, relative to the depth, depths may differ a pixel apart
+    pub depth_sigma: f64,
+}
+
+// one pixel of the image being filtered
+#[derive(Debug, Clone, Copy, Default)]
+struct Sample {
+    colour: Colour,
+    variance: f64,
+}
+
+fn demodulate(c: Colour, albedo: Colour) -> Colour {
+    let channel = |c: f64, a: f64| if a > 1e-3 { c / a } else { c };
+    Colour::new(channel(c.r, albedo.r), channel(c.g, albedo.g), channel(c.b, albedo.b))
+}
+
+fn remodulate(c: Colour, albedo: Colour) -> Colour {
+    let channel = |c: f64, a: f64| if a > 1e-3 { c * a } else { c };
+    Colour::new(channel(c.r, albedo.r), channel(c.g, albedo.g), channel(c.b, albedo.b))
+}
+
+impl Denoiser {
+    pub fn new() -> Denoiser {
+        Denoiser { passes: 5, colour_sigma: 4.0, normal_power: 64.0, depth_sigma: 0.1 }
+    }
+
+    // how much q, distance pixels from p, is like it apart from the noise
+    fn weight(&self, guides: &Guides, p: (usize, usize), q: (usize, usize), distance: f64, samples: (Sample, Sample)) -> f64 {
+        let (np, nq) = (guides.normal.read(p.0, p.1), guides.normal.read(q.0, q.1));
+        let (zp, zq) = (guides.depth.read(p.0, p.1).r, guides.depth.read(q.0, q.1).r);
+        // pixels that see nothing have no normal or depth and only blur
+        // into each other
+        if (zp == 0.0) != (zq == 0.0) {
+            return 0.0;
+        }
+        let normal = (np.r * nq.r + np.g * nq.g + np.b * nq.b).max(0.0).powf(self.normal_power);
+        let normal = if zp == 0.0 { 1.0 } else { normal };
+        let depth = (-(zp - zq).abs() / (self.depth_sigma * zp.max(zq) * distance + 1e-9)).exp();
+        let (sp, sq) = samples;
+        let difference = (sp.colour.luminance() - sq.colour.luminance()).abs();
+        let colour = (-difference / (self.colour_sigma * sp.variance.sqrt() + 1e-9)).exp();
+        normal * depth * colour
+    }
+
+    // a pass of the filter with taps step pixels apart
+    fn pass(&self, samples: &[Vec<Sample>], guides: &Guides, step: usize, threads: usize) -> Vec<Vec<Sample>> {
+        let (w, h) = (samples.len(), samples[0].len());
+        render::parallel_pixels(w, h, threads, |x, y| {
+            let centre = samples[x][y];
+            let mut total = Colour::new(0.0, 0.0, 0.0);
+            let (mut weights, mut variance) = (0.0, 0.0);
+            for (i, kx) in KERNEL.iter().enumerate() {
+                for (j, ky) in KERNEL.iter().enumerate() {
+                    let (dx, dy) = ((i as isize - 2) * step as isize, (j as isize - 2) * step as isize);
+                    let (qx, qy) = (x as isize + dx, y as isize + dy);
+                    if qx < 0 || qy < 0 || qx >= w as isize || qy >= h as isize {
+                        continue;
+                    }
+                    let (qx, qy) = (qx as usize, qy as usize);
+                    let q = samples[qx][qy];
+                    let distance = ((dx * dx + dy * dy) as f64).sqrt();
+                    let wq = if distance == 0.0 {
+                        kx * ky
+                    } else {
+                        kx * ky * self.weight(guides, (x, y), (qx, qy), distance, (centre, q))
+                    };
+                    total = total + q.colour * wq;
+                    weights += wq;
+                    variance += wq * wq * q.variance;
+                }
+            }
+            Sample { colour: total * (1.0 / weights), variance: variance / (weights * weights) }
+        })
+    }
+
+    // the image with its noise smoothed away, where guides were rendered
+    // from the same camera
+    pub fn denoise(&self, image: &Canvas, guides: &Guides, threads: usize) -> Canvas {
+        let (w, h) = (image.width(), image.height());
+        let lighting: Vec<Vec<Colour>> = (0..w).map(|x| {
+            (0..h).map(|y| demodulate(image.read(x, y), guides.albedo.read(x, y))).collect()
+        }).collect();
+
+        // the noise in each pixel is estimated from the spread of brightness
+        // around it
+        let mut samples = render::parallel_pixels(w, h, threads, |x, y| {
+            let (mut sum, mut squares, mut n) = (0.0, 0.0, 0.0);
+            for column in &lighting[x.saturating_sub(1)..(x + 2).min(w)] {
+                for c in &column[y.saturating_sub(1)..(y + 2).min(h)] {
+                    let l = c.luminance();
+                    sum += l;
+                    squares += l * l;
+                    n += 1.0;
+                }
+            }
+            let mean = sum / n;
+            Sample { colour: lighting[x][y], variance: (squares / n - mean * mean).max(0.0) }
+        });
+        for i in 0..self.passes {
+            samples = self.pass(&samples, guides, 1 << i, threads);
+        }
+
+        let mut out = Canvas::new(w, h);
+        for (x, column) in samples.into_iter().enumerate() {
+            for (y, s) in column.into_iter().enumerate() {
+                out.write(x, y, remodulate(s.colour, guides.albedo.read(x, y)));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod denoise_tests {
+    use super::*;
+    use random::Rng;
+
+    // a 32 x 32 image of two walls, a red one facing the camera on the left
+    // and a white one facing right, lit evenly but noisily
+    fn walls(noise: f64) -> (Canvas, Guides, Canvas) {
+        let (mut image, mut clean) = (Canvas::new(32, 32), Canvas::new(32, 32));
+        let mut guides = Guides { albedo: Canvas::new(32, 32), normal: Canvas::new(32, 32), depth: Canvas::new(32, 32) };
+        let mut rng = Rng::new(1);
+        for x in 0..32 {
+            for y in 0..32 {
+                let (albedo, normal, light) = if x < 16 {
+                    (Colour::new(0.8, 0.1, 0.1), Colour::new(0.0, 0.0, -1.0), 0.5)
+                } else {
+                    (Colour::new(0.9, 0.9, 0.9), Colour::new(1.0, 0.0, 0.0), 0.2)
+                };
+                guides.albedo.write(x, y, albedo);
+                guides.normal.write(x, y, normal);
+                guides.depth.write(x, y, Colour::new(5.0, 5.0, 5.0));
+                clean.write(x, y, albedo * light);
+                image.write(x, y, albedo * (light * (1.0 + noise * (2.0 * rng.next_f64() - 1.0))));
+            }
+        }
+        (image, guides, clean)
+    }
+
+    fn error(a: &Canvas, b: &Canvas, columns: std::ops::Range<usize>) -> f64 {
+        let mut total = 0.0;
+        for x in columns {
+            for y in 0..a.height() {
+                let d = a.read(x, y) - b.read(x, y);
+                total += d.r.abs() + d.g.abs() + d.b.abs();
+            }
+        }
+        total
+    }
+
+    #[test]
+    fn smooths_noise_but_not_edges() {
+        let (image, guides, clean) = walls(0.8);
+        let denoised = Denoiser::new().denoise(&image, &guides, 2);
+        assert!(error(&denoised, &clean, 0..32) < error(&image, &clean, 0..32) / 4.0);
+        // the walls don't bleed into each other
+        for y in 0..32 {
+            let (left, right) = (denoised.read(15, y), denoised.read(16, y));
+            assert!(left.r > 2.0 * left.g && (right.r - right.b).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn leaves_clean_images_alone() {
+        let (image, guides, clean) = walls(0.0);
+        let denoised = Denoiser::new().denoise(&image, &guides, 1);
+        assert!(error(&denoised, &clean, 0..32) < 1e-9);
+    }
+}
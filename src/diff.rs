@@ -0,0 +1,129 @@
+use std::f64;
+
+use super::canvas::{Canvas, Colour};
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiffStats {
+    pub max_error: Colour,
+    pub mean_error: Colour,
+    pub psnr: f64,
+    pub over_tolerance: usize,
+    pub pixels: usize,
+}
+
+impl DiffStats {
+    pub fn passed(&self) -> bool {
+        self.over_tolerance == 0
+    }
+}
+
+fn channel_error(a: Colour, b: Colour) -> Colour {
+    Colour::new((a.r - b.r).abs(), (a.g - b.g).abs(), (a.b - b.b).abs())
+}
+
+fn max_channel(c: Colour) -> f64 {
+    c.r.max(c.g).max(c.b)
+}
+
+// black -> blue -> green -> yellow -> red as t goes from 0 to 1
+fn heat(t: f64) -> Colour {
+    let t = t.clamp(0.0, 1.0) * 4.0;
+    match t as usize {
+        0 => Colour::new(0.0, 0.0, t),
+        1 => Colour::new(0.0, t - 1.0, 2.0 - t),
+        2 => Colour::new(t - 2.0, 1.0, 0.0),
+        _ => Colour::new(1.0, (4.0 - t).max(0.0), 0.0),
+    }
+}
+
+/// Compares two canvases channel by channel. Pixels whose largest channel error
+/// exceeds `tolerance` are counted as failures. The returned heat map is scaled
+/// so the worst pixel in the image is red.
+pub fn diff(a: &Canvas, b: &Canvas, tolerance: f64) -> Result<(DiffStats, Canvas), String> {
+    if a.width() != b.width() || a.height() != b.height() {
+        return Err(format!("image sizes differ: {}x{} vs {}x{}",
+                           a.width(), a.height(), b.width(), b.height()));
+    }
+
+    let pixels = a.width() * a.height();
+    let mut max_error = Colour::new(0.0, 0.0, 0.0);
+    let mut total = Colour::new(0.0, 0.0, 0.0);
+    let mut squared = 0.0;
+    let mut over_tolerance = 0;
+    let mut errors = Vec::with_capacity(pixels);
+
+    for y in 0..a.height() {
+        for x in 0..a.width() {
+            let e = channel_error(a.read(x, y), b.read(x, y));
+            max_error = Colour::new(max_error.r.max(e.r), max_error.g.max(e.g), max_error.b.max(e.b));
+            total = total + e;
+            squared += e.r * e.r + e.g * e.g + e.b * e.b;
+            if max_channel(e) > tolerance {
+                over_tolerance += 1;
+            }
+            errors.push(max_channel(e));
+        }
+    }
+
+    let mse = squared / (3 * pixels.max(1)) as f64;
+    let psnr = if mse > 0.0 { -10.0 * mse.log10() } else { f64::INFINITY };
+
+    let worst = max_channel(max_error);
+    let mut heatmap = Canvas::new(a.width(), a.height());
+    for y in 0..a.height() {
+        for x in 0..a.width() {
+            let e = errors[y * a.width() + x];
+            let t = if worst > 0.0 { e / worst } else { 0.0 };
+            heatmap.write(x, y, heat(t));
+        }
+    }
+
+    let stats = DiffStats {
+        max_error,
+        mean_error: total * (1.0 / pixels.max(1) as f64),
+        psnr,
+        over_tolerance,
+        pixels,
+    };
+    Ok((stats, heatmap))
+}
+
+#[cfg(test)]
+mod diff_tests {
+    use super::*;
+
+    #[test]
+    fn identical() {
+        let mut a = Canvas::new(4, 3);
+        a.write(1, 1, Colour::new(0.5, 0.25, 1.0));
+        let (stats, heatmap) = diff(&a, &a, 0.0).unwrap();
+        assert_eq!(stats.max_error, Colour::new(0.0, 0.0, 0.0));
+        assert_eq!(stats.over_tolerance, 0);
+        assert!(stats.psnr.is_infinite());
+        assert!(stats.passed());
+        assert_eq!(heatmap.read(1, 1), Colour::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn differences() {
+        let a = Canvas::new(2, 2);
+        let mut b = Canvas::new(2, 2);
+        b.write(0, 0, Colour::new(0.5, 0.0, 0.0));
+        b.write(1, 1, Colour::new(0.0, 0.0, 0.05));
+
+        let (stats, heatmap) = diff(&a, &b, 0.1).unwrap();
+        assert_eq!(stats.max_error, Colour::new(0.5, 0.0, 0.05));
+        assert_eq!(stats.mean_error, Colour::new(0.125, 0.0, 0.0125));
+        assert_eq!(stats.over_tolerance, 1);
+        assert!(!stats.passed());
+        assert!((stats.psnr - -10.0 * ((0.25 + 0.0025) / 12.0_f64).log10()).abs() < 1e-9);
+
+        assert_eq!(heatmap.read(0, 0), Colour::new(1.0, 0.0, 0.0));
+        assert_eq!(heatmap.read(1, 0), Colour::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn size_mismatch() {
+        assert!(diff(&Canvas::new(2, 2), &Canvas::new(2, 3), 0.0).is_err());
+    }
+}
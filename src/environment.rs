@@ -0,0 +1,245 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use super::canvas::{Canvas, Colour};
+use super::sampling::{self, Distribution};
+use super::sky::Sky;
+use super::transformation::{Transformation, Vector};
+
+// What rays that leave the scene see. The path tracer is also lit by it, from
+// every direction; Whitted rendering shows it in the background and in
+// mirrors, but isn't lit by it.
+
+// Where the unit direction d falls on an equirectangular image, u across and v
+// down it. Straight ahead along z is the middle of the image, and turning
+// towards x moves right, so it isn't mirrored when seen from inside.
+pub fn equirect(d: Vector) -> (f64, f64) {
+    let u = d.x().atan2(d.z()) / (2.0 * PI) + 0.5;
+    let v = d.y().clamp(-1.0, 1.0).acos() / PI;
+    (u.clamp(0.0, 1.0), v)
+}
+
+// the unit direction equirect maps to (u, v)
+pub fn equirect_direction(u: f64, v: f64) -> Vector {
+    let (theta, phi) = ((u - 0.5) * 2.0 * PI, v * PI);
+    Vector::new(phi.sin() * theta.sin(), phi.cos(), phi.sin() * theta.cos())
+}
+
+// An equirectangular image of the light arriving from every direction, with
+// directions picked in proportion to how bright their pixel is.
+#[derive(Debug)]
+pub struct EnvironmentMap {
+    image: Arc<Canvas>,
+    // chooses a row, then the row's own distribution chooses a pixel in it
+    rows: Distribution,
+    columns: Vec<Distribution>,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Arc<Canvas>) -> EnvironmentMap {
+        let (width, height) = (image.width(), image.height());
+        let mut row_weights = vec!();
+        let mut columns = vec!();
+        for y in 0..height {
+            // rows are squeezed together towards the poles
+            let sin = ((y as f64 + 0.5) / height as f64 * PI).sin();
+            let weights: Vec<f64> = (0..width).map(|x| image.read(x, y).luminance().max(0.0) * sin).collect();
+            row_weights.push(weights.iter().sum());
+            columns.push(Distribution::new(&weights));
+        }
+        EnvironmentMap { image, rows: Distribution::new(&row_weights), columns }
+    }
+
+    fn pixel(&self, u: f64) -> usize {
+        ((u * self.image.width() as f64) as usize).min(self.image.width() - 1)
+    }
+
+    fn row(&self, v: f64) -> usize {
+        ((v * self.image.height() as f64) as usize).min(self.image.height() - 1)
+    }
+
+    // the nearest pixel, so the image is as bright as it is sampled
+    fn colour_at(&self, u: f64, v: f64) -> Colour {
+        self.image.read(self.pixel(u), self.row(v))
+    }
+
+    // the density over (u, v), turned into one over directions
+    fn pdf(&self, u: f64, v: f64) -> f64 {
+        let sin = (v * PI).sin();
+        if sin <= 0.0 {
+            return 0.0;
+        }
+        self.rows.pdf(v) * self.columns[self.row(v)].pdf(u) / (2.0 * PI * PI * sin)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Background {
+    Solid(Colour),
+    // blended by height, from bottom straight down to top straight up
+    Gradient { bottom: Colour, top: Colour },
+    Map(Arc<EnvironmentMap>),
+    Sky(Sky),
+}
+
+#[derive(Debug, Clone)]
+pub struct Environment {
+    pub background: Background,
+    pub intensity: f64,
+    // rotates the background around the scene
+    pub trans: Transformation,
+}
+
+impl Environment {
+    // black, as if there were nothing around the scene
+    pub fn new() -> Environment {
+        Environment {
+            background: Background::Solid(Colour::new(0.0, 0.0, 0.0)),
+            intensity: 1.0,
+            trans: Transformation::new(),
+        }
+    }
+
+    fn local(&self, d: Vector) -> Vector {
+        self.trans.inverse().direction(d).normalize()
+    }
+
+    pub fn is_black(&self) -> bool {
+        match self.background {
+            _ if self.intensity == 0.0 => true,
+            Background::Solid(c) => c == Colour::new(0.0, 0.0, 0.0),
+            _ => false,
+        }
+    }
+
+    // the light arriving from direction -d, seen by a ray leaving along d
+    pub fn radiance(&self, d: Vector) -> Colour {
+        let d = self.local(d);
+        let c = match self.background {
+            Background::Solid(c) => c,
+            Background::Gradient { bottom, top } => {
+                let t = (d.y() + 1.0) / 2.0;
+                bottom * (1.0 - t) + top * t
+            },
+            Background::Map(ref map) => {
+                let (u, v) = equirect(d);
+                map.colour_at(u, v)
+            },
+            Background::Sky(ref sky) => sky.radiance(d),
+        };
+        c * self.intensity
+    }
+
+    // A direction out to the environment, chosen with u and v in [0, 1) in
+    // proportion to how bright the environment map is that way, or evenly
+    // otherwise, along with the light from it and the density of choosing it.
+    // None if the environment gives off no light.
+    pub fn sample(&self, u: f64, v: f64) -> Option<(Vector, Colour, f64)> {
+        if self.is_black() {
+            return None;
+        }
+        let local = match self.background {
+            Background::Map(ref map) => {
+                let (y, _) = map.rows.sample(v);
+                let (x, _) = map.columns[map.row(y)].sample(u);
+                equirect_direction(x, y)
+            },
+            _ => sampling::uniform_sphere(u, v),
+        };
+        let d = self.trans.direction(local).normalize();
+        let pdf = self.pdf(d);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((d, self.radiance(d), pdf))
+    }
+
+    // the density, over directions, with which sample chooses d
+    pub fn pdf(&self, d: Vector) -> f64 {
+        if self.is_black() {
+            return 0.0;
+        }
+        match self.background {
+            Background::Map(ref map) => {
+                let (u, v) = equirect(self.local(d));
+                map.pdf(u, v)
+            },
+            _ => 1.0 / (4.0 * PI),
+        }
+    }
+}
+
+#[cfg(test)]
+mod environment_tests {
+    use super::*;
+    use random::Rng;
+
+    fn map(image: Canvas) -> Environment {
+        Environment { background: Background::Map(Arc::new(EnvironmentMap::new(Arc::new(image)))), ..Environment::new() }
+    }
+
+    #[test]
+    fn equirect_mapping() {
+        assert_eq!(equirect(Vector::new(0.0, 0.0, 1.0)), (0.5, 0.5));
+        assert_eq!(equirect(Vector::new(0.0, 1.0, 0.0)).1, 0.0);
+        assert_eq!(equirect(Vector::new(1.0, 0.0, 0.0)), (0.75, 0.5));
+        for &(u, v) in &[(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)] {
+            let (u2, v2) = equirect(equirect_direction(u, v));
+            assert!((u - u2).abs() < 1e-9 && (v - v2).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn backgrounds() {
+        let black = Environment::new();
+        assert!(black.is_black() && black.sample(0.5, 0.5).is_none() && black.pdf(Vector::new(0.0, 1.0, 0.0)) == 0.0);
+
+        let sky = Environment {
+            background: Background::Gradient { bottom: Colour::new(0.0, 0.0, 0.0), top: Colour::new(0.5, 1.0, 2.0) },
+            intensity: 2.0,
+            ..Environment::new()
+        };
+        assert_eq!(sky.radiance(Vector::new(0.0, 1.0, 0.0)), Colour::new(1.0, 2.0, 4.0));
+        assert_eq!(sky.radiance(Vector::new(1.0, 0.0, 0.0)), Colour::new(0.5, 1.0, 2.0));
+        let (d, c, pdf) = sky.sample(0.25, 0.5).unwrap();
+        assert_eq!(c, sky.radiance(d));
+        assert_eq!(pdf, 1.0 / (4.0 * PI));
+    }
+
+    #[test]
+    fn maps_are_rotated() {
+        // a single bright pixel just below the middle of the image, straight
+        // ahead, turned to face x
+        let mut image = Canvas::new(4, 2);
+        image.write(2, 1, Colour::new(1.0, 1.0, 1.0));
+        let mut env = map(image);
+        assert_eq!(env.radiance(Vector::new(0.1, -0.5, 1.0).normalize()), Colour::new(1.0, 1.0, 1.0));
+        env.trans = Transformation::new().rotate(0.0, PI / 2.0, 0.0);
+        assert_eq!(env.radiance(Vector::new(0.1, -0.5, 1.0).normalize()), Colour::new(0.0, 0.0, 0.0));
+        assert_eq!(env.radiance(Vector::new(1.0, -0.5, -0.1).normalize()), Colour::new(1.0, 1.0, 1.0));
+
+        // and only the bright pixel is ever sampled
+        let mut rng = Rng::new(2);
+        for _ in 0..100 {
+            let (d, c, pdf) = env.sample(rng.next_f64(), rng.next_f64()).unwrap();
+            assert_eq!(c, Colour::new(1.0, 1.0, 1.0));
+            assert!(d.x() > 0.0 && d.y() < 0.0 && pdf == env.pdf(d));
+        }
+    }
+
+    #[test]
+    fn map_pdf_integrates_to_one() {
+        let mut image = Canvas::new(8, 4);
+        for y in 0..4 {
+            for x in 0..8 {
+                image.write(x, y, Colour::new((x + y) as f64, 1.0, 0.5));
+            }
+        }
+        let env = map(image);
+        // averaging 1 / pdf over sampled directions gives the sphere's area
+        let mut rng = Rng::new(5);
+        let n = 20000;
+        let area = (0..n).map(|_| 1.0 / env.sample(rng.next_f64(), rng.next_f64()).unwrap().2).sum::<f64>() / n as f64;
+        assert!((area - 4.0 * PI).abs() < 0.02 * 4.0 * PI);
+    }
+}
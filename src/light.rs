@@ -1,47 +1,491 @@
+use std::f64::consts::PI;
+
+use super::bsdf::{Bsdf, Lambert};
 use super::canvas::Colour;
+use super::random::Rng;
+use super::ray::Ray;
 use super::scene_object::Material;
 use super::transformation::Vector;
 
+// intensity at distance d is divided by constant + linear * d + quadratic * d^2
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attenuation {
+    pub constant: f64,
+    pub linear: f64,
+    pub quadratic: f64,
+}
+
+impl Attenuation {
+    pub fn none() -> Attenuation {
+        Attenuation { constant: 1.0, linear: 0.0, quadratic: 0.0 }
+    }
+
+    pub fn inverse_square() -> Attenuation {
+        Attenuation { constant: 0.0, linear: 0.0, quadratic: 1.0 }
+    }
+
+    fn factor(&self, d: f64) -> f64 {
+        1.0 / (self.constant + self.linear * d + self.quadratic * d * d).max(1e-12)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum LightKind {
+    Point { attenuation: Attenuation },
+    // parallel light travelling along dir, pos and shape are ignored
+    Directional { dir: Vector },
+    // shines along dir from pos, fading out over the last falloff radians of
+    // a cone with half angle cone
+    Spot { dir: Vector, cone: f64, falloff: f64, attenuation: Attenuation },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum LightShape {
+    Point,
+    // pos is a corner, the light covers pos + s*u + t*v for s and t in [0, 1]
+    Rect { u: Vector, v: Vector, usteps: usize, vsteps: usize },
+    // pos is the centre
+    Sphere { radius: f64, samples: usize },
+    // for directional lights: arriving from a disc angle radians across, as
+    // sunlight does, rather than a single direction
+    Disc { angle: f64, samples: usize },
+}
+
+// The surface an area light is spread over, as seen from some point: its
+// rectangle, or the disc of a sphere light facing that point.
+#[derive(Debug, Clone, Copy)]
+pub enum Emitter {
+    Rect { corner: Vector, u: Vector, v: Vector },
+    Disc { centre: Vector, normal: Vector, radius: f64 },
+}
+
+impl Emitter {
+    pub fn area(&self) -> f64 {
+        match *self {
+            Emitter::Rect { u, v, .. } => u.cross(&v).norm(),
+            Emitter::Disc { radius, .. } => PI * radius * radius,
+        }
+    }
+
+    pub fn normal(&self) -> Vector {
+        match *self {
+            Emitter::Rect { u, v, .. } => u.cross(&v).normalize(),
+            Emitter::Disc { normal, .. } => normal,
+        }
+    }
+
+    // a point spread uniformly over the surface
+    pub fn sample(&self, rng: &mut Rng) -> Vector {
+        let (s, t) = (rng.next_f64(), rng.next_f64());
+        match *self {
+            Emitter::Rect { corner, u, v } => corner + u * s + v * t,
+            Emitter::Disc { centre, normal, radius } => {
+                let (a, b) = normal.orthonormal_basis();
+                let (r, theta) = (radius * s.sqrt(), 2.0 * PI * t);
+                centre + a * (r * theta.cos()) + b * (r * theta.sin())
+            },
+        }
+    }
+
+    // where r crosses the surface, from either side
+    pub fn intersect(&self, r: Ray) -> Option<f64> {
+        let (origin, n) = match *self {
+            Emitter::Rect { corner, .. } => (corner, self.normal()),
+            Emitter::Disc { centre, normal, .. } => (centre, normal),
+        };
+        let denom = r.dir.dot(&n);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = (origin - r.origin).dot(&n) / denom;
+        if t <= 0.0 {
+            return None;
+        }
+        let d = r.position(t) - origin;
+        let inside = match *self {
+            Emitter::Rect { u, v, .. } => {
+                let (s, t) = (d.dot(&u) / u.dot(&u), d.dot(&v) / v.dot(&v));
+                (0.0..=1.0).contains(&s) && (0.0..=1.0).contains(&t)
+            },
+            Emitter::Disc { radius, .. } => d.norm() <= radius,
+        };
+        if inside { Some(t) } else { None }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Light {
-    pub intensity: f64,
+    pub intensity: Colour,
     pub pos: Vector,
+    pub kind: LightKind,
+    pub shape: LightShape,
 }
 
-pub fn lighting(m: Material, p: Vector, l: Light, eye: Vector, normal: Vector) -> Colour {
-    let effective = m.colour * l.intensity; 
-    let lightv = (l.pos -p).normalize();
-    let ambient = effective * m.ambient;
-    let light_dot_normal = lightv.dot(&normal);
+impl Light {
+    pub fn new(intensity: Colour, pos: Vector) -> Light {
+        Light { intensity, pos, kind: LightKind::Point { attenuation: Attenuation::none() }, shape: LightShape::Point }
+    }
+
+    pub fn directional(intensity: Colour, dir: Vector) -> Light {
+        Light { kind: LightKind::Directional { dir: dir.normalize() }, ..Light::new(intensity, Vector::new(0.0, 0.0, 0.0)) }
+    }
+
+    pub fn spot(intensity: Colour, pos: Vector, dir: Vector, cone: f64, falloff: f64) -> Light {
+        let kind = LightKind::Spot { dir: dir.normalize(), cone, falloff, attenuation: Attenuation::none() };
+        Light { kind, ..Light::new(intensity, pos) }
+    }
+
+    // Points on the light as seen from p, one per stratum. Without an rng each
+    // point is at the centre of its stratum, otherwise it is jittered within it.
+    pub fn sample_points(&self, p: Vector, mut rng: Option<&mut Rng>) -> Vec<Vector> {
+        let mut jitter = || rng.as_mut().map_or(0.5, |r| r.next_f64());
+        match (self.kind, self.shape) {
+            (LightKind::Directional { .. }, _) | (_, LightShape::Point) | (_, LightShape::Disc { .. }) => vec!(self.pos),
+            (_, LightShape::Rect { u, v, usteps, vsteps }) => {
+                let mut points = Vec::with_capacity(usteps * vsteps);
+                for j in 0..vsteps {
+                    for i in 0..usteps {
+                        let s = (i as f64 + jitter()) / usteps as f64;
+                        let t = (j as f64 + jitter()) / vsteps as f64;
+                        points.push(self.pos + u * s + v * t);
+                    }
+                }
+                points
+            },
+            (_, LightShape::Sphere { radius, samples }) => {
+                // the sphere looks like a disc from p, so samples are spread over
+                // that disc along a golden angle spiral, one per ring of equal area
+                let (a, b) = (self.pos - p).normalize().orthonormal_basis();
+                let golden_angle = PI * (3.0 - 5.0_f64.sqrt());
+                (0..samples).map(|i| {
+                    let r = radius * ((i as f64 + jitter()) / samples as f64).sqrt();
+                    let theta = golden_angle * i as f64 + 2.0 * PI * (jitter() - 0.5) / samples as f64;
+                    self.pos + a * (r * theta.cos()) + b * (r * theta.sin())
+                }).collect()
+            },
+        }
+    }
 
+    // Directions towards a directional light, spread over its disc the same
+    // way sample_points spreads points over a sphere light. Other lights have
+    // no one direction, and give none.
+    pub fn directions(&self, mut rng: Option<&mut Rng>) -> Vec<Vector> {
+        let (dir, angle, samples) = match (self.kind, self.shape) {
+            (LightKind::Directional { dir }, LightShape::Disc { angle, samples }) => (dir, angle, samples),
+            (LightKind::Directional { dir }, _) => return vec!(-dir),
+            _ => return vec!(),
+        };
+        let mut jitter = || rng.as_mut().map_or(0.5, |r| r.next_f64());
+        let (a, b) = dir.orthonormal_basis();
+        let radius = (angle / 2.0).tan();
+        let golden_angle = PI * (3.0 - 5.0_f64.sqrt());
+        (0..samples).map(|i| {
+            let r = radius * ((i as f64 + jitter()) / samples as f64).sqrt();
+            let theta = golden_angle * i as f64 + 2.0 * PI * (jitter() - 0.5) / samples as f64;
+            (-dir + a * (r * theta.cos()) + b * (r * theta.sin())).normalize()
+        }).collect()
+    }
+
+    // the surface of an area light as seen from p, None for lights that are a
+    // single point or direction
+    pub fn emitter(&self, p: Vector) -> Option<Emitter> {
+        match (self.kind, self.shape) {
+            (LightKind::Directional { .. }, _) | (_, LightShape::Point) | (_, LightShape::Disc { .. }) => None,
+            (_, LightShape::Rect { u, v, .. }) => Some(Emitter::Rect { corner: self.pos, u, v }),
+            (_, LightShape::Sphere { radius, .. }) => {
+                Some(Emitter::Disc { centre: self.pos, normal: (p - self.pos).normalize(), radius })
+            },
+        }
+    }
+
+    // The unit vector from p towards a sample point on the light, and the light
+    // arriving at p from that point before shadowing. A point on the light
+    // itself has no direction to it, and gets nothing.
+    pub fn incident(&self, p: Vector, sample: Vector) -> (Vector, Colour) {
+        let towards = sample - p;
+        let distance = towards.norm();
+        if distance == 0.0 && !matches!(self.kind, LightKind::Directional { .. }) {
+            return (Vector::new(0.0, 0.0, 0.0), Colour::new(0.0, 0.0, 0.0));
+        }
+        let lightv = towards * (1.0 / distance);
+        match self.kind {
+            LightKind::Point { attenuation } => (lightv, self.intensity * attenuation.factor(distance)),
+            LightKind::Directional { dir } => (-dir, self.intensity),
+            LightKind::Spot { dir, cone, falloff, attenuation } => {
+                let angle = (-lightv).dot(&dir).clamp(-1.0, 1.0).acos();
+                let t = ((cone - angle) / falloff.max(1e-12)).clamp(0.0, 1.0);
+                let spot = t * t * (3.0 - 2.0 * t);
+                (lightv, self.intensity * (spot * attenuation.factor(distance)))
+            },
+        }
+    }
+
+    // the light's share of the ambient term at p, which fades with distance
+    // and outside a spot's cone as its direct light does
+    pub fn ambient(&self, p: Vector) -> Colour {
+        let centre = match self.shape {
+            LightShape::Rect { u, v, .. } => self.pos + u * 0.5 + v * 0.5,
+            _ => self.pos,
+        };
+        self.incident(p, centre).1
+    }
+}
+
+// samples are the points on the light that p is shaded from, None where the
+// shadow test found them hidden, and colour is the surface's at p, from m's
+// pattern
+pub fn lighting(m: &Material, colour: Colour, p: Vector, l: Light, eye: Vector, normal: Vector, samples: &[Option<Vector>]) -> Colour {
+    let ambient = colour * l.ambient(p) * m.ambient;
     let mut diffuse = Colour::new(0.0, 0.0, 0.0);
     let mut specular = Colour::new(0.0, 0.0, 0.0);
-    if light_dot_normal >= 0.0 {
-        diffuse = effective * m.diffuse * light_dot_normal;
-        let reflect = (-lightv).reflect(&normal); //  helpers::reflect(-lightv, normal);
-        let reflect_dot_eye = reflect.dot(&eye).powi(m.shininess as i32);
-        if reflect_dot_eye > 0.0 {
-            let s = l.intensity * m.specular * reflect_dot_eye;
-            specular  = Colour::new(s, s, s);
+    for pos in samples.iter().flatten() {
+        let (lightv, intensity) = l.incident(p, *pos);
+        let light_dot_normal = lightv.dot(&normal);
+        if light_dot_normal >= 0.0 {
+            diffuse = diffuse + colour * intensity * m.diffuse * light_dot_normal;
+            let reflect = (-lightv).reflect(&normal);
+            let reflect_dot_eye = reflect.dot(&eye);
+            if reflect_dot_eye > 0.0 {
+                specular = specular + intensity * (m.specular * reflect_dot_eye.powf(m.shininess));
+            }
         }
     }
-    ambient + diffuse + specular
+    let scale = 1.0 / samples.len().max(1) as f64;
+    ambient + diffuse * scale + specular * scale
 }
 
+// The same for materials with a bsdf, which scatters the light instead of the
+// phong terms, or lambertian ones without. normal faces out of the object
+// rather than towards the eye.
+pub fn bsdf_lighting(m: &Material, colour: Colour, p: Vector, l: Light, eye: Vector, normal: Vector, samples: &[Option<Vector>]) -> Colour {
+    let bsdf: &dyn Bsdf = m.bsdf.as_ref().map_or(&Lambert, |b| &**b);
+    let ambient = colour * l.ambient(p) * m.ambient;
+    let mut scattered = Colour::new(0.0, 0.0, 0.0);
+    for pos in samples.iter().flatten() {
+        let (lightv, intensity) = l.incident(p, *pos);
+        // pi makes a white lambertian surface as bright as phong's diffuse term
+        let f = bsdf.eval(eye, lightv, normal, colour);
+        scattered = scattered + f * intensity * (PI * lightv.dot(&normal).abs());
+    }
+    ambient + scattered * (1.0 / samples.len().max(1) as f64)
+}
 
 #[cfg(test)]
 mod lighting_tests {
     use super::*;
+    use scene_object::SceneObject;
+    use sphere::Sphere;
 
     #[test]
     fn eye_between_light_and_surface() {
         let m = Material::new();
+        let s = Sphere::new();
         let p = Vector::new(0.0, 0.0, 0.0);
         let eye = Vector::new(0.0, 0.0, -1.0);
         let normal = Vector::new(0.0, 0.0, -1.0);
-        let l = Light{intensity: 1.0,
-                      pos: Vector::new(0.0, 0.0, -10.0)};
-        let result = lighting(m, p, l, eye, normal);
+        let l = Light{intensity: Colour::new(1.0, 1.0, 1.0),
+                      pos: Vector::new(0.0, 0.0, -10.0),
+                      kind: LightKind::Point {attenuation: Attenuation::none()},
+                      shape: LightShape::Point};
+        let result = lighting(&m, s.colour_at(p, 0.0), p, l, eye, normal, &[Some(l.pos)]);
         assert_eq!(result, Colour::new(1.9, 1.9, 1.9)); 
     }
-}
 
+    #[test]
+    fn in_shadow() {
+        let m = Material::new();
+        let s = Sphere::new();
+        let p = Vector::new(0.0, 0.0, 0.0);
+        let eye = Vector::new(0.0, 0.0, -1.0);
+        let normal = Vector::new(0.0, 0.0, -1.0);
+        let l = Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, 0.0, -10.0));
+        assert_eq!(lighting(&m, s.colour_at(p, 0.0), p, l, eye, normal, &[None]), Colour::new(0.1, 0.1, 0.1));
+        assert_eq!(lighting(&m, s.colour_at(p, 0.0), p, l, eye, normal, &[Some(l.pos), None]), Colour::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn fractional_shininess() {
+        let mut m = Material::new();
+        m.shininess = 1.5;
+        let p = Vector::new(0.0, 0.0, 0.0);
+        let (eye, normal) = (Vector::new(0.0, 0.0, -1.0), Vector::new(0.0, 0.0, -1.0));
+        let white = Colour::new(1.0, 1.0, 1.0);
+        let l = Light::new(white, Vector::new(0.0, 10.0, -10.0));
+        let shiny = lighting(&m, white, p, l, eye, normal, &[Some(l.pos)]);
+        m.specular = 0.0;
+        let matte = lighting(&m, white, p, l, eye, normal, &[Some(l.pos)]);
+        assert!((shiny.r - matte.r - 0.9 * 0.5_f64.sqrt().powf(1.5)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rect_samples() {
+        let l = Light {
+            shape: LightShape::Rect { u: Vector::new(2.0, 0.0, 0.0), v: Vector::new(0.0, 0.0, 1.0), usteps: 4, vsteps: 2 },
+            ..Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, 0.0, 0.0))
+        };
+        let p = Vector::new(0.0, 5.0, 0.0);
+        let centres = l.sample_points(p, None);
+        assert_eq!(centres.len(), 8);
+        assert_eq!(centres[0], Vector::new(0.25, 0.0, 0.25));
+        assert_eq!(centres[6], Vector::new(1.25, 0.0, 0.75));
+
+        let mut rng = Rng::new(3);
+        let jittered = l.sample_points(p, Some(&mut rng));
+        for (j, c) in jittered.iter().zip(centres.iter()) {
+            assert!((j.x() - c.x()).abs() <= 0.25 && (j.z() - c.z()).abs() <= 0.25);
+            assert_eq!(j.y(), 0.0);
+        }
+    }
+
+    #[test]
+    fn sphere_samples() {
+        let l = Light {
+            shape: LightShape::Sphere { radius: 2.0, samples: 16 },
+            ..Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, 10.0, 0.0))
+        };
+        let mut rng = Rng::new(5);
+        let points = l.sample_points(Vector::new(0.0, 0.0, 0.0), Some(&mut rng));
+        assert_eq!(points.len(), 16);
+        for p in &points {
+            // on the disc facing the shaded point
+            assert!((p.y() - 10.0).abs() < 1e-10);
+            assert!((*p - l.pos).norm() <= 2.0 + 1e-10);
+        }
+    }
+
+    #[test]
+    fn emitters() {
+        let mut l = Light {
+            shape: LightShape::Rect { u: Vector::new(2.0, 0.0, 0.0), v: Vector::new(0.0, 0.0, 1.0), usteps: 4, vsteps: 2 },
+            ..Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, 0.0, 0.0))
+        };
+        let rect = l.emitter(Vector::new(0.0, 5.0, 0.0)).unwrap();
+        assert_eq!(rect.area(), 2.0);
+        let up = |x: f64, z: f64| Ray::new(Vector::new(x, -1.0, z), Vector::new(0.0, 1.0, 0.0));
+        assert_eq!(rect.intersect(up(1.5, 0.5)), Some(1.0));
+        assert_eq!(rect.intersect(up(2.5, 0.5)), None);
+        let mut rng = Rng::new(1);
+        for _ in 0..10 {
+            let p = rect.sample(&mut rng);
+            assert!((0.0..=2.0).contains(&p.x()) && p.y() == 0.0 && (0.0..=1.0).contains(&p.z()));
+        }
+
+        // a sphere light is the disc facing the point it is seen from
+        l.shape = LightShape::Sphere { radius: 1.0, samples: 1 };
+        let disc = l.emitter(Vector::new(0.0, 0.0, -5.0)).unwrap();
+        assert!((disc.area() - PI).abs() < 1e-12);
+        assert_eq!(disc.normal(), Vector::new(0.0, 0.0, -1.0));
+        let towards = |x: f64| Ray::new(Vector::new(x, 0.0, -5.0), Vector::new(0.0, 0.0, 1.0));
+        assert_eq!(disc.intersect(towards(0.5)), Some(5.0));
+        assert_eq!(disc.intersect(towards(1.5)), None);
+
+        assert!(Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, 0.0, 0.0)).emitter(Vector::new(0.0, 1.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn bsdf_lighting_matches_phong() {
+        let m = Material::new();
+        let s = Sphere::new();
+        let p = Vector::new(0.0, 0.0, 0.0);
+        let eye = Vector::new(0.0, 0.0, -1.0);
+        let l = Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, 0.0, -10.0));
+        // a white lambertian surface is as bright as phong's diffuse term at 1
+        let lit = bsdf_lighting(&m, s.colour_at(p, 0.0), p, l, eye, Vector::new(0.0, 0.0, -1.0), &[Some(l.pos)]);
+        assert_eq!(lit, Colour::new(1.1, 1.1, 1.1));
+        assert_eq!(bsdf_lighting(&m, s.colour_at(p, 0.0), p, l, eye, Vector::new(0.0, 0.0, 1.0), &[Some(l.pos)]), lit);
+        assert_eq!(bsdf_lighting(&m, s.colour_at(p, 0.0), p, l, eye, Vector::new(0.0, 0.0, -1.0), &[None]), Colour::new(0.1, 0.1, 0.1));
+    }
+
+    #[test]
+    fn coloured_light() {
+        let m = Material::new();
+        let s = Sphere::new();
+        let p = Vector::new(0.0, 0.0, 0.0);
+        let eye = Vector::new(0.0, 0.0, -1.0);
+        let normal = Vector::new(0.0, 0.0, -1.0);
+        let l = Light::new(Colour::new(1.0, 0.5, 0.0), Vector::new(0.0, 0.0, -10.0));
+        assert_eq!(lighting(&m, s.colour_at(p, 0.0), p, l, eye, normal, &[Some(l.pos)]), Colour::new(1.9, 0.95, 0.0));
+    }
+
+    #[test]
+    fn attenuation() {
+        let mut l = Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, 0.0, -4.0));
+        let p = Vector::new(0.0, 0.0, 0.0);
+        assert_eq!(l.incident(p, l.pos).1, Colour::new(1.0, 1.0, 1.0));
+        l.kind = LightKind::Point { attenuation: Attenuation::inverse_square() };
+        let (lightv, intensity) = l.incident(p, l.pos);
+        assert_eq!(lightv, Vector::new(0.0, 0.0, -1.0));
+        assert_eq!(intensity, Colour::new(0.0625, 0.0625, 0.0625));
+        l.kind = LightKind::Point { attenuation: Attenuation { constant: 1.0, linear: 0.5, quadratic: 0.0 } };
+        assert_eq!(l.incident(p, l.pos).1, Colour::new(1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0));
+    }
+
+    #[test]
+    fn at_the_light() {
+        let mut l = Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, 0.0, -4.0));
+        l.kind = LightKind::Point { attenuation: Attenuation::inverse_square() };
+        let m = Material::new();
+        let (eye, normal) = (Vector::new(0.0, 0.0, -1.0), Vector::new(0.0, 0.0, -1.0));
+        assert_eq!(l.incident(l.pos, l.pos), (Vector::new(0.0, 0.0, 0.0), Colour::new(0.0, 0.0, 0.0)));
+        assert_eq!(l.ambient(l.pos), Colour::new(0.0, 0.0, 0.0));
+        assert_eq!(lighting(&m, Colour::new(1.0, 1.0, 1.0), l.pos, l, eye, normal, &[Some(l.pos)]), Colour::new(0.0, 0.0, 0.0));
+        assert_eq!(bsdf_lighting(&m, Colour::new(1.0, 1.0, 1.0), l.pos, l, eye, normal, &[Some(l.pos)]), Colour::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn directional() {
+        let l = Light::directional(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, -2.0, 0.0));
+        let (lightv, intensity) = l.incident(Vector::new(5.0, 0.0, 5.0), l.pos);
+        assert_eq!(lightv, Vector::new(0.0, 1.0, 0.0));
+        assert_eq!(intensity, Colour::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn directional_discs() {
+        let mut l = Light::directional(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, -1.0, 0.0));
+        assert_eq!(l.directions(None), vec!(Vector::new(0.0, 1.0, 0.0)));
+        l.shape = LightShape::Disc { angle: 0.2, samples: 16 };
+        let directions = l.directions(Some(&mut Rng::new(0)));
+        assert_eq!(directions.len(), 16);
+        for d in &directions {
+            assert!((d.norm() - 1.0).abs() < 1e-12 && d.y().acos() <= 0.1 + 1e-12);
+        }
+        assert!(directions.iter().any(|d| d.y().acos() > 0.07));
+        assert!(Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, 0.0, 0.0)).directions(None).is_empty());
+    }
+
+    #[test]
+    fn ambient_fades_with_the_light() {
+        let m = Material::new();
+        let p = Vector::new(0.0, 0.0, 0.0);
+        let (eye, normal) = (Vector::new(0.0, 0.0, -1.0), Vector::new(0.0, 0.0, -1.0));
+        let white = Colour::new(1.0, 1.0, 1.0);
+        let mut far = Light::new(white, Vector::new(0.0, 0.0, -4.0));
+        far.kind = LightKind::Point { attenuation: Attenuation::inverse_square() };
+        assert_eq!(lighting(&m, white, p, far, eye, normal, &[None]), Colour::new(0.1, 0.1, 0.1) * 0.0625);
+        assert_eq!(bsdf_lighting(&m, white, p, far, eye, normal, &[None]), Colour::new(0.1, 0.1, 0.1) * 0.0625);
+
+        // nothing from a spot light pointing away
+        let away = Light::spot(white, Vector::new(0.0, 0.0, -4.0), Vector::new(0.0, 0.0, -1.0), 0.5, 0.2);
+        assert_eq!(lighting(&m, white, p, away, eye, normal, &[Some(away.pos)]), Colour::new(0.0, 0.0, 0.0));
+
+        // a rectangle's is as bright as at its centre
+        let rect = Light {
+            shape: LightShape::Rect { u: Vector::new(2.0, 0.0, 0.0), v: Vector::new(0.0, 2.0, 0.0), usteps: 1, vsteps: 1 },
+            ..far
+        };
+        assert_eq!(rect.ambient(Vector::new(1.0, 1.0, 0.0)), Colour::new(0.0625, 0.0625, 0.0625));
+    }
+
+    #[test]
+    fn spot() {
+        let l = Light::spot(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, 10.0, 0.0), Vector::new(0.0, -1.0, 0.0), 0.5, 0.2);
+        let at = |x: f64| l.incident(Vector::new(x, 0.0, 0.0), l.pos).1.r;
+        assert_eq!(at(0.0), 1.0);
+        // inside the cone but before the falloff starts
+        assert_eq!(at(10.0 * 0.25_f64.tan()), 1.0);
+        let edge = at(10.0 * 0.4_f64.tan());
+        assert!(edge > 0.0 && edge < 1.0);
+        assert_eq!(at(10.0 * 0.6_f64.tan()), 0.0);
+    }
+}
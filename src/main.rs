@@ -1,74 +1,603 @@
 extern crate clap;
 extern crate approx;
 extern crate nalgebra as na;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
+extern crate png;
 
-use clap::{Arg, App};
+use clap::{Arg, App, AppSettings, SubCommand};
+use std::process;
+use std::time::Duration;
 
+mod adaptive;
+mod animation;
+mod aov;
+mod bsdf;
+mod camera;
 mod canvas;
+mod denoise;
+mod diff;
+mod environment;
 mod ray;
+mod scene;
 mod sphere;
 mod scene_object;
+mod sky;
 mod light;
+mod mesh;
+mod noise;
+mod output;
+mod path;
+mod pattern;
+mod plane;
+mod progressive;
+mod random;
+mod render;
+mod sampling;
+mod texture;
+mod tiles;
 mod transformation;
+mod world;
 
-use scene_object::SceneObject;
 use transformation::Vector;
 
-#[derive(Clone, Copy)]
-struct Wall {
-    z: f64, 
-    size: f64, 
-    pixel_size: f64, 
-}
-
-fn raytrace(x: f64, y: f64, source: Vector, wall: Wall, sphere: sphere::Sphere) -> canvas::Colour {
-    let position = Vector::new(x, y, wall.z);
-    let r = ray::Ray::new(source, (position-source).normalize() );
-    
-    match ray::get_hit(sphere.intersect(r)) {
-        Some(hit) => {
-            let point = r.position(hit.t); 
-            let light = light::Light {intensity: 1.0, pos: Vector::new(-10.0, 10.0, -10.0)};
-            let normal = sphere.normal(point);
-            let c = light::lighting(sphere.material, point, light, r.dir, normal);
-            return c;
+fn default_scene() -> scene::Scene {
+    let mut world = world::World::new();
+    world.objects.push(Box::new(sphere::Sphere::new()));
+    world.lights.push(light::Light::new(canvas::Colour::new(1.0, 1.0, 1.0), Vector::new(-10.0, 10.0, -10.0)));
+    let camera = camera::Camera::new(1000, 1000, 2.0 * (3.5_f64 / 15.0).atan())
+                     .look_at(Vector::new(0.0, 0.0, -5.0), Vector::new(0.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0));
+    scene::Scene { camera, world }
+}
+
+fn is_positive(v: String) -> Result<(), String> {
+    match v.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(()),
+        _ => Err(format!("expected a whole number greater than zero, found `{}`", v)),
+    }
+}
+
+fn is_count(v: String) -> Result<(), String> {
+    v.parse::<usize>().map(|_| ()).map_err(|_| format!("expected a whole number, found `{}`", v))
+}
+
+fn is_fraction(v: String) -> Result<(), String> {
+    match v.parse::<f64>() {
+        Ok(f) if f > 0.0 => Ok(()),
+        _ => Err(format!("expected a number greater than zero, found `{}`", v)),
+    }
+}
+
+fn is_seed(v: String) -> Result<(), String> {
+    v.parse::<u64>().map(|_| ()).map_err(|_| format!("expected an unsigned 64 bit integer, found `{}`", v))
+}
+
+// "start..end", including both, or a single frame
+fn frame_range(v: &str) -> Option<(usize, usize)> {
+    let (start, end) = match v.find("..") {
+        Some(i) => (v[..i].parse().ok()?, v[i + 2..].parse().ok()?),
+        None => {
+            let frame = v.parse().ok()?;
+            (frame, frame)
         },
-        None => { }
+    };
+    if start <= end { Some((start, end)) } else { None }
+}
+
+fn is_frames(v: String) -> Result<(), String> {
+    frame_range(&v).map(|_| ()).ok_or_else(|| format!("expected frames as start..end, or a single frame, found `{}`", v))
+}
+
+// "name=file", writing the output variable name to file
+fn aov_file(v: &str) -> Option<(aov::Aov, &str)> {
+    let i = v.find('=')?;
+    match (aov::Aov::from_name(&v[..i]), &v[i + 1..]) {
+        (Some(aov), file) if !file.is_empty() => Some((aov, file)),
+        _ => None,
+    }
+}
+
+fn is_aov(v: String) -> Result<(), String> {
+    aov_file(&v).map(|_| ()).ok_or_else(|| {
+        format!("expected NAME=FILE, where NAME is depth, normal, albedo, position, object-id or material-id, found `{}`", v)
+    })
+}
+
+// "x0,y0,x1,y1", the corners of a crop, with x1 and y1 past its last pixel
+fn crop(v: &str) -> Option<(usize, usize, usize, usize)> {
+    let n: Vec<usize> = v.split(',').map(|n| n.trim().parse().ok()).collect::<Option<_>>()?;
+    match n[..] {
+        [x0, y0, x1, y1] if x0 < x1 && y0 < y1 => Some((x0, y0, x1, y1)),
+        _ => None,
+    }
+}
+
+fn is_crop(v: String) -> Result<(), String> {
+    crop(&v).map(|_| ()).ok_or_else(|| format!("expected x0,y0,x1,y1 with x0 < x1 and y0 < y1, found `{}`", v))
+}
+
+// "i/n", the i'th of n tiles, counting from 1
+fn tile(v: &str) -> Option<(usize, usize)> {
+    let i = v.find('/')?;
+    match (v[..i].parse().ok()?, v[i + 1..].parse().ok()?) {
+        (i, n) if i >= 1 && i <= n => Some((i, n)),
+        _ => None,
     }
-    canvas::Colour::new(0.0, 0.0, 0.0)
 }
 
-fn run(filename: &str, canvas_size: usize) {
+fn is_tile(v: String) -> Result<(), String> {
+    tile(&v).map(|_| ()).ok_or_else(|| format!("expected I/N, with I from 1 to N, found `{}`", v))
+}
 
-    let mut cvs = canvas::Canvas::new(canvas_size, canvas_size);
-    let source = Vector::new(0.0, 0.0, -5.0);
-    let sphere = sphere::Sphere::new();
+fn number<T: std::str::FromStr>(matches: &clap::ArgMatches, name: &str) -> Option<T> {
+    // validators have already checked these parse
+    matches.value_of(name).and_then(|v| v.parse().ok())
+}
 
-    let wall = Wall {z: 10.0, 
-                     size: 7.0, 
-                     pixel_size: 7.0 / (canvas_size as f64) };
-    let half = wall.size / 2.0; 
+// options that render progressively
+const PROGRESSIVE: [&str; 5] = ["time-limit", "noise-threshold", "snapshot-every", "checkpoint-every", "resume"];
 
-    for y in 0..(canvas_size - 1) {
-        let world_y =  half - wall.pixel_size * (y as f64);
-        for x in 0..(canvas_size - 1) {
-            let world_x =  half - wall.pixel_size * (x as f64);
-            let c =  raytrace(world_x, world_y, source, wall, sphere);
-            cvs.write(x, y, c);
+// the camera at the size --width and --height ask for
+fn resize(matches: &clap::ArgMatches, camera: &mut camera::Camera) {
+    // if only one dimension is given the scene's aspect ratio is kept
+    let (w, h) = (camera.hsize, camera.vsize);
+    match (number::<usize>(matches, "width"), number::<usize>(matches, "height")) {
+        (Some(width), Some(height)) => camera.resize(width, height),
+        (Some(width), None) => camera.resize(width, (width * h / w).max(1)),
+        (None, Some(height)) => camera.resize((height * w / h).max(1), height),
+        (None, None) => {},
+    }
+}
+
+// the part of the camera's image that --crop or --tile picks, as x0, y0, x1, y1
+fn region(matches: &clap::ArgMatches, camera: &camera::Camera) -> Option<(usize, usize, usize, usize)> {
+    let (w, h) = (camera.hsize, camera.vsize);
+    if let Some((i, n)) = matches.value_of("tile").and_then(tile) {
+        if n > h {
+            eprintln!("Unable to split a {} pixel tall image into {} tiles", h, n);
+            process::exit(2);
         }
+        let (y0, y1) = tiles::band(i, n, h);
+        return Some((0, y0, w, y1));
     }
-    cvs.save_ppm(filename.to_string());
+    let (x0, y0, x1, y1) = matches.value_of("crop").and_then(crop)?;
+    if x1 > w || y1 > h {
+        eprintln!("The crop {},{},{},{} is outside the {}x{} image", x0, y0, x1, y1, w, h);
+        process::exit(2);
+    }
+    Some((x0, y0, x1, y1))
+}
+
+// filename, numbered after frame and named after where region starts in the
+// whole image, which is size pixels across and down
+fn output_name(filename: &str, frame: Option<usize>, region: Option<(usize, usize, usize, usize)>, size: (usize, usize)) -> String {
+    let filename = frame.map_or(filename.to_string(), |n| animation::frame_filename(filename, n));
+    region.map_or(filename.clone(), |(x, y, _, _)| tiles::tile_filename(&filename, x, y, size.0, size.1))
+}
+
+// frame is set when rendering one frame of an animation, which is written to
+// a file numbered after it
+fn run(matches: &clap::ArgMatches, mut scene: scene::Scene, frame: Option<usize>) {
+    resize(matches, &mut scene.camera);
+    let region = region(matches, &scene.camera);
+    let size = (scene.camera.hsize, scene.camera.vsize);
+    if let Some((x0, y0, x1, y1)) = region {
+        scene.camera = scene.camera.crop(x0, y0, x1, y1);
+    }
+    let name = |f: &str| output_name(f, frame, region, size);
+    let filename = &name(matches.value_of("filename").unwrap());
+
+    let mut settings = render::Settings::new();
+    settings.samples = number(matches, "samples").unwrap_or(settings.samples);
+    settings.max_depth = number(matches, "max-depth").unwrap_or(settings.max_depth);
+    settings.threads = number(matches, "threads").unwrap_or_else(|| {
+        std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    });
+    settings.seed = number(matches, "seed").unwrap_or(settings.seed);
+    settings.pattern = sampling::SamplePattern::from_name(matches.value_of("pattern").unwrap()).unwrap();
+    settings.filter = sampling::Filter::from_name(matches.value_of("filter").unwrap()).unwrap();
+    settings.integrator = render::Integrator::from_name(matches.value_of("integrator").unwrap()).unwrap();
+
+    let format = matches.value_of("format")
+                        .and_then(output::Format::from_name)
+                        .or_else(|| output::Format::from_filename(filename))
+                        .unwrap_or(output::Format::Ppm);
+    let tone_map = output::ToneMap::from_name(matches.value_of("tone-map").unwrap()).unwrap();
+
+    // written under another name first, so a file that's there is finished
+    let save_mapped = |image: &canvas::Canvas, filename: &str, format, tone_map| {
+        let partial = format!("{}.partial", filename);
+        output::save(image, &partial, format, tone_map).and_then(|_| std::fs::rename(&partial, filename)).unwrap_or_else(|e| {
+            eprintln!("Unable to write {}: {}", filename, e);
+            process::exit(2);
+        });
+    };
+    let save = |image: &canvas::Canvas, filename: &str, format| save_mapped(image, filename, format, tone_map);
+
+    // output variables keep their values in pfm, which they default to, and
+    // are clamped in anything else
+    let aovs: Vec<(aov::Aov, String)> = matches.values_of("aov").into_iter().flatten()
+                                               .map(|v| aov_file(v).map(|(a, f)| (a, name(f))).unwrap())
+                                               .collect();
+    let kinds: Vec<aov::Aov> = aovs.iter().map(|&(a, _)| a).collect();
+    for (image, (_, file)) in aov::render(&scene.camera, &scene.world, &kinds, settings.threads).iter().zip(&aovs) {
+        save_mapped(image, file, output::Format::from_filename(file).unwrap_or(output::Format::Pfm), output::ToneMap::Clamp);
+    }
+
+    // checkpoints sit next to the image, and are kept only while it's unfinished
+    let checkpoint_file = &format!("{}.checkpoint", filename);
+    let progressively = !matches.is_present("adaptive") && PROGRESSIVE.iter().any(|a| matches.is_present(a));
+    let image = if matches.is_present("adaptive") {
+        let mut adaptive = adaptive::Adaptive::new();
+        adaptive.threshold = number(matches, "adaptive-threshold").unwrap_or(adaptive.threshold);
+        adaptive.max_level = number(matches, "adaptive-levels").unwrap_or(adaptive.max_level);
+        let (image, debug) = adaptive.render(&scene.camera, &scene.world, &settings);
+        if let Some(debug_file) = matches.value_of("adaptive-debug") {
+            let debug_file = &name(debug_file);
+            save(&debug, debug_file, output::Format::from_filename(debug_file).unwrap_or(format));
+        }
+        image
+    } else if progressively {
+        let mut progressive = progressive::Progressive::new();
+        progressive.max_samples = number(matches, "samples").unwrap_or(progressive.max_samples);
+        progressive.time_limit = number(matches, "time-limit").map(Duration::from_secs_f64);
+        progressive.threshold = number(matches, "noise-threshold");
+        progressive.snapshot_every = number(matches, "snapshot-every").map(Duration::from_secs_f64);
+        progressive.checkpoint_every = number(matches, "checkpoint-every").map(Duration::from_secs_f64);
+
+        let source = matches.value_of("scene").and_then(|f| std::fs::read_to_string(f).ok()).unwrap_or_default();
+        let key = &progressive.checkpoint_key(&settings, &scene.camera, &source);
+        let (w, h) = (scene.camera.hsize, scene.camera.vsize);
+        let mut acc = if matches.is_present("resume") && std::path::Path::new(checkpoint_file).exists() {
+            progressive::Accumulation::load(checkpoint_file, w, h, key).unwrap_or_else(|e| {
+                eprintln!("Unable to resume from {}: {}", checkpoint_file, e);
+                process::exit(2);
+            })
+        } else {
+            progressive::Accumulation::new(w, h)
+        };
+        let checkpoint = |acc: &progressive::Accumulation| {
+            let partial = format!("{}.partial", checkpoint_file);
+            acc.save(&partial, key).and_then(|_| std::fs::rename(&partial, checkpoint_file)).unwrap_or_else(|e| {
+                eprintln!("Unable to write {}: {}", checkpoint_file, e);
+                process::exit(2);
+            });
+        };
+        // snapshots are written elsewhere, so that --frames doesn't skip an
+        // unfinished frame, and removed once it's finished
+        let snapshot_file = &progressive::snapshot_filename(filename);
+        progressive.render(&mut acc, &scene.camera, &scene.world, &settings, |acc| save(&acc.image(), snapshot_file, format), checkpoint);
+        let _ = std::fs::remove_file(snapshot_file);
+        // whatever the options, so that --frames knows to render a frame
+        // stopped by a time limit again and an old checkpoint doesn't outlive
+        // the image it was for
+        if progressive.finished(&acc) {
+            let _ = std::fs::remove_file(checkpoint_file);
+        } else {
+            checkpoint(&acc);
+        }
+        acc.image()
+    } else {
+        render::render(&scene.camera, &scene.world, &settings)
+    };
+    if matches.is_present("denoise") {
+        let guides = denoise::Guides::render(&scene.camera, &scene.world, settings.threads);
+        save(&denoise::Denoiser::new().denoise(&image, &guides, settings.threads), filename, format);
+    } else {
+        save(&image, filename, format);
+    }
+    // anything else renders the whole image, leaving an earlier checkpoint stale
+    if !progressively {
+        let _ = std::fs::remove_file(checkpoint_file);
+    }
+}
+
+fn run_diff(matches: &clap::ArgMatches) {
+    let load = |name| {
+        let filename = matches.value_of(name).unwrap();
+        canvas::Canvas::load_ppm(filename).unwrap_or_else(|e| {
+            eprintln!("Unable to read {}: {}", filename, e);
+            process::exit(2);
+        })
+    };
+    let expected = load("expected");
+    let actual = load("actual");
+    let tolerance = matches.value_of("tolerance").unwrap().parse::<f64>().unwrap_or_else(|_| {
+        eprintln!("Tolerance must be a number");
+        process::exit(2);
+    });
+
+    let (stats, heatmap) = diff::diff(&expected, &actual, tolerance).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    println!("max error:  {:.6} {:.6} {:.6}", stats.max_error.r, stats.max_error.g, stats.max_error.b);
+    println!("mean error: {:.6} {:.6} {:.6}", stats.mean_error.r, stats.mean_error.g, stats.mean_error.b);
+    println!("psnr:       {:.2} dB", stats.psnr);
+    println!("over tolerance ({}): {} of {} pixels", tolerance, stats.over_tolerance, stats.pixels);
+
+    if let Some(output) = matches.value_of("output") {
+        heatmap.save_ppm(output.to_string());
+    }
+    if !stats.passed() {
+        process::exit(1);
+    }
+}
+
+fn run_merge(matches: &clap::ArgMatches) {
+    let mut parts = vec!();
+    let mut size = None;
+    for filename in matches.values_of("tiles").unwrap() {
+        let (x, y, width, height) = tiles::tile_position(filename).unwrap_or_else(|| {
+            eprintln!("{} isn't named after where it goes, as in shot_tile_0_540_1920x1080.png", filename);
+            process::exit(2);
+        });
+        // every tile has to be of the same image
+        if let Some((w, h)) = size {
+            if (w, h) != (width, height) {
+                eprintln!("{} is part of a {}x{} image, not {}x{} as the tiles before it", filename, width, height, w, h);
+                process::exit(1);
+            }
+        }
+        size = Some((width, height));
+        let part = output::load(filename).unwrap_or_else(|e| {
+            eprintln!("Unable to read {}: {}", filename, e);
+            process::exit(2);
+        });
+        parts.push((x, y, part));
+    }
+    let (width, height) = size.unwrap();
+    let image = tiles::merge(width, height, &parts).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    let filename = matches.value_of("output").unwrap();
+    let format = output::Format::from_filename(filename).unwrap_or(output::Format::Ppm);
+    let tone_map = output::ToneMap::from_name(matches.value_of("tone-map").unwrap()).unwrap();
+    output::save(&image, filename, format, tone_map).unwrap_or_else(|e| {
+        eprintln!("Unable to write {}: {}", filename, e);
+        process::exit(2);
+    });
 }
 
 fn main() {
     let matches = App::new("Ray Tracer")
+                           .setting(AppSettings::SubcommandsNegateReqs)
                            .arg(Arg::with_name("filename")
                                 .short("f")
                                 .long("filename")
                                 .value_name("FILE")
                                 .required(true)
                                 .takes_value(true))
+                           .arg(Arg::with_name("scene")
+                                .short("s")
+                                .long("scene")
+                                .value_name("FILE")
+                                .help("Renders the scene described in a toml file")
+                                .takes_value(true))
+                           .arg(Arg::with_name("width")
+                                .long("width")
+                                .value_name("PIXELS")
+                                .help("Overrides the scene's image width")
+                                .validator(is_positive)
+                                .takes_value(true))
+                           .arg(Arg::with_name("height")
+                                .long("height")
+                                .value_name("PIXELS")
+                                .help("Overrides the scene's image height")
+                                .validator(is_positive)
+                                .takes_value(true))
+                           .arg(Arg::with_name("samples")
+                                .long("samples")
+                                .value_name("N")
+                                .help("Samples per pixel")
+                                .validator(is_positive)
+                                .takes_value(true))
+                           .arg(Arg::with_name("pattern")
+                                .long("pattern")
+                                .help("How samples are placed within each pixel")
+                                .possible_values(&["grid", "jittered", "halton", "sobol"])
+                                .default_value("jittered")
+                                .takes_value(true))
+                           .arg(Arg::with_name("filter")
+                                .long("filter")
+                                .help("Reconstruction filter used to combine samples")
+                                .possible_values(&["box", "tent", "gaussian", "mitchell"])
+                                .default_value("box")
+                                .takes_value(true))
+                           .arg(Arg::with_name("integrator")
+                                .long("integrator")
+                                .help("Whitted ray tracing, or path tracing for indirect light")
+                                .possible_values(&["whitted", "path"])
+                                .default_value("whitted")
+                                .takes_value(true))
+                           .arg(Arg::with_name("adaptive")
+                                .long("adaptive")
+                                .help("Supersamples only pixels that differ from their neighbours, instead of every pixel")
+                                .conflicts_with("samples"))
+                           .arg(Arg::with_name("adaptive-threshold")
+                                .long("adaptive-threshold")
+                                .value_name("CONTRAST")
+                                .help("Colour difference that triggers refinement [default: 0.1]")
+                                .validator(is_fraction)
+                                .takes_value(true))
+                           .arg(Arg::with_name("adaptive-levels")
+                                .long("adaptive-levels")
+                                .value_name("N")
+                                .help("How many times a pixel may be split into quarters [default: 3]")
+                                .validator(is_count)
+                                .takes_value(true))
+                           .arg(Arg::with_name("adaptive-debug")
+                                .long("adaptive-debug")
+                                .value_name("FILE")
+                                .help("Writes an image showing where adaptive refinement happened")
+                                .requires("adaptive")
+                                .takes_value(true))
+                           .arg(Arg::with_name("time-limit")
+                                .long("time-limit")
+                                .value_name("SECONDS")
+                                .help("Renders progressively, adding samples until this long has passed or --samples are taken [default samples: 1024]")
+                                .validator(is_fraction)
+                                .conflicts_with("adaptive")
+                                .takes_value(true))
+                           .arg(Arg::with_name("noise-threshold")
+                                .long("noise-threshold")
+                                .value_name("ERROR")
+                                .help("Renders progressively, leaving pixels once their brightness's standard error is under this fraction of it")
+                                .validator(is_fraction)
+                                .conflicts_with("adaptive")
+                                .takes_value(true))
+                           .arg(Arg::with_name("snapshot-every")
+                                .long("snapshot-every")
+                                .value_name("SECONDS")
+                                .help("Renders progressively, writing the image so far to FILE with .snapshot before its extension this often")
+                                .validator(is_fraction)
+                                .conflicts_with("adaptive")
+                                .takes_value(true))
+                           .arg(Arg::with_name("checkpoint-every")
+                                .long("checkpoint-every")
+                                .value_name("SECONDS")
+                                .help("Renders progressively, saving the samples so far to FILE.checkpoint this often, until the image is finished")
+                                .validator(is_fraction)
+                                .conflicts_with("adaptive")
+                                .takes_value(true))
+                           .arg(Arg::with_name("resume")
+                                .long("resume")
+                                .help("Carries on from FILE.checkpoint if it's there, with the same settings, giving the image an uninterrupted render would")
+                                .conflicts_with("adaptive"))
+                           .arg(Arg::with_name("denoise")
+                                .long("denoise")
+                                .help("Smooths away noise, guided by the albedo, normal and depth of what each pixel sees"))
+                           .arg(Arg::with_name("max-depth")
+                                .long("max-depth")
+                                .value_name("N")
+                                .help("Maximum number of times a ray may bounce")
+                                .validator(is_count)
+                                .takes_value(true))
+                           .arg(Arg::with_name("threads")
+                                .short("j")
+                                .long("threads")
+                                .value_name("N")
+                                .help("Number of render threads, defaults to one per core")
+                                .validator(is_positive)
+                                .takes_value(true))
+                           .arg(Arg::with_name("format")
+                                .long("format")
+                                .help("Output format, guessed from the file extension by default")
+                                .possible_values(&["ppm", "pfm", "png"])
+                                .takes_value(true))
+                           .arg(Arg::with_name("tone-map")
+                                .long("tone-map")
+                                .help("How colours brighter than white are brought into range")
+                                .possible_values(&["clamp", "reinhard", "aces"])
+                                .default_value("clamp")
+                                .takes_value(true))
+                           .arg(Arg::with_name("aov")
+                                .long("aov")
+                                .value_name("NAME=FILE")
+                                .help("Also writes what each pixel first sees to a file, pfm unless its extension says otherwise: depth, normal, albedo, position, object-id or material-id")
+                                .validator(is_aov)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .takes_value(true))
+                           .arg(Arg::with_name("frames")
+                                .long("frames")
+                                .value_name("START..END")
+                                .help("Renders these frames of the scene's animation, numbering each file after its frame and skipping those already written without a checkpoint")
+                                .validator(is_frames)
+                                .takes_value(true))
+                           .arg(Arg::with_name("crop")
+                                .long("crop")
+                                .value_name("X0,Y0,X1,Y1")
+                                .help("Renders only the pixels from X0,Y0 up to X1,Y1, writing them to FILE with _tile_X0_Y0_WxH added to its name, W and H being the whole image's size")
+                                .validator(is_crop)
+                                .takes_value(true))
+                           .arg(Arg::with_name("tile")
+                                .long("tile")
+                                .value_name("I/N")
+                                .help("Renders the I'th of N bands of rows, as --crop does, for merging with the others")
+                                .validator(is_tile)
+                                .conflicts_with("crop")
+                                .takes_value(true))
+                           .arg(Arg::with_name("seed")
+                                .long("seed")
+                                .help("Seed for random sampling")
+                                .validator(is_seed)
+                                .takes_value(true))
+                           .subcommand(SubCommand::with_name("diff")
+                                .about("Compares two ppm images, exiting non-zero if any pixel is over tolerance")
+                                .arg(Arg::with_name("expected")
+                                     .required(true)
+                                     .index(1))
+                                .arg(Arg::with_name("actual")
+                                     .required(true)
+                                     .index(2))
+                                .arg(Arg::with_name("tolerance")
+                                     .short("t")
+                                     .long("tolerance")
+                                     .default_value("0.01")
+                                     .takes_value(true))
+                                .arg(Arg::with_name("output")
+                                     .short("o")
+                                     .long("output")
+                                     .value_name("FILE")
+                                     .help("Writes a heat map of the differences")
+                                     .takes_value(true)))
+                           .subcommand(SubCommand::with_name("merge")
+                                .about("Puts together the tiles of an image rendered with --tile or --crop")
+                                .arg(Arg::with_name("output")
+                                     .required(true)
+                                     .index(1))
+                                .arg(Arg::with_name("tiles")
+                                     .required(true)
+                                     .multiple(true)
+                                     .index(2))
+                                .arg(Arg::with_name("tone-map")
+                                     .long("tone-map")
+                                     .help("How colours brighter than white are brought into range, for tiles saved as pfm")
+                                     .possible_values(&["clamp", "reinhard", "aces"])
+                                     .default_value("clamp")
+                                     .takes_value(true)))
                            .get_matches();
-    let filename = matches.value_of("filename").unwrap();
-    run(filename, 1000);    
+    if let Some(matches) = matches.subcommand_matches("diff") {
+        run_diff(matches);
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("merge") {
+        run_merge(matches);
+        return;
+    }
+    let load = |frame: Option<usize>| match matches.value_of("scene") {
+        Some(file) => match frame {
+            Some(frame) => scene::load_frame(file, frame as f64),
+            None => scene::load(file),
+        }.unwrap_or_else(|e| {
+            eprintln!("{}: {}", file, e);
+            process::exit(2);
+        }),
+        None => default_scene(),
+    };
+    // the scene as written is loaded first in any case, so that mistakes in it
+    // are found at their line
+    let scene = load(None);
+    let (start, end) = match matches.value_of("frames") {
+        Some(frames) => frame_range(frames).unwrap(),
+        None => return run(&matches, scene, None),
+    };
+    let mut camera = scene.camera.clone();
+    resize(&matches, &mut camera);
+    let region = region(&matches, &camera);
+    for frame in start..=end {
+        let filename = output_name(matches.value_of("filename").unwrap(), Some(frame), region, (camera.hsize, camera.vsize));
+        // frames stopped short by a time limit are left with a checkpoint,
+        // and aren't finished
+        let checkpoint_file = format!("{}.checkpoint", filename);
+        if std::path::Path::new(&filename).exists() && !std::path::Path::new(&checkpoint_file).exists() {
+            eprintln!("Skipping frame {}, {} is already there", frame, filename);
+            continue;
+        }
+        run(&matches, load(Some(frame)), Some(frame));
+    }
 }
@@ -1,145 +1,304 @@
-use cgmath::InnerSpace;
+use std::sync::Arc;
 
 use super::scene_object::SceneObject;
 use super::scene_object::Material;
 use super::ray;
+use super::transformation::{Motion, Transformation, Vector};
 
+#[derive(Debug, Clone)]
 pub struct Triangle {
-    vertices: [cgmath::Vector3<f64>; 3],
-    normal: cgmath::Vector3<f64>,
-    e1: cgmath::Vector3<f64>,
-    e2: cgmath::Vector3<f64>,
+    vertices: [Vector; 3],
+    // texture coordinates at each vertex, from the obj's vt lines
+    uvs: Option<[(f64, f64); 3]>,
+    normal: Vector,
+    e1: Vector,
+    e2: Vector,
+    pub material: Material,
+    pub trans: Transformation,
+    // when set, the object moves and trans is ignored
+    pub motion: Option<Arc<Motion>>,
 }
 
 impl Triangle {
-    pub fn new(vertices: [cgmath::Vector3<f64>; 3]) -> Triangle {
-        Triangle { vertices: vertices, 
-                normal: 0, 
-                e1: vertices[2] - vertices[0],
-                e2: vertices[3] - vertices[0]
+    pub fn new(vertices: [Vector; 3], uvs: Option<[(f64, f64); 3]>) -> Triangle {
+        let e1 = vertices[1] - vertices[0];
+        let e2 = vertices[2] - vertices[0];
+        Triangle {
+            vertices,
+            uvs,
+            normal: e2.cross(&e1).normalize(),
+            e1,
+            e2,
+            material: Material::new(),
+            trans: Transformation::new(),
+            motion: None,
+        }
     }
-}
 
-/*#[derive(Debug, Clone, Copy)]
-pub struct Sphere {
-    pub material: Material,
+    // the weights of the second and third vertices at p
+    fn barycentric(&self, p: Vector) -> (f64, f64) {
+        let d = p - self.vertices[0];
+        let (d00, d01, d11) = (self.e1.dot(&self.e1), self.e1.dot(&self.e2), self.e2.dot(&self.e2));
+        let (d20, d21) = (d.dot(&self.e1), d.dot(&self.e2));
+        let denom = d00 * d11 - d01 * d01;
+        ((d11 * d20 - d01 * d21) / denom, (d00 * d21 - d01 * d20) / denom)
+    }
 }
 
-impl SceneObject for Sphere {
-    fn intersect(&self, r: ray::Ray) -> Vec<ray::Intersection> {
-
-        let sphere_to_ray = r.origin - cgmath::vec3(0.0, 0.0, 0.0);
-        let a = cgmath::dot(r.dir, r.dir);
-        let b = 2.0 * cgmath::dot(r.dir, sphere_to_ray);
-        let c = cgmath::dot(sphere_to_ray, sphere_to_ray) - 1.0;
-
-        let discriminant =  (b * b) - (4.0 * a * c);
-
-        let mut hits = vec!();
-
-        if discriminant >= 0.0 { 
-            let t1 = ray::Intersection {
-                t: (-b - discriminant.sqrt()) / (2.0 * a),
-                object: Box::new(*self),
-            };
-            let t2 = ray::Intersection { 
-                t: (-b + discriminant.sqrt()) / (2.0 * a),
-                object: Box::new(*self),
-            };
-            if t1.t < t2.t { 
-                hits.push(t1);
-                hits.push(t2);
-            } else {
-                hits.push(t2);
-                hits.push(t1);
-            }
+impl SceneObject for Triangle {
+    // Möller-Trumbore
+    fn intersect(&self, r: ray::Ray) -> Vec<ray::Intersection<'_>> {
+        let inv = self.transformation_at(r.time).inverse();
+        let r = ray::Ray::new(inv.point(r.origin), inv.direction(r.dir));
+        let dir_cross_e2 = r.dir.cross(&self.e2);
+        let det = self.e1.dot(&dir_cross_e2);
+        // parallel to the triangle, relative to its size so small ones still
+        // get hit
+        if det.abs() < self.e1.norm() * self.e2.norm() * r.dir.norm() * 1e-12 {
+            return vec!();
+        }
+        let f = 1.0 / det;
+        let p1_to_origin = r.origin - self.vertices[0];
+        let u = f * p1_to_origin.dot(&dir_cross_e2);
+        if !(0.0..=1.0).contains(&u) {
+            return vec!();
+        }
+        let origin_cross_e1 = p1_to_origin.cross(&self.e1);
+        let v = f * r.dir.dot(&origin_cross_e1);
+        if v < 0.0 || u + v > 1.0 {
+            return vec!();
+        }
+        vec!(ray::Intersection { t: f * self.e2.dot(&origin_cross_e1), object: self })
+    }
+
+    fn local_normal(&self, _p: Vector) -> Vector {
+        self.normal
+    }
+
+    // solved from how the texture coordinates change along the edges, or just
+    // the edges when there are none
+    fn local_tangents(&self, _p: Vector) -> (Vector, Vector) {
+        let uvs = match self.uvs {
+            Some(uvs) => uvs,
+            None => return (self.e1, self.e2),
+        };
+        let (du1, dv1) = (uvs[1].0 - uvs[0].0, uvs[1].1 - uvs[0].1);
+        let (du2, dv2) = (uvs[2].0 - uvs[0].0, uvs[2].1 - uvs[0].1);
+        let det = du1 * dv2 - du2 * dv1;
+        if det.abs() < 1e-12 {
+            return (self.e1, self.e2);
+        }
+        ((self.e1 * dv2 - self.e2 * dv1) * (1.0 / det), (self.e2 * du1 - self.e1 * du2) * (1.0 / det))
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn transformation(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn motion(&self) -> Option<&Motion> {
+        self.motion.as_deref()
+    }
+
+    // interpolated from the vertices' texture coordinates, or the barycentric
+    // coordinates if there are none
+    fn uv(&self, p: Vector) -> (f64, f64) {
+        let (b1, b2) = self.barycentric(p);
+        match self.uvs {
+            Some(uvs) => {
+                let b0 = 1.0 - b1 - b2;
+                (b0 * uvs[0].0 + b1 * uvs[1].0 + b2 * uvs[2].0, b0 * uvs[0].1 + b1 * uvs[1].1 + b2 * uvs[2].1)
+            },
+            None => (b1, b2),
         }
-        hits
     }
 
-    fn normal(&self, p: cgmath::Vector3<f64>) -> cgmath::Vector3<f64> {
-        (p - cgmath::vec3(0.0, 0.0, 0.0)).normalize()
+    fn sample_surface(&self, u: f64, v: f64, time: f64) -> Option<Vector> {
+        let s = u.sqrt();
+        Some(self.transformation_at(time).point(self.vertices[0] + self.e1 * (s * (1.0 - v)) + self.e2 * (s * v)))
+    }
+
+    // the transformation is affine, so points stay evenly spread
+    fn surface_pdf(&self, _p: Vector, time: f64) -> f64 {
+        let trans = self.transformation_at(time);
+        2.0 / trans.direction(self.e1).cross(&trans.direction(self.e2)).norm()
     }
 }
 
-impl Sphere {
-    pub fn new() -> Sphere {
-        let m = Material::new();
-        Sphere {material: m}
+// Reads the vertices (v), texture coordinates (vt) and faces (f) of a Wavefront
+// obj file, splitting polygons into fans of triangles. Anything else is ignored.
+// Errors give the line they were found on.
+pub fn parse_obj(source: &str) -> Result<Vec<Triangle>, String> {
+    let mut vertices = vec!();
+    let mut uvs = vec!();
+    let mut triangles = vec!();
+
+    for (n, line) in source.lines().enumerate() {
+        let error = |message: String| Err(format!("line {}: {}", n + 1, message));
+        let mut tokens = line.split('#').next().unwrap_or("").split_whitespace();
+        let kind = match tokens.next() {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+        let numbers = || -> Result<Vec<f64>, String> {
+            args.iter().map(|a| a.parse().map_err(|_| format!("`{}` is not a number", a))).collect()
+        };
+        match kind {
+            "v" => match numbers() {
+                Ok(ref v) if v.len() >= 3 => vertices.push(Vector::new(v[0], v[1], v[2])),
+                Ok(_) => return error("vertices need three coordinates".to_string()),
+                Err(e) => return error(e),
+            },
+            "vt" => match numbers() {
+                Ok(ref t) if !t.is_empty() => uvs.push((t[0], t.get(1).cloned().unwrap_or(0.0))),
+                Ok(_) => return error("texture coordinates need at least u".to_string()),
+                Err(e) => return error(e),
+            },
+            "f" => {
+                if args.len() < 3 {
+                    return error("faces need at least three vertices".to_string());
+                }
+                // indices count from 1, or back from the latest when negative
+                let index = |i: &str, len: usize| -> Result<usize, String> {
+                    match i.parse::<i64>() {
+                        Ok(i) if i > 0 && i as usize <= len => Ok(i as usize - 1),
+                        Ok(i) if i < 0 && (-i) as usize <= len => Ok(len - (-i) as usize),
+                        _ => Err(format!("`{}` is not a valid index", i)),
+                    }
+                };
+                let mut corners = vec!();
+                for a in &args {
+                    let mut parts = a.split('/');
+                    let v = match index(parts.next().unwrap_or(""), vertices.len()) {
+                        Ok(v) => v,
+                        Err(e) => return error(e),
+                    };
+                    let t = match parts.next() {
+                        Some(t) if !t.is_empty() => match index(t, uvs.len()) {
+                            Ok(t) => Some(uvs[t]),
+                            Err(e) => return error(e),
+                        },
+                        _ => None,
+                    };
+                    corners.push((vertices[v], t));
+                }
+                for i in 1..corners.len() - 1 {
+                    let (a, b, c) = (corners[0], corners[i], corners[i + 1]);
+                    let face_uvs = match (a.1, b.1, c.1) {
+                        (Some(ta), Some(tb), Some(tc)) => Some([ta, tb, tc]),
+                        _ => None,
+                    };
+                    triangles.push(Triangle::new([a.0, b.0, c.0], face_uvs));
+                }
+            },
+            _ => {},
+        }
     }
+    Ok(triangles)
 }
 
-mod sphere_tests {
+#[cfg(test)]
+mod mesh_tests {
     use super::*;
-    #[test]
-    fn intersection_tangent() {
-        let r = ray::Ray::new(cgmath::vec3(0.0, 1.0, -5.0), cgmath::vec3(0.0, 0.0, 1.0)); 
-        let s = Sphere::new();
-        let intersections = s.intersect(r);
-        assert_eq!(intersections[0].t, 5.0);
-        assert_eq!(intersections[1].t, 5.0);
- 
-        let hit = ray::get_hit(intersections).unwrap();
-        assert_eq!(hit.t, 5.0);
+
+    fn triangle() -> Triangle {
+        Triangle::new([Vector::new(0.0, 1.0, 0.0), Vector::new(-1.0, 0.0, 0.0), Vector::new(1.0, 0.0, 0.0)], None)
     }
 
     #[test]
-    fn intersection_two_points() {
-        let r = ray::Ray::new(cgmath::vec3(0.0, 0.0, -5.0), cgmath::vec3(0.0, 0.0, 1.0)); 
-        let s = Sphere::new();
-        let intersections = s.intersect(r);
-        assert_eq!(intersections[0].t, 4.0);
-        assert_eq!(intersections[1].t, 6.0);
-
-        let hit = ray::get_hit(intersections).unwrap();
-        assert_eq!(hit.t, 4.0);
+    fn construction() {
+        let t = triangle();
+        assert_eq!(t.e1, Vector::new(-1.0, -1.0, 0.0));
+        assert_eq!(t.e2, Vector::new(1.0, -1.0, 0.0));
+        assert_eq!(t.normal(Vector::new(0.0, 0.5, 0.0), 0.0), Vector::new(0.0, 0.0, -1.0));
     }
 
     #[test]
-    fn intersection_misses() {
-        let r = ray::Ray::new(cgmath::vec3(0.0, 2.0, -5.0), cgmath::vec3(0.0, 0.0, 1.0)); 
-        let s = Sphere::new();
-        let intersections = s.intersect(r);
-        assert_eq!(intersections.len(), 0);
+    fn intersections() {
+        let t = triangle();
+        let miss = |origin: Vector, dir: Vector| t.intersect(ray::Ray::new(origin, dir)).is_empty();
+        // parallel, and past each edge
+        assert!(miss(Vector::new(0.0, -1.0, -2.0), Vector::new(0.0, 1.0, 0.0)));
+        assert!(miss(Vector::new(1.0, 1.0, -2.0), Vector::new(0.0, 0.0, 1.0)));
+        assert!(miss(Vector::new(-1.0, 1.0, -2.0), Vector::new(0.0, 0.0, 1.0)));
+        assert!(miss(Vector::new(0.0, -1.0, -2.0), Vector::new(0.0, 0.0, 1.0)));
 
-        let hit = ray::get_hit(intersections);
-        assert!(hit.is_none());
+        let hits = t.intersect(ray::Ray::new(Vector::new(0.0, 0.5, -2.0), Vector::new(0.0, 0.0, 1.0)));
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].t, 2.0);
+
+        // a triangle a tenth of a millimetre across in a scene in metres
+        let small = Triangle::new([Vector::new(0.0, 1e-4, 0.0), Vector::new(-1e-4, 0.0, 0.0), Vector::new(1e-4, 0.0, 0.0)], None);
+        assert_eq!(small.intersect(ray::Ray::new(Vector::new(0.0, 5e-5, -2.0), Vector::new(0.0, 0.0, 1.0))).len(), 1);
+        assert!(small.intersect(ray::Ray::new(Vector::new(0.0, -1.0, -2.0), Vector::new(0.0, 1.0, 0.0))).is_empty());
     }
 
     #[test]
-    fn intersection_inside() {
-        let r = ray::Ray::new(cgmath::vec3(0.0, 0.0, 0.0), cgmath::vec3(0.0, 0.0, 1.0)); 
-        let s = Sphere::new();
-        let intersections = s.intersect(r);
-        assert_eq!(intersections[0].t, -1.0);
-        assert_eq!(intersections[1].t, 1.0);
-
-        let hit = ray::get_hit(intersections).unwrap();
-        assert_eq!(hit.t, 1.0);
+    fn texture_coordinates() {
+        let uvs = Some([(0.5, 1.0), (0.0, 0.0), (1.0, 0.0)]);
+        let t = Triangle::new(triangle().vertices, uvs);
+        let close = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).abs() < 1e-10 && (a.1 - b.1).abs() < 1e-10;
+        assert!(close(t.uv(Vector::new(0.0, 1.0, 0.0)), (0.5, 1.0)));
+        assert!(close(t.uv(Vector::new(-1.0, 0.0, 0.0)), (0.0, 0.0)));
+        assert!(close(t.uv(Vector::new(0.0, 0.5, 0.0)), (0.5, 0.5)));
+        // without vt the barycentric coordinates are used
+        assert!(close(triangle().uv(Vector::new(1.0, 0.0, 0.0)), (0.0, 1.0)));
     }
 
     #[test]
-    fn intersection_behind() {
-        let r = ray::Ray::new(cgmath::vec3(0.0, 0.0, 5.0), cgmath::vec3(0.0, 0.0, 1.0)); 
-        let s = Sphere::new();
-        let intersections = s.intersect(r);
-        assert_eq!(intersections[0].t, -6.0);
-        assert_eq!(intersections[1].t, -4.0);
+    fn tangents() {
+        let uvs = Some([(0.5, 1.0), (0.0, 0.0), (1.0, 0.0)]);
+        let t = Triangle::new(triangle().vertices, uvs);
+        assert_eq!(t.local_tangents(Vector::new(0.0, 0.5, 0.0)), (Vector::new(2.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0)));
+        // mirrored texture coordinates flip the tangent
+        let mirrored = Triangle::new(triangle().vertices, Some([(0.5, 1.0), (1.0, 0.0), (0.0, 0.0)]));
+        assert_eq!(mirrored.local_tangents(Vector::new(0.0, 0.5, 0.0)).0, Vector::new(-2.0, 0.0, 0.0));
+    }
 
-        let hit = ray::get_hit(intersections);
-        assert!(hit.is_none());
+    #[test]
+    fn surface_sampling() {
+        let mut t = triangle();
+        t.trans = Transformation::new().scale(2.0, 2.0, 2.0);
+        // the corners, and everything between lies inside the triangle
+        assert_eq!(t.sample_surface(0.0, 0.0, 0.0), Some(Vector::new(0.0, 2.0, 0.0)));
+        assert_eq!(t.sample_surface(1.0, 0.0, 0.0), Some(Vector::new(-2.0, 0.0, 0.0)));
+        assert_eq!(t.sample_surface(1.0, 1.0, 0.0), Some(Vector::new(2.0, 0.0, 0.0)));
+        let p = t.trans.inverse().point(t.sample_surface(0.4, 0.3, 0.0).unwrap());
+        let (b1, b2) = t.barycentric(p);
+        assert!(b1 > 0.0 && b2 > 0.0 && b1 + b2 < 1.0);
+        assert_eq!(t.surface_pdf(p, 0.0), 0.25);
     }
 
     #[test]
-    fn normals() {
-        let s = Sphere::new();
-        let s3o3 = (3.0 as f64).sqrt() / 3.0;
-        assert_eq!(s.normal(cgmath::vec3(1.0, 0.0, 0.0)), cgmath::vec3(1.0, 0.0, 0.0));
-        assert_eq!(s.normal(cgmath::vec3(0.0, 1.0, 0.0)), cgmath::vec3(0.0, 1.0, 0.0));
-        assert_eq!(s.normal(cgmath::vec3(0.0, 0.0, 1.0)), cgmath::vec3(0.0, 0.0, 1.0));
-        assert_eq!(s.normal(cgmath::vec3(s3o3, s3o3, s3o3)), cgmath::vec3(s3o3, s3o3, s3o3));
+    fn obj_files() {
+        let obj = "
+# a square made of one quad with texture coordinates and one bare triangle
+v -1 1 0
+v -1 0 0
+v 1 0 0
+v 1 1 0
+vt 0 1
+vt 0 0
+vt 1 0
+vt 1 1
+vn 0 0 -1
+g square
+f 1/1/1 2/2/1 3/3/1 4/4/1
+f -4 -2 -1
+";
+        let triangles = parse_obj(obj).unwrap();
+        assert_eq!(triangles.len(), 3);
+        assert_eq!(triangles[1].vertices, [Vector::new(-1.0, 1.0, 0.0), Vector::new(1.0, 0.0, 0.0), Vector::new(1.0, 1.0, 0.0)]);
+        assert_eq!(triangles[1].uvs, Some([(0.0, 1.0), (1.0, 0.0), (1.0, 1.0)]));
+        assert_eq!(triangles[2].uvs, None);
+        assert_eq!(triangles[2].vertices[2], Vector::new(1.0, 1.0, 0.0));
 
-        assert_eq!(s.normal(cgmath::vec3(s3o3, s3o3, s3o3)), cgmath::vec3(s3o3, s3o3, s3o3).normalize());
+        assert_eq!(parse_obj("v 1 2\n").err(), Some("line 1: vertices need three coordinates".to_string()));
+        assert_eq!(parse_obj("v 1 2 3\nv 1 2 4\nv 1 1 1\nf 1 2 4\n").err(), Some("line 4: `4` is not a valid index".to_string()));
+        assert!(parse_obj("vt 0.5 x\n").is_err());
     }
 }
-*/
@@ -0,0 +1,188 @@
+use super::random::Rng;
+use super::transformation::Vector;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Basis {
+    Perlin,
+    Simplex,
+}
+
+// Gradient noise over a lattice whose gradients are picked by a permutation
+// table shuffled from the seed, so the same seed always gives the same noise.
+#[derive(Debug, Clone)]
+pub struct Noise {
+    pub basis: Basis,
+    perm: Vec<usize>,
+}
+
+// the midpoints of the edges of a cube
+const GRADIENTS: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [1.0, -1.0, 0.0], [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0], [-1.0, 0.0, 1.0], [1.0, 0.0, -1.0], [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0], [0.0, -1.0, 1.0], [0.0, 1.0, -1.0], [0.0, -1.0, -1.0],
+];
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+fn dot(g: [f64; 3], x: f64, y: f64, z: f64) -> f64 {
+    g[0] * x + g[1] * y + g[2] * z
+}
+
+impl Noise {
+    pub fn new(seed: u64, basis: Basis) -> Noise {
+        let mut perm: Vec<usize> = (0..256).collect();
+        let mut rng = Rng::new(seed);
+        for i in (1..256).rev() {
+            let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+            perm.swap(i, j);
+        }
+        Noise { basis, perm }
+    }
+
+    fn gradient(&self, x: i64, y: i64, z: i64) -> [f64; 3] {
+        let h = self.perm[(self.perm[(self.perm[(x & 255) as usize] + (y & 255) as usize) & 255] + (z & 255) as usize) & 255];
+        GRADIENTS[h % 12]
+    }
+
+    // roughly in [-1, 1], zero at every lattice point
+    pub fn perlin(&self, p: Vector) -> f64 {
+        let (fx, fy, fz) = (p.x().floor(), p.y().floor(), p.z().floor());
+        let (x, y, z) = (p.x() - fx, p.y() - fy, p.z() - fz);
+        let (ix, iy, iz) = (fx as i64, fy as i64, fz as i64);
+        let corner = |dx: i64, dy: i64, dz: i64| {
+            dot(self.gradient(ix + dx, iy + dy, iz + dz), x - dx as f64, y - dy as f64, z - dz as f64)
+        };
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+        lerp(w,
+             lerp(v, lerp(u, corner(0, 0, 0), corner(1, 0, 0)), lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+             lerp(v, lerp(u, corner(0, 0, 1), corner(1, 0, 1)), lerp(u, corner(0, 1, 1), corner(1, 1, 1))))
+    }
+
+    // roughly in [-1, 1]; sums the four corners of the simplex containing p
+    pub fn simplex(&self, p: Vector) -> f64 {
+        let (f3, g3) = (1.0 / 3.0, 1.0 / 6.0);
+        let s = (p.x() + p.y() + p.z()) * f3;
+        let (i, j, k) = ((p.x() + s).floor(), (p.y() + s).floor(), (p.z() + s).floor());
+        let t = (i + j + k) * g3;
+        let (x0, y0, z0) = (p.x() - (i - t), p.y() - (j - t), p.z() - (k - t));
+
+        // which of the six simplices in the skewed cube p is in
+        let (i1, j1, k1, i2, j2, k2) = if x0 >= y0 {
+            if y0 >= z0 { (1, 0, 0, 1, 1, 0) } else if x0 >= z0 { (1, 0, 0, 1, 0, 1) } else { (0, 0, 1, 1, 0, 1) }
+        } else if y0 < z0 {
+            (0, 0, 1, 0, 1, 1)
+        } else if x0 < z0 {
+            (0, 1, 0, 0, 1, 1)
+        } else {
+            (0, 1, 0, 1, 1, 0)
+        };
+
+        let (i, j, k) = (i as i64, j as i64, k as i64);
+        let corners = [
+            (0, 0, 0, 0.0),
+            (i1, j1, k1, g3),
+            (i2, j2, k2, 2.0 * g3),
+            (1, 1, 1, 3.0 * g3),
+        ];
+        let total: f64 = corners.iter().map(|&(di, dj, dk, offset)| {
+            let x = x0 - di as f64 + offset;
+            let y = y0 - dj as f64 + offset;
+            let z = z0 - dk as f64 + offset;
+            let falloff = 0.6 - x * x - y * y - z * z;
+            if falloff <= 0.0 {
+                return 0.0;
+            }
+            let falloff = falloff * falloff;
+            falloff * falloff * dot(self.gradient(i + di, j + dj, k + dk), x, y, z)
+        }).sum();
+        32.0 * total
+    }
+
+    pub fn at(&self, p: Vector) -> f64 {
+        match self.basis {
+            Basis::Perlin => self.perlin(p),
+            Basis::Simplex => self.simplex(p),
+        }
+    }
+
+    // octaves of noise, each at twice the frequency and half the amplitude of
+    // the last, normalised back to roughly [-1, 1]
+    pub fn fbm(&self, p: Vector, octaves: usize) -> f64 {
+        self.octaves(p, octaves, |n| n)
+    }
+
+    // like fbm but summing the magnitude of each octave, in [0, 1]
+    pub fn turbulence(&self, p: Vector, octaves: usize) -> f64 {
+        self.octaves(p, octaves, f64::abs)
+    }
+
+    fn octaves<F: Fn(f64) -> f64>(&self, p: Vector, octaves: usize, f: F) -> f64 {
+        let (mut total, mut amplitude, mut frequency, mut norm) = (0.0, 1.0, 1.0, 0.0);
+        for _ in 0..octaves.max(1) {
+            total += amplitude * f(self.at(p * frequency));
+            norm += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        total / norm
+    }
+}
+
+#[cfg(test)]
+mod noise_tests {
+    use super::*;
+
+    fn grid() -> Vec<Vector> {
+        (0..1000).map(|i| {
+            let i = i as f64;
+            Vector::new(i * 0.173, i * 0.091 - 20.0, i * 0.057 + 3.3)
+        }).collect()
+    }
+
+    #[test]
+    fn seeded() {
+        let p = Vector::new(1.3, -2.7, 0.4);
+        for &basis in &[Basis::Perlin, Basis::Simplex] {
+            assert_eq!(Noise::new(4, basis).at(p), Noise::new(4, basis).at(p));
+            assert!(Noise::new(4, basis).at(p) != Noise::new(5, basis).at(p));
+        }
+    }
+
+    #[test]
+    fn perlin_is_zero_on_the_lattice() {
+        let n = Noise::new(0, Basis::Perlin);
+        assert_eq!(n.perlin(Vector::new(3.0, -2.0, 7.0)), 0.0);
+        assert!(n.perlin(Vector::new(3.5, -2.5, 7.5)) != 0.0);
+    }
+
+    #[test]
+    fn ranges() {
+        for &basis in &[Basis::Perlin, Basis::Simplex] {
+            let n = Noise::new(1, basis);
+            let values: Vec<f64> = grid().into_iter().map(|p| n.at(p)).collect();
+            assert!(values.iter().all(|v| v.abs() <= 1.0));
+            // uses a good part of its range and is centred on zero
+            assert!(values.iter().any(|&v| v > 0.3) && values.iter().any(|&v| v < -0.3));
+            assert!((values.iter().sum::<f64>() / values.len() as f64).abs() < 0.1);
+            for p in grid() {
+                assert!(n.fbm(p, 4).abs() <= 1.0);
+                let t = n.turbulence(p, 4);
+                assert!((0.0..=1.0).contains(&t));
+            }
+        }
+    }
+
+    #[test]
+    fn continuous() {
+        let n = Noise::new(2, Basis::Simplex);
+        let p = Vector::new(0.31, 0.42, 0.53);
+        let q = Vector::new(0.3101, 0.42, 0.53);
+        assert!((n.at(p) - n.at(q)).abs() < 0.01);
+    }
+}
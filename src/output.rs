@@ -0,0 +1,390 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use png;
+
+use super::canvas::{Canvas, Colour};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Ppm,
+    Pfm,
+    Png,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "ppm" => Some(Format::Ppm),
+            "pfm" => Some(Format::Pfm),
+            "png" => Some(Format::Png),
+            _ => None,
+        }
+    }
+
+    pub fn from_filename(filename: &str) -> Option<Format> {
+        Path::new(filename).extension()
+                           .and_then(|e| e.to_str())
+                           .and_then(|e| Format::from_name(&e.to_lowercase()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    Clamp,
+    Reinhard,
+    Aces,
+}
+
+impl ToneMap {
+    pub fn from_name(name: &str) -> Option<ToneMap> {
+        match name {
+            "clamp" => Some(ToneMap::Clamp),
+            "reinhard" => Some(ToneMap::Reinhard),
+            "aces" => Some(ToneMap::Aces),
+            _ => None,
+        }
+    }
+
+    fn channel(&self, c: f64) -> f64 {
+        let c = c.max(0.0);
+        let mapped = match *self {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => c / (1.0 + c),
+            // Narkowicz's fit of the ACES filmic curve
+            ToneMap::Aces => (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14),
+        };
+        mapped.min(1.0)
+    }
+
+    pub fn apply(&self, c: Colour) -> Colour {
+        Colour::new(self.channel(c.r), self.channel(c.g), self.channel(c.b))
+    }
+}
+
+fn tone_mapped(canvas: &Canvas, tone_map: ToneMap) -> Canvas {
+    let mut out = Canvas::new(canvas.width(), canvas.height());
+    for y in 0..canvas.height() {
+        for x in 0..canvas.width() {
+            out.write(x, y, tone_map.apply(canvas.read(x, y)));
+        }
+    }
+    out
+}
+
+// pfm stores linear floats bottom row first, so tone mapping is not applied
+pub fn pfm_bytes(canvas: &Canvas) -> Vec<u8> {
+    let mut data = format!("PF\n{} {}\n-1.0\n", canvas.width(), canvas.height()).into_bytes();
+    for y in (0..canvas.height()).rev() {
+        for x in 0..canvas.width() {
+            let c = canvas.read(x, y);
+            for v in &[c.r, c.g, c.b] {
+                data.extend_from_slice(&(*v as f32).to_le_bytes());
+            }
+        }
+    }
+    data
+}
+
+fn save_png(canvas: &Canvas, filename: &str) -> io::Result<()> {
+    let file = BufWriter::new(File::create(filename)?);
+    let mut encoder = png::Encoder::new(file, canvas.width() as u32, canvas.height() as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+
+    let mut data = Vec::with_capacity(canvas.width() * canvas.height() * 3);
+    for y in 0..canvas.height() {
+        for x in 0..canvas.width() {
+            let c = canvas.read(x, y);
+            for v in &[c.r, c.g, c.b] {
+                data.push((v.clamp(0.0, 1.0) * 255.0).round() as u8);
+            }
+        }
+    }
+    writer.write_image_data(&data).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+pub fn save(canvas: &Canvas, filename: &str, format: Format, tone_map: ToneMap) -> io::Result<()> {
+    match format {
+        Format::Ppm => {
+            let image = tone_mapped(canvas, tone_map);
+            let mut file = BufWriter::new(File::create(filename)?);
+            file.write_all(image.ppm_header().as_bytes())?;
+            file.write_all(image.ppm_data().as_bytes())?;
+            file.flush()
+        },
+        Format::Pfm => fs::write(filename, pfm_bytes(canvas)),
+        Format::Png => save_png(&tone_mapped(canvas, tone_map), filename),
+    }
+}
+
+fn load_png(filename: &str) -> io::Result<Canvas> {
+    let mut decoder = png::Decoder::new(File::open(filename)?);
+    // palettes are expanded and 16 bit channels cut down to 8
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(io::Error::other)?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(io::Error::other)?;
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        _ => 4,
+    };
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut canvas = Canvas::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = &data[y * info.line_size + x * channels..];
+            let v = |i: usize| pixel[i] as f64 / 255.0;
+            // alpha is dropped
+            let c = if channels < 3 { Colour::new(v(0), v(0), v(0)) } else { Colour::new(v(0), v(1), v(2)) };
+            canvas.write(x, y, c);
+        }
+    }
+    Ok(canvas)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// A colour or greyscale pfm, whose scale's sign gives the byte order. Rows are
+// stored bottom first.
+pub fn pfm_from_bytes(data: &[u8]) -> io::Result<Canvas> {
+    // the header is three whitespace separated tokens, with a single
+    // whitespace character before the pixels
+    let mut tokens = vec!();
+    let mut pos = 0;
+    while tokens.len() < 4 {
+        while pos < data.len() && data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(invalid("truncated pfm header"));
+        }
+        tokens.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+    }
+    let channels = match tokens[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid("not a pfm")),
+    };
+    let number = |t: &str| t.parse::<f64>().map_err(|_| invalid("malformed pfm header"));
+    let (width, height, scale) = (number(&tokens[1])? as usize, number(&tokens[2])? as usize, number(&tokens[3])?);
+    let pixels = &data[(pos + 1).min(data.len())..];
+    if pixels.len() < width * height * channels * 4 {
+        return Err(invalid("truncated pfm"));
+    }
+
+    let mut canvas = Canvas::new(width, height);
+    let value = |i: usize| {
+        let bytes = [pixels[i * 4], pixels[i * 4 + 1], pixels[i * 4 + 2], pixels[i * 4 + 3]];
+        (if scale < 0.0 { f32::from_le_bytes(bytes) } else { f32::from_be_bytes(bytes) }) as f64
+    };
+    for y in 0..height {
+        for x in 0..width {
+            let i = ((height - 1 - y) * width + x) * channels;
+            let c = if channels == 1 { Colour::new(value(i), value(i), value(i)) } else { Colour::new(value(i), value(i + 1), value(i + 2)) };
+            canvas.write(x, y, c);
+        }
+    }
+    Ok(canvas)
+}
+
+// red, green and blue sharing the exponent e
+fn rgbe(r: u8, g: u8, b: u8, e: u8) -> Colour {
+    if e == 0 {
+        return Colour::new(0.0, 0.0, 0.0);
+    }
+    let f = 2f64.powi(e as i32 - 136);
+    Colour::new(r as f64 * f, g as f64 * f, b as f64 * f)
+}
+
+// A Radiance rgbe image, top row first, with scanlines either stored flat or
+// run length encoded one channel at a time.
+pub fn hdr_from_bytes(data: &[u8]) -> io::Result<Canvas> {
+    let mut lines = 0;
+    let mut pos = 0;
+    let mut line = || -> io::Result<String> {
+        let start = pos;
+        while pos < data.len() && data[pos] != b'\n' {
+            pos += 1;
+        }
+        if pos == data.len() {
+            return Err(invalid("truncated hdr header"));
+        }
+        pos += 1;
+        lines += 1;
+        Ok(String::from_utf8_lossy(&data[start..pos - 1]).into_owned())
+    };
+    let magic = line()?;
+    if !magic.starts_with("#?") {
+        return Err(invalid("not a Radiance hdr"));
+    }
+    loop {
+        let l = line()?;
+        if l.is_empty() {
+            break;
+        }
+        if l.starts_with("FORMAT=") && l != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid("only rgbe hdr images are supported"));
+        }
+    }
+    let size: Vec<String> = line()?.split_whitespace().map(|s| s.to_string()).collect();
+    let (height, width) = match (size.first().map(|s| s.as_str()), size.get(2).map(|s| s.as_str())) {
+        (Some("-Y"), Some("+X")) => match (size[1].parse::<usize>(), size[3].parse::<usize>()) {
+            (Ok(h), Ok(w)) => (h, w),
+            _ => return Err(invalid("malformed hdr size")),
+        },
+        _ => return Err(invalid("only top to bottom, left to right hdr images are supported")),
+    };
+
+    let mut canvas = Canvas::new(width, height);
+    let truncated = || invalid("truncated hdr");
+    // red, green, blue and exponent for each pixel of a row
+    let mut scanline = vec![0u8; width * 4];
+    for y in 0..height {
+        let rle = (8..0x8000).contains(&width) && data.len() >= pos + 4 && data[pos] == 2 && data[pos + 1] == 2 &&
+                  ((data[pos + 2] as usize) << 8 | data[pos + 3] as usize) == width;
+        if rle {
+            pos += 4;
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = *data.get(pos).ok_or_else(truncated)? as usize;
+                    pos += 1;
+                    // over 128 is a run of one value, otherwise that many values
+                    let (n, run) = if count > 128 { (count - 128, true) } else { (count, false) };
+                    if n == 0 || x + n > width {
+                        return Err(invalid("bad run length in hdr"));
+                    }
+                    for i in 0..n {
+                        scanline[(x + i) * 4 + channel] = *data.get(if run { pos } else { pos + i }).ok_or_else(truncated)?;
+                    }
+                    pos += if run { 1 } else { n };
+                    x += n;
+                }
+            }
+        } else {
+            let bytes = data.get(pos..pos + width * 4).ok_or_else(truncated)?;
+            scanline.copy_from_slice(bytes);
+            pos += width * 4;
+        }
+        for (x, p) in scanline.chunks(4).enumerate() {
+            canvas.write(x, y, rgbe(p[0], p[1], p[2], p[3]));
+        }
+    }
+    Ok(canvas)
+}
+
+// reads a png, plain ppm, pfm or Radiance hdr image, such as a texture or an
+// environment map
+pub fn load(filename: &str) -> io::Result<Canvas> {
+    let hdr = Path::new(filename).extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("hdr"));
+    match Format::from_filename(filename) {
+        Some(Format::Png) => load_png(filename),
+        Some(Format::Ppm) => Canvas::load_ppm(filename),
+        Some(Format::Pfm) => pfm_from_bytes(&fs::read(filename)?),
+        None if hdr => hdr_from_bytes(&fs::read(filename)?),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("can't read {}, expected a png, ppm, pfm or hdr", filename))),
+    }
+}
+
+#[cfg(test)]
+mod output_tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        assert_eq!(Format::from_filename("out.png"), Some(Format::Png));
+        assert_eq!(Format::from_filename("dir.d/OUT.PFM"), Some(Format::Pfm));
+        assert_eq!(Format::from_filename("out"), None);
+        assert_eq!(Format::from_name("jpg"), None);
+    }
+
+    #[test]
+    fn tone_maps() {
+        let bright = Colour::new(1.9, 0.5, -0.2);
+        assert_eq!(ToneMap::Clamp.apply(bright), Colour::new(1.0, 0.5, 0.0));
+        assert_eq!(ToneMap::Reinhard.apply(Colour::new(1.0, 3.0, 0.0)), Colour::new(0.5, 0.75, 0.0));
+        let aces = ToneMap::Aces.apply(Colour::new(0.0, 0.18, 100.0));
+        assert_eq!(aces.r, 0.0);
+        assert!(aces.g > 0.2 && aces.g < 0.3);
+        assert_eq!(aces.b, 1.0);
+    }
+
+    #[test]
+    fn pfm() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.write(1, 0, Colour::new(2.0, 0.5, 0.25));
+        let data = pfm_bytes(&canvas);
+        let header = b"PF\n2 1\n-1.0\n";
+        assert_eq!(&data[..header.len()], &header[..]);
+        assert_eq!(data.len(), header.len() + 24);
+        assert_eq!(&data[header.len() + 12..header.len() + 16], &2.0f32.to_le_bytes());
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let mut canvas = Canvas::new(3, 2);
+        canvas.write(0, 0, Colour::new(1.0, 0.0, 0.0));
+        canvas.write(2, 1, Colour::new(0.0, 0.2, 1.0));
+        let filename = std::env::temp_dir().join(format!("ray_tracer_png_{}.png", std::process::id()));
+        let filename = filename.to_str().unwrap();
+        save(&canvas, filename, Format::Png, ToneMap::Clamp).unwrap();
+        let loaded = load(filename).unwrap();
+        fs::remove_file(filename).unwrap();
+        assert_eq!((loaded.width(), loaded.height()), (3, 2));
+        assert_eq!(loaded.read(0, 0), Colour::new(1.0, 0.0, 0.0));
+        assert_eq!(loaded.read(2, 1), Colour::new(0.0, 51.0 / 255.0, 1.0));
+        assert!(load("texture.jpg").is_err());
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let mut canvas = Canvas::new(2, 3);
+        canvas.write(1, 0, Colour::new(2.0, 0.5, 0.25));
+        canvas.write(0, 2, Colour::new(0.0, 100.0, 0.0));
+        let loaded = pfm_from_bytes(&pfm_bytes(&canvas)).unwrap();
+        assert_eq!((loaded.width(), loaded.height()), (2, 3));
+        assert_eq!(loaded.read(1, 0), Colour::new(2.0, 0.5, 0.25));
+        assert_eq!(loaded.read(0, 2), Colour::new(0.0, 100.0, 0.0));
+
+        // big endian greyscale
+        let mut grey = b"Pf 1 1 1.0\n".to_vec();
+        grey.extend_from_slice(&1.5f32.to_be_bytes());
+        assert_eq!(pfm_from_bytes(&grey).unwrap().read(0, 0), Colour::new(1.5, 1.5, 1.5));
+        assert!(pfm_from_bytes(&grey[..grey.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn hdr() {
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+        // the first row stored flat, the second run length encoded
+        let mut data = header.clone();
+        for x in 0..8 {
+            data.extend_from_slice(&[128, 64, x * 16, 129]);
+        }
+        data.extend_from_slice(&[2, 2, 0, 8]);
+        for channel in &[[136u8, 128], [136, 0], [136, 32], [136, 130]] {
+            data.extend_from_slice(channel);
+        }
+        let image = hdr_from_bytes(&data).unwrap();
+        assert_eq!((image.width(), image.height()), (8, 2));
+        assert_eq!(image.read(0, 0), Colour::new(1.0, 0.5, 0.0));
+        assert_eq!(image.read(3, 0), Colour::new(1.0, 0.5, 0.375));
+        assert_eq!(image.read(5, 1), Colour::new(2.0, 0.0, 0.5));
+
+        assert!(hdr_from_bytes(&data[..data.len() - 1]).is_err());
+        assert!(hdr_from_bytes(b"P3\n1 1\n255\n").is_err());
+    }
+}
@@ -0,0 +1,452 @@
+use std::f64::consts::PI;
+
+use super::bsdf::{Bsdf, Lambert};
+use super::canvas::Colour;
+use super::light::Light;
+use super::random::Rng;
+use super::ray::{self, Intersection, Ray};
+use super::transformation::Vector;
+use super::world::{offset, World};
+
+// Monte Carlo path tracing. Lights are in the same units as light::lighting,
+// where a unit light shining straight onto a white diffuse surface makes it
+// white, which in radiometric terms means a surface receives pi times the
+// light's intensity. Area lights are the same cloud of points the Whitted
+// renderer samples, so they aren't seen directly or in mirrors, only found by
+// next event estimation and by rough bounces that happen to cross them, with
+// the two weighted against each other by multiple importance sampling.
+// Emissive objects and the environment give off their light as radiance, with
+// no factor of pi, and are found the same two ways.
+
+// bounces before russian roulette may end a path
+const ROULETTE_DEPTH: usize = 3;
+
+fn power_heuristic(a: f64, b: f64) -> f64 {
+    a * a / (a * a + b * b)
+}
+
+fn nearest(world: &World, r: Ray) -> Option<Intersection<'_>> {
+    ray::get_hit(world.intersect(r))
+}
+
+// The light from l arriving at p along r, which crosses it at t, weighted
+// against the chance of next event estimation having picked the same point.
+// pdf is the density with which the bsdf chose r.
+fn emitted(l: &Light, p: Vector, r: Ray, t: f64, pdf: f64) -> Colour {
+    let black = Colour::new(0.0, 0.0, 0.0);
+    let emitter = match l.emitter(p) {
+        Some(emitter) => emitter,
+        None => return black,
+    };
+    let cos_light = r.dir.dot(&emitter.normal()).abs();
+    if cos_light < 1e-12 {
+        return black;
+    }
+    let (_, intensity) = l.incident(p, r.position(t));
+    // the light's point density per unit area, as a density over directions
+    let light_pdf = t * t / (emitter.area() * cos_light);
+    intensity * (PI * light_pdf * power_heuristic(pdf, light_pdf))
+}
+
+// the shading point: where the path is, which way it came from, and how the
+// surface there scatters light
+struct Vertex<'a> {
+    point: Vector,
+    wo: Vector,
+    // out of the object, and turned towards wo
+    outward: Vector,
+    normal: Vector,
+    bsdf: &'a dyn Bsdf,
+    colour: Colour,
+    // the time of the ray that found it, which the path keeps to
+    time: f64,
+    // whether the path bounces on from here, and so may find lights itself
+    continues: bool,
+}
+
+impl<'a> Vertex<'a> {
+    // The weight of light reaching v from wi, found by sampling the light
+    // with density light_pdf, against the next bounce finding it too. On the
+    // last bounce light sampling is the only way it's found.
+    fn weight(&self, wi: Vector, light_pdf: f64) -> f64 {
+        if !self.continues {
+            return 1.0;
+        }
+        power_heuristic(light_pdf, self.bsdf.pdf(self.wo, wi, self.outward, self.colour))
+    }
+}
+
+// the light reaching v directly from a point on one emissive object, picked by
+// how much light it gives off
+fn emissive(world: &World, v: &Vertex, rng: &mut Rng) -> Colour {
+    let black = Colour::new(0.0, 0.0, 0.0);
+    let (object, chance) = match world.pick_emitter(rng.next_f64()) {
+        Some(picked) => picked,
+        None => return black,
+    };
+    let sample = match object.sample_surface(rng.next_f64(), rng.next_f64(), v.time) {
+        Some(sample) => sample,
+        None => return black,
+    };
+    let towards = sample - v.point;
+    let distance = towards.norm();
+    let wi = towards.normalize();
+    let light_normal = object.normal(sample, v.time);
+    let cos_light = wi.dot(&light_normal).abs();
+    let f = v.bsdf.eval(v.wo, wi, v.outward, v.colour);
+    if distance < 1e-12 || cos_light < 1e-12 || f == black {
+        return black;
+    }
+    let origin = offset(v.point, v.normal, towards);
+    if world.is_shadowed(origin, offset(sample, light_normal, -towards), v.time) {
+        return black;
+    }
+    let light_pdf = chance * object.surface_pdf(sample, v.time) * distance * distance / cos_light;
+    let weight = v.weight(wi, light_pdf);
+    f * object.material().emission * (wi.dot(&v.outward).abs() * weight / light_pdf)
+}
+
+// the light reaching v from a direction towards the environment, picked by how
+// bright the environment is that way
+fn environment(world: &World, v: &Vertex, rng: &mut Rng) -> Colour {
+    let black = Colour::new(0.0, 0.0, 0.0);
+    let (wi, light, light_pdf) = match world.environment.sample(rng.next_f64(), rng.next_f64()) {
+        Some(sample) => sample,
+        None => return black,
+    };
+    let f = v.bsdf.eval(v.wo, wi, v.outward, v.colour);
+    if f == black || world.occluded(Ray::new(offset(v.point, v.normal, wi), wi).with_time(v.time), f64::INFINITY) {
+        return black;
+    }
+    let weight = v.weight(wi, light_pdf);
+    f * light * (wi.dot(&v.outward).abs() * weight / light_pdf)
+}
+
+// next event estimation: the light reaching v directly from each light,
+// emissive object and the environment, and scattered towards wo
+fn direct(world: &World, v: &Vertex, rng: &mut Rng) -> Colour {
+    let mut total = emissive(world, v, rng) + environment(world, v, rng);
+    for l in &world.lights {
+        let towards = match l.emitter(v.point) {
+            Some(emitter) => emitter.sample(rng) - v.point,
+            None => l.incident(v.point, l.pos).0,
+        };
+        let origin = offset(v.point, v.normal, towards);
+        let f = v.bsdf.eval(v.wo, towards.normalize(), v.outward, v.colour);
+        if f == Colour::new(0.0, 0.0, 0.0) {
+            continue;
+        }
+        match l.emitter(origin) {
+            Some(emitter) => {
+                let sample = v.point + towards;
+                let (lightv, intensity) = l.incident(origin, sample);
+                let cos_light = lightv.dot(&emitter.normal()).abs();
+                if cos_light < 1e-12 || world.is_shadowed(origin, sample, v.time) {
+                    continue;
+                }
+                let distance = (sample - origin).norm();
+                let light_pdf = distance * distance / (emitter.area() * cos_light);
+                let weight = v.weight(lightv, light_pdf);
+                total = total + f * intensity * (PI * lightv.dot(&v.outward).abs() * weight);
+            },
+            None => {
+                let (lightv, intensity) = l.incident(origin, l.pos);
+                let visibility = world.visibility(origin, l, v.time, rng);
+                total = total + f * intensity * (PI * lightv.dot(&v.outward).abs() * visibility);
+            },
+        }
+    }
+    total
+}
+
+// The light arriving along r. Paths bounce at most max_depth times, and after
+// ROULETTE_DEPTH bounces are ended at random in proportion to how little they
+// could still add, with the survivors made brighter to make up for it.
+pub fn radiance(world: &World, r: Ray, max_depth: usize, rng: &mut Rng) -> Colour {
+    let mut total = Colour::new(0.0, 0.0, 0.0);
+    let mut throughput = Colour::new(1.0, 1.0, 1.0);
+    let mut r = r;
+    let mut hit = nearest(world, r);
+    let mut bounce = 0;
+    // the density with which the last rough bounce chose r, or None when r
+    // comes from the camera or a smooth surface, neither of which next event
+    // estimation can stand in for
+    let mut bsdf_pdf = None;
+    loop {
+        let h = match hit {
+            Some(h) => h,
+            None => {
+                let weight = match bsdf_pdf {
+                    Some(pdf) => power_heuristic(pdf, world.environment.pdf(r.dir)),
+                    None => 1.0,
+                };
+                total = total + throughput * world.environment.radiance(r.dir) * weight;
+                break;
+            },
+        };
+        let m = h.object.material();
+        let point = r.position(h.t);
+        let outward = h.object.normal(point, r.time);
+        if m.emission != Colour::new(0.0, 0.0, 0.0) {
+            let weight = match bsdf_pdf {
+                Some(pdf) => {
+                    let cos_light = r.dir.dot(&outward).abs().max(1e-12);
+                    let light_pdf = world.emitter_chance(h.object) * h.object.surface_pdf(point, r.time) * h.t * h.t / cos_light;
+                    power_heuristic(pdf, light_pdf)
+                },
+                None => 1.0,
+            };
+            total = total + throughput * m.emission * weight;
+        }
+        // materials without a bsdf scatter diffusely, as much as their phong
+        // diffuse term suggests
+        let (bsdf, colour): (&dyn Bsdf, Colour) = match m.bsdf {
+            Some(ref bsdf) => (&**bsdf, h.object.colour_at(point, r.time)),
+            None => (&Lambert, h.object.colour_at(point, r.time) * m.diffuse),
+        };
+        let v = Vertex {
+            point,
+            wo: -r.dir,
+            outward,
+            normal: if outward.dot(&r.dir) > 0.0 { -outward } else { outward },
+            bsdf,
+            colour,
+            time: r.time,
+            continues: bounce < max_depth,
+        };
+        total = total + throughput * direct(world, &v, rng);
+        if bounce == max_depth {
+            break;
+        }
+
+        let s = match bsdf.sample(v.wo, outward, colour, rng) {
+            Some(s) => s,
+            None => break,
+        };
+        let origin = offset(point, v.normal, s.wi);
+        r = Ray::new(origin, s.wi).with_time(r.time);
+        hit = nearest(world, r);
+        throughput = throughput * s.weight;
+        bsdf_pdf = if s.specular { None } else { Some(s.pdf) };
+        // lights are only found by chance off rough surfaces, where next event
+        // estimation could have found them too
+        if !s.specular {
+            let blocked = hit.as_ref().map_or(f64::INFINITY, |h| h.t);
+            for l in &world.lights {
+                match l.emitter(origin).and_then(|e| e.intersect(r)) {
+                    Some(t) if t < blocked => total = total + throughput * emitted(l, origin, r, t, s.pdf),
+                    _ => {},
+                }
+            }
+        }
+
+        bounce += 1;
+        if bounce >= ROULETTE_DEPTH {
+            let survive = throughput.r.max(throughput.g).max(throughput.b).min(0.95);
+            if rng.next_f64() >= survive {
+                break;
+            }
+            throughput = throughput * (1.0 / survive);
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+mod path_tests {
+    use super::*;
+    use bsdf::Conductor;
+    use scene_object::Material;
+    use canvas::Canvas;
+    use environment::{Background, EnvironmentMap};
+    use light::LightShape;
+    use mesh::Triangle;
+    use std::sync::Arc;
+    use plane::Plane;
+    use sphere::Sphere;
+    use transformation::Transformation;
+
+    fn matte(colour: Colour) -> Material {
+        let mut m = Material::new();
+        m.colour = colour;
+        m.ambient = 0.0;
+        m.diffuse = 1.0;
+        m.specular = 0.0;
+        m
+    }
+
+    fn floor() -> Plane {
+        Plane { material: matte(Colour::new(1.0, 1.0, 1.0)), trans: Transformation::new(), motion: None }
+    }
+
+    fn average(world: &World, r: Ray, n: usize) -> Colour {
+        let mut rng = Rng::new(7);
+        (0..n).fold(Colour::new(0.0, 0.0, 0.0), |t, _| t + radiance(world, r, 5, &mut rng)) * (1.0 / n as f64)
+    }
+
+    fn close(a: Colour, b: Colour, tolerance: f64) -> bool {
+        (a.r - b.r).abs() < tolerance && (a.g - b.g).abs() < tolerance && (a.b - b.b).abs() < tolerance
+    }
+
+    #[test]
+    fn point_lights_match_whitted() {
+        let mut world = World::new();
+        world.objects = vec!(Box::new(floor()));
+        world.lights = vec!(Light::new(Colour::new(1.0, 0.5, 0.25), Vector::new(-3.0, 4.0, 0.0)));
+        let r = Ray::new(Vector::new(0.0, 1.0, -1.0), Vector::new(0.0, -1.0, 1.0).normalize());
+        let whitted = world.colour_at(r, 5, &mut Rng::new(0));
+        // only differing by the shading point being nudged off the surface
+        assert!(close(radiance(&world, r, 5, &mut Rng::new(0)), whitted, 1e-6));
+        assert!(close(whitted, Colour::new(0.8, 0.4, 0.2), 1e-9));
+    }
+
+    #[test]
+    fn area_lights_converge() {
+        // light sampling and diffuse bounces both find the light, and together
+        // agree with the Whitted renderer's dense grid of light samples
+        let light = Light {
+            shape: LightShape::Rect { u: Vector::new(2.0, 0.0, 0.0), v: Vector::new(0.0, 0.0, 1.0), usteps: 64, vsteps: 64 },
+            ..Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(-0.5, 1.0, -0.5))
+        };
+        let mut world = World::new();
+        world.objects = vec!(Box::new(floor()));
+        world.lights = vec!(light);
+        let r = Ray::new(Vector::new(0.0, 0.5, -1.0), Vector::new(0.0, -0.5, 1.0).normalize());
+        let whitted = world.colour_at(r, 0, &mut Rng::new(0));
+        assert!(close(average(&world, r, 20000), whitted, 0.01));
+    }
+
+    #[test]
+    fn indirect_light() {
+        // the floor under a red ball is in its shadow, but lit by light
+        // bouncing off the ball
+        let mut ball = Sphere::new();
+        ball.material = matte(Colour::new(1.0, 0.0, 0.0));
+        ball.trans = Transformation::new().translate(0.0, 1.1, 0.0);
+        let mut world = World::new();
+        world.objects = vec!(Box::new(floor()), Box::new(ball));
+        world.lights = vec!(Light::directional(Colour::new(1.0, 1.0, 1.0), Vector::new(0.1, -1.0, 0.0)));
+        let r = Ray::new(Vector::new(0.0, 0.05, -3.0), Vector::new(0.0, -0.05, 3.0).normalize());
+        assert_eq!(world.colour_at(r, 5, &mut Rng::new(0)), Colour::new(0.0, 0.0, 0.0));
+        let bounced = average(&world, r, 2000);
+        assert!(bounced.r > 0.02 && bounced.g == 0.0 && bounced.b == 0.0);
+
+        // a mirror floor shows the ball instead of being lit itself
+        world.objects[0] = Box::new(Plane { material: Material { bsdf: Some(Arc::new(Conductor { roughness: 0.0, f0: None })), ..floor().material }, trans: Transformation::new(), motion: None });
+        world.lights[0] = Light::directional(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, -1.0, 1.0));
+        let r = Ray::new(Vector::new(0.0, 1.1, -3.0), Vector::new(0.0, -1.1, 1.5).normalize());
+        let mirrored = average(&world, r, 16);
+        assert!(mirrored.r > 0.1 && mirrored.g == 0.0);
+    }
+
+    #[test]
+    fn emissive_objects() {
+        // a glowing ball of radius 1, 3 above the floor, lights the point
+        // below it by emission * (1 / 3)^2
+        let mut ball = Sphere::new();
+        ball.material = matte(Colour::new(0.0, 0.0, 0.0));
+        ball.material.emission = Colour::new(9.0, 4.5, 9.0);
+        ball.trans = Transformation::new().translate(0.0, 3.0, 0.0);
+        let mut world = World::new();
+        world.objects = vec!(Box::new(floor()), Box::new(ball));
+        let r = Ray::new(Vector::new(0.0, 1.0, -1.0), Vector::new(0.0, -1.0, 1.0).normalize());
+        assert!(close(average(&world, r, 10000), Colour::new(1.0, 0.5, 1.0), 0.03));
+
+        // seen directly at full strength, whichever side it is seen from
+        let r = Ray::new(Vector::new(0.0, 3.0, -5.0), Vector::new(0.0, 0.0, 1.0));
+        assert_eq!(radiance(&world, r, 5, &mut Rng::new(0)), Colour::new(9.0, 4.5, 9.0));
+        let r = Ray::new(Vector::new(0.0, 3.0, 0.0), Vector::new(0.0, 0.0, 1.0));
+        assert_eq!(radiance(&world, r, 5, &mut Rng::new(0)), Colour::new(9.0, 4.5, 9.0));
+        // Whitted shows the glow but isn't lit by it
+        assert_eq!(world.colour_at(r, 5, &mut Rng::new(0)), Colour::new(9.0, 4.5, 9.0));
+    }
+
+    #[test]
+    fn mesh_lights() {
+        // a glowing triangle is found by both light sampling and bounces, so
+        // it gives the same light however it's split up
+        let glow = |vertices: [Vector; 3]| {
+            let mut t = Triangle::new(vertices, None);
+            t.material = matte(Colour::new(0.0, 0.0, 0.0));
+            t.material.emission = Colour::new(2.0, 2.0, 2.0);
+            Box::new(t)
+        };
+        let (a, b, c, d) = (Vector::new(-1.0, 1.0, -1.0), Vector::new(1.0, 1.0, -1.0),
+                            Vector::new(1.0, 1.0, 1.0), Vector::new(-1.0, 1.0, 1.0));
+        let mut halves = World::new();
+        halves.objects = vec!(Box::new(floor()), glow([a, b, c]), glow([a, c, d]));
+        let mut quarters = World::new();
+        let centre = Vector::new(0.0, 1.0, 0.0);
+        quarters.objects = vec!(Box::new(floor()), glow([a, b, centre]), glow([b, c, centre]), glow([c, d, centre]), glow([d, a, centre]));
+        let r = Ray::new(Vector::new(0.0, 0.5, -1.0), Vector::new(0.0, -0.5, 1.0).normalize());
+        let expected = average(&halves, r, 4000);
+        assert!(expected.r > 0.5);
+        assert!(close(average(&quarters, r, 4000), expected, 0.03));
+    }
+
+    #[test]
+    fn environments() {
+        // an even sky lights a white floor, which sees only sky, as brightly
+        // as the sky itself
+        let mut world = World::new();
+        world.objects = vec!(Box::new(floor()));
+        world.environment.background = Background::Solid(Colour::new(0.5, 1.0, 0.25));
+        let r = Ray::new(Vector::new(0.0, 1.0, -1.0), Vector::new(0.0, -1.0, 1.0).normalize());
+        assert!(close(average(&world, r, 2000), Colour::new(0.5, 1.0, 0.25), 0.01));
+        let up = Ray::new(Vector::new(0.0, 1.0, 0.0), Vector::new(0.0, 1.0, 0.0));
+        assert_eq!(radiance(&world, up, 5, &mut Rng::new(0)), Colour::new(0.5, 1.0, 0.25));
+
+        // as does a map, sampled by brightness, whose bright half is below
+        // the floor
+        let mut image = Canvas::new(16, 8);
+        for y in 0..8 {
+            for x in 0..16 {
+                image.write(x, y, if y < 4 { Colour::new(1.0, 1.0, 1.0) } else { Colour::new(50.0, 0.0, 0.0) });
+            }
+        }
+        world.environment.background = Background::Map(Arc::new(EnvironmentMap::new(Arc::new(image))));
+        assert!(close(average(&world, r, 2000), Colour::new(1.0, 1.0, 1.0), 0.02));
+    }
+
+    #[test]
+    fn last_bounce_finds_lights_alone() {
+        // a floor that sees nothing but its lights is lit the same by paths
+        // that end there as by paths that bounce on
+        let depths = |world: &World, r: Ray, n: usize| {
+            let mut rng = Rng::new(3);
+            let mut totals = (Colour::new(0.0, 0.0, 0.0), Colour::new(0.0, 0.0, 0.0));
+            for _ in 0..n {
+                totals.0 = totals.0 + radiance(world, r, 0, &mut rng);
+                totals.1 = totals.1 + radiance(world, r, 5, &mut rng);
+            }
+            (totals.0 * (1.0 / n as f64), totals.1 * (1.0 / n as f64))
+        };
+        let r = Ray::new(Vector::new(0.0, 0.5, -1.0), Vector::new(0.0, -0.5, 1.0).normalize());
+
+        let mut sky = World::new();
+        sky.objects = vec!(Box::new(floor()));
+        sky.environment.background = Background::Solid(Colour::new(1.0, 1.0, 1.0));
+        let (last, on) = depths(&sky, r, 4000);
+        let white = Colour::new(1.0, 1.0, 1.0);
+        assert!(close(last, white, 0.03) && close(on, white, 0.03));
+
+        let mut ball = Sphere::new();
+        ball.material = matte(Colour::new(0.0, 0.0, 0.0));
+        ball.material.emission = Colour::new(9.0, 9.0, 9.0);
+        ball.trans = Transformation::new().translate(0.0, 3.0, 0.0);
+        let mut glow = World::new();
+        glow.objects = vec!(Box::new(floor()), Box::new(ball));
+        let (last, on) = depths(&glow, r, 4000);
+        assert!(close(last, white, 0.05) && close(on, white, 0.05));
+
+        let mut lamp = World::new();
+        lamp.objects = vec!(Box::new(floor()));
+        lamp.lights = vec!(Light {
+            shape: LightShape::Rect { u: Vector::new(2.0, 0.0, 0.0), v: Vector::new(0.0, 0.0, 1.0), usteps: 64, vsteps: 64 },
+            ..Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(-0.5, 1.0, -0.5))
+        });
+        let (last, on) = depths(&lamp, r, 4000);
+        let whitted = lamp.colour_at(r, 0, &mut Rng::new(0));
+        assert!(close(last, whitted, 0.02) && close(on, whitted, 0.02));
+    }
+}
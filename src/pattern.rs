@@ -0,0 +1,352 @@
+use std::f64::consts::PI;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use super::canvas::Colour;
+use super::noise::Noise;
+use super::transformation::{Transformation, Vector};
+
+// uv is the object's own texture coordinate at the point being coloured,
+// which is passed down to nested patterns unchanged
+pub trait Pattern: Debug + Send + Sync {
+    // maps object space to pattern space
+    fn transform(&self) -> &Transformation;
+    // p is in pattern space
+    fn local_colour_at(&self, p: Vector, uv: (f64, f64)) -> Colour;
+
+    // p is in the space of the object (or parent pattern) the pattern is on
+    fn colour_at(&self, p: Vector, uv: (f64, f64)) -> Colour {
+        self.local_colour_at(self.transform().inverse().point(p), uv)
+    }
+}
+
+// checkers and stripes would flicker on surfaces lying exactly on a boundary
+const EPSILON: f64 = 1e-9;
+
+fn mix(a: Colour, b: Colour, t: f64) -> Colour {
+    a + (b - a) * t
+}
+
+#[derive(Debug)]
+pub struct Solid {
+    pub colour: Colour,
+    pub trans: Transformation,
+}
+
+// a single colour, used as the leaves of nested patterns
+pub fn solid(colour: Colour) -> Arc<dyn Pattern> {
+    Arc::new(Solid { colour, trans: Transformation::new() })
+}
+
+impl Pattern for Solid {
+    fn transform(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn local_colour_at(&self, _p: Vector, _uv: (f64, f64)) -> Colour {
+        self.colour
+    }
+}
+
+// the two sub-patterns alternate every unit along x
+#[derive(Debug)]
+pub struct Stripe {
+    pub a: Arc<dyn Pattern>,
+    pub b: Arc<dyn Pattern>,
+    pub trans: Transformation,
+}
+
+impl Pattern for Stripe {
+    fn transform(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn local_colour_at(&self, p: Vector, uv: (f64, f64)) -> Colour {
+        if (p.x() + EPSILON).floor() as i64 % 2 == 0 {
+            self.a.colour_at(p, uv)
+        } else {
+            self.b.colour_at(p, uv)
+        }
+    }
+}
+
+// blends from a to b between x = 0 and x = 1, then repeats
+#[derive(Debug)]
+pub struct Gradient {
+    pub a: Arc<dyn Pattern>,
+    pub b: Arc<dyn Pattern>,
+    pub trans: Transformation,
+}
+
+impl Pattern for Gradient {
+    fn transform(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn local_colour_at(&self, p: Vector, uv: (f64, f64)) -> Colour {
+        mix(self.a.colour_at(p, uv), self.b.colour_at(p, uv), p.x() - p.x().floor())
+    }
+}
+
+// concentric rings around the y axis, one unit wide
+#[derive(Debug)]
+pub struct Ring {
+    pub a: Arc<dyn Pattern>,
+    pub b: Arc<dyn Pattern>,
+    pub trans: Transformation,
+}
+
+impl Pattern for Ring {
+    fn transform(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn local_colour_at(&self, p: Vector, uv: (f64, f64)) -> Colour {
+        let d = (p.x() * p.x() + p.z() * p.z()).sqrt();
+        if (d + EPSILON).floor() as i64 % 2 == 0 {
+            self.a.colour_at(p, uv)
+        } else {
+            self.b.colour_at(p, uv)
+        }
+    }
+}
+
+// unit cubes alternating in all three dimensions
+#[derive(Debug)]
+pub struct Checkers {
+    pub a: Arc<dyn Pattern>,
+    pub b: Arc<dyn Pattern>,
+    pub trans: Transformation,
+}
+
+impl Pattern for Checkers {
+    fn transform(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn local_colour_at(&self, p: Vector, uv: (f64, f64)) -> Colour {
+        let sum = (p.x() + EPSILON).floor() + (p.y() + EPSILON).floor() + (p.z() + EPSILON).floor();
+        if sum as i64 % 2 == 0 {
+            self.a.colour_at(p, uv)
+        } else {
+            self.b.colour_at(p, uv)
+        }
+    }
+}
+
+// the average of two patterns
+#[derive(Debug)]
+pub struct Blend {
+    pub a: Arc<dyn Pattern>,
+    pub b: Arc<dyn Pattern>,
+    pub trans: Transformation,
+}
+
+impl Pattern for Blend {
+    fn transform(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn local_colour_at(&self, p: Vector, uv: (f64, f64)) -> Colour {
+        (self.a.colour_at(p, uv) + self.b.colour_at(p, uv)) * 0.5
+    }
+}
+
+// looks up another pattern at a point displaced by up to amount in each
+// direction, which makes straight edges wobble
+#[derive(Debug)]
+pub struct Perturbed {
+    pub inner: Arc<dyn Pattern>,
+    pub noise: Noise,
+    pub amount: f64,
+    pub octaves: usize,
+    pub trans: Transformation,
+}
+
+impl Pattern for Perturbed {
+    fn transform(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn local_colour_at(&self, p: Vector, uv: (f64, f64)) -> Colour {
+        // offset lookups so each axis moves independently
+        let dx = self.noise.fbm(p, self.octaves);
+        let dy = self.noise.fbm(p + Vector::new(31.4, 0.0, 0.0), self.octaves);
+        let dz = self.noise.fbm(p + Vector::new(0.0, 0.0, 27.1), self.octaves);
+        self.inner.colour_at(p + Vector::new(dx, dy, dz) * self.amount, uv)
+    }
+}
+
+// veins of b through a running across x, bent by turbulence
+#[derive(Debug)]
+pub struct Marble {
+    pub a: Arc<dyn Pattern>,
+    pub b: Arc<dyn Pattern>,
+    pub noise: Noise,
+    pub amount: f64,
+    pub octaves: usize,
+    pub trans: Transformation,
+}
+
+impl Pattern for Marble {
+    fn transform(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn local_colour_at(&self, p: Vector, uv: (f64, f64)) -> Colour {
+        let t = 0.5 + 0.5 * (PI * (p.x() + self.amount * self.noise.turbulence(p, self.octaves))).sin();
+        mix(self.a.colour_at(p, uv), self.b.colour_at(p, uv), t)
+    }
+}
+
+// growth rings around the y axis, fading from a to b across each ring
+#[derive(Debug)]
+pub struct Wood {
+    pub a: Arc<dyn Pattern>,
+    pub b: Arc<dyn Pattern>,
+    pub noise: Noise,
+    pub amount: f64,
+    pub octaves: usize,
+    pub trans: Transformation,
+}
+
+impl Pattern for Wood {
+    fn transform(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn local_colour_at(&self, p: Vector, uv: (f64, f64)) -> Colour {
+        let d = (p.x() * p.x() + p.z() * p.z()).sqrt() + self.amount * self.noise.fbm(p, self.octaves);
+        mix(self.a.colour_at(p, uv), self.b.colour_at(p, uv), d - d.floor())
+    }
+}
+
+// soft blobs of b over a, amount scales the contrast
+#[derive(Debug)]
+pub struct Clouds {
+    pub a: Arc<dyn Pattern>,
+    pub b: Arc<dyn Pattern>,
+    pub noise: Noise,
+    pub amount: f64,
+    pub octaves: usize,
+    pub trans: Transformation,
+}
+
+impl Pattern for Clouds {
+    fn transform(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn local_colour_at(&self, p: Vector, uv: (f64, f64)) -> Colour {
+        let t = (0.5 + 0.5 * self.amount * self.noise.fbm(p, self.octaves)).clamp(0.0, 1.0);
+        mix(self.a.colour_at(p, uv), self.b.colour_at(p, uv), t)
+    }
+}
+
+#[cfg(test)]
+mod pattern_tests {
+    use super::*;
+    use noise::Basis;
+
+    fn white() -> Arc<dyn Pattern> {
+        solid(Colour::new(1.0, 1.0, 1.0))
+    }
+
+    fn black() -> Arc<dyn Pattern> {
+        solid(Colour::new(0.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn stripes() {
+        let p = Stripe { a: white(), b: black(), trans: Transformation::new() };
+        assert_eq!(p.colour_at(Vector::new(0.0, 0.0, 0.0), (0.0, 0.0)), Colour::new(1.0, 1.0, 1.0));
+        assert_eq!(p.colour_at(Vector::new(0.0, 1.0, 2.0), (0.0, 0.0)), Colour::new(1.0, 1.0, 1.0));
+        assert_eq!(p.colour_at(Vector::new(0.9, 0.0, 0.0), (0.0, 0.0)), Colour::new(1.0, 1.0, 1.0));
+        assert_eq!(p.colour_at(Vector::new(1.0, 0.0, 0.0), (0.0, 0.0)), Colour::new(0.0, 0.0, 0.0));
+        assert_eq!(p.colour_at(Vector::new(-0.1, 0.0, 0.0), (0.0, 0.0)), Colour::new(0.0, 0.0, 0.0));
+        assert_eq!(p.colour_at(Vector::new(-1.1, 0.0, 0.0), (0.0, 0.0)), Colour::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn transformed() {
+        let p = Stripe { a: white(), b: black(), trans: Transformation::new().scale(2.0, 2.0, 2.0) };
+        assert_eq!(p.colour_at(Vector::new(1.5, 0.0, 0.0), (0.0, 0.0)), Colour::new(1.0, 1.0, 1.0));
+        let p = Stripe { a: white(), b: black(), trans: Transformation::new().translate(0.5, 0.0, 0.0) };
+        assert_eq!(p.colour_at(Vector::new(2.5, 0.0, 0.0), (0.0, 0.0)), Colour::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn gradient() {
+        let p = Gradient { a: white(), b: black(), trans: Transformation::new() };
+        assert_eq!(p.colour_at(Vector::new(0.25, 0.0, 0.0), (0.0, 0.0)), Colour::new(0.75, 0.75, 0.75));
+        assert_eq!(p.colour_at(Vector::new(0.75, 0.0, 0.0), (0.0, 0.0)), Colour::new(0.25, 0.25, 0.25));
+    }
+
+    #[test]
+    fn ring() {
+        let p = Ring { a: white(), b: black(), trans: Transformation::new() };
+        assert_eq!(p.colour_at(Vector::new(0.0, 0.0, 0.0), (0.0, 0.0)), Colour::new(1.0, 1.0, 1.0));
+        assert_eq!(p.colour_at(Vector::new(1.0, 0.0, 0.0), (0.0, 0.0)), Colour::new(0.0, 0.0, 0.0));
+        assert_eq!(p.colour_at(Vector::new(0.708, 0.0, 0.708), (0.0, 0.0)), Colour::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn checkers() {
+        let p = Checkers { a: white(), b: black(), trans: Transformation::new() };
+        assert_eq!(p.colour_at(Vector::new(0.99, 0.0, 0.0), (0.0, 0.0)), Colour::new(1.0, 1.0, 1.0));
+        assert_eq!(p.colour_at(Vector::new(1.01, 0.0, 0.0), (0.0, 0.0)), Colour::new(0.0, 0.0, 0.0));
+        assert_eq!(p.colour_at(Vector::new(0.0, 1.01, 0.0), (0.0, 0.0)), Colour::new(0.0, 0.0, 0.0));
+        assert_eq!(p.colour_at(Vector::new(0.0, 0.0, 1.01), (0.0, 0.0)), Colour::new(0.0, 0.0, 0.0));
+        assert_eq!(p.colour_at(Vector::new(-0.5, 0.0, -0.5), (0.0, 0.0)), Colour::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn nested_and_blended() {
+        let red = solid(Colour::new(1.0, 0.0, 0.0));
+        let stripes: Arc<dyn Pattern> = Arc::new(Stripe { a: red, b: black(), trans: Transformation::new().scale(0.5, 0.5, 0.5) });
+        let p = Checkers { a: stripes.clone(), b: white(), trans: Transformation::new() };
+        assert_eq!(p.colour_at(Vector::new(0.25, 0.0, 0.0), (0.0, 0.0)), Colour::new(1.0, 0.0, 0.0));
+        assert_eq!(p.colour_at(Vector::new(0.75, 0.0, 0.0), (0.0, 0.0)), Colour::new(0.0, 0.0, 0.0));
+        assert_eq!(p.colour_at(Vector::new(1.25, 0.0, 0.0), (0.0, 0.0)), Colour::new(1.0, 1.0, 1.0));
+
+        let b = Blend { a: stripes, b: white(), trans: Transformation::new() };
+        assert_eq!(b.colour_at(Vector::new(0.25, 0.0, 0.0), (0.0, 0.0)), Colour::new(1.0, 0.5, 0.5));
+    }
+
+    fn between(c: Colour) -> bool {
+        [c.r, c.g, c.b].iter().all(|v| (0.0..=1.0).contains(v))
+    }
+
+    #[test]
+    fn perturbed() {
+        let stripes: Arc<dyn Pattern> = Arc::new(Stripe { a: white(), b: black(), trans: Transformation::new() });
+        let still = Perturbed { inner: stripes.clone(), noise: Noise::new(0, Basis::Perlin), amount: 0.0, octaves: 3, trans: Transformation::new() };
+        let p = Vector::new(0.99, 0.3, 0.7);
+        assert_eq!(still.colour_at(p, (0.0, 0.0)), stripes.colour_at(p, (0.0, 0.0)));
+
+        let wobbly = Perturbed { amount: 0.5, ..still };
+        let moved = (0..100).map(|i| Vector::new(0.98, i as f64 * 0.13, 0.0))
+                            .filter(|&p| wobbly.colour_at(p, (0.0, 0.0)) != stripes.colour_at(p, (0.0, 0.0)))
+                            .count();
+        assert!(moved > 0 && moved < 100);
+    }
+
+    #[test]
+    fn noise_textures() {
+        let noise = Noise::new(7, Basis::Simplex);
+        let textures: Vec<Box<dyn Pattern>> = vec!(
+            Box::new(Marble { a: white(), b: black(), noise: noise.clone(), amount: 2.0, octaves: 4, trans: Transformation::new() }),
+            Box::new(Wood { a: white(), b: black(), noise: noise.clone(), amount: 0.3, octaves: 2, trans: Transformation::new() }),
+            Box::new(Clouds { a: white(), b: black(), noise: noise.clone(), amount: 1.5, octaves: 5, trans: Transformation::new() }),
+        );
+        for t in &textures {
+            let colours: Vec<Colour> = (0..200).map(|i| t.colour_at(Vector::new(i as f64 * 0.037, 0.5, i as f64 * 0.021), (0.0, 0.0))).collect();
+            assert!(colours.iter().all(|&c| between(c)));
+            // not a flat colour
+            assert!(colours.iter().any(|&c| c.r > 0.6) && colours.iter().any(|&c| c.r < 0.4));
+            // and always the same for the same seed
+            let again = t.colour_at(Vector::new(1.0, 2.0, 3.0), (0.0, 0.0));
+            assert_eq!(again, t.colour_at(Vector::new(1.0, 2.0, 3.0), (0.0, 0.0)));
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use super::scene_object::SceneObject;
+use super::scene_object::Material;
+use super::ray;
+use super::texture;
+use super::transformation::{Motion, Transformation, Vector};
+use super::world::EPSILON;
+
+// the xz plane, facing up the y axis
+#[derive(Debug, Clone)]
+pub struct Plane {
+    pub material: Material,
+    pub trans: Transformation,
+    // when set, the object moves and trans is ignored
+    pub motion: Option<Arc<Motion>>,
+}
+
+impl SceneObject for Plane {
+    fn intersect(&self, r: ray::Ray) -> Vec<ray::Intersection<'_>> {
+        let inv = self.transformation_at(r.time).inverse();
+        let r = ray::Ray::new(inv.point(r.origin), inv.direction(r.dir));
+        if r.dir.y().abs() < EPSILON {
+            return vec!();
+        }
+        vec!(ray::Intersection { t: -r.origin.y() / r.dir.y(), object: self })
+    }
+
+    fn local_normal(&self, _p: Vector) -> Vector {
+        Vector::new(0.0, 1.0, 0.0)
+    }
+
+    fn local_tangents(&self, _p: Vector) -> (Vector, Vector) {
+        (Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 0.0, 1.0))
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn transformation(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn motion(&self) -> Option<&Motion> {
+        self.motion.as_deref()
+    }
+
+    fn uv(&self, p: Vector) -> (f64, f64) {
+        texture::planar(p)
+    }
+}
+
+#[cfg(test)]
+mod plane_tests {
+    use super::*;
+
+    fn plane() -> Plane {
+        Plane { material: Material::new(), trans: Transformation::new(), motion: None }
+    }
+
+    #[test]
+    fn normal_is_constant() {
+        let p = plane();
+        assert_eq!(p.normal(Vector::new(0.0, 0.0, 0.0), 0.0), Vector::new(0.0, 1.0, 0.0));
+        assert_eq!(p.normal(Vector::new(10.0, 0.0, -10.0), 0.0), Vector::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn intersections() {
+        let p = plane();
+        // parallel and coplanar rays miss
+        let parallel = ray::Ray::new(Vector::new(0.0, 10.0, 0.0), Vector::new(0.0, 0.0, 1.0));
+        assert!(p.intersect(parallel).is_empty());
+        let coplanar = ray::Ray::new(Vector::new(0.0, 0.0, 0.0), Vector::new(0.0, 0.0, 1.0));
+        assert!(p.intersect(coplanar).is_empty());
+
+        let above = ray::Ray::new(Vector::new(0.0, 1.0, 0.0), Vector::new(0.0, -1.0, 0.0));
+        assert_eq!(p.intersect(above)[0].t, 1.0);
+        let below = ray::Ray::new(Vector::new(0.0, -1.0, 0.0), Vector::new(0.0, 1.0, 0.0));
+        assert_eq!(p.intersect(below)[0].t, 1.0);
+    }
+
+    #[test]
+    fn transformed() {
+        let mut p = plane();
+        p.trans = Transformation::new().rotate(0.0, 0.0, std::f64::consts::FRAC_PI_2);
+        let r = ray::Ray::new(Vector::new(-3.0, 0.5, 0.0), Vector::new(1.0, 0.0, 0.0));
+        assert!((p.intersect(r)[0].t - 3.0).abs() < 1e-10);
+    }
+}
@@ -0,0 +1,384 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::camera::Camera;
+use super::canvas::{Canvas, Colour};
+use super::random::Rng;
+use super::render::{self, Settings};
+use super::world::World;
+
+// The samples taken so far in one pixel: the filter weighted sum of their
+// colours, and a running mean and spread of their brightness.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Pixel {
+    pub total: Colour,
+    pub weights: f64,
+    pub samples: usize,
+    pub mean: f64,
+    // the sum of squared differences from the mean, as in Welford's method
+    pub squares: f64,
+}
+
+impl Pixel {
+    fn add(&mut self, c: Colour, weight: f64) {
+        self.total = self.total + c * weight;
+        self.weights += weight;
+        self.samples += 1;
+        let l = c.luminance();
+        let delta = l - self.mean;
+        self.mean += delta / self.samples as f64;
+        self.squares += delta * (l - self.mean);
+    }
+
+    pub fn colour(&self) -> Colour {
+        if self.weights.abs() < 1e-12 {
+            return Colour::new(0.0, 0.0, 0.0);
+        }
+        self.total * (1.0 / self.weights)
+    }
+
+    // the standard error of the pixel's mean brightness
+    pub fn error(&self) -> f64 {
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.samples as f64;
+        (self.squares / (n - 1.0) / n).sqrt()
+    }
+}
+
+// a float buffer the samples of each pass are added into, indexed by [x][y]
+#[derive(Debug, Clone)]
+pub struct Accumulation {
+    pub pixels: Vec<Vec<Pixel>>,
+    // how many passes have been added
+    pub passes: usize,
+}
+
+impl Accumulation {
+    pub fn new(width: usize, height: usize) -> Accumulation {
+        Accumulation { pixels: vec![vec![Pixel::default(); height]; width], passes: 0 }
+    }
+
+    // Writes everything needed to carry on rendering: the sums in each pixel,
+    // exactly, and how many passes were made, which is all the state the
+    // random numbers have since each sample's are keyed by its pass. key
+    // describes the settings the samples were taken with.
+    pub fn save(&self, filename: &str, key: &str) -> io::Result<()> {
+        let (w, h) = (self.pixels.len(), self.pixels.first().map_or(0, |c| c.len()));
+        let mut data = format!("{}\n{} {} {}\n{}\n", CHECKPOINT, w, h, self.passes, key).into_bytes();
+        for p in self.pixels.iter().flatten() {
+            for v in &[p.total.r, p.total.g, p.total.b, p.weights, p.mean, p.squares] {
+                data.extend_from_slice(&v.to_bits().to_le_bytes());
+            }
+            data.extend_from_slice(&(p.samples as u64).to_le_bytes());
+        }
+        fs::write(filename, data)
+    }
+
+    // what save wrote, as long as it was width x height and rendered with
+    // the same key
+    pub fn load(filename: &str, width: usize, height: usize, key: &str) -> io::Result<Accumulation> {
+        let data = fs::read(filename)?;
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut lines = data.splitn(4, |&b| b == b'\n');
+        let mut line = || lines.next().map(|l| String::from_utf8_lossy(l).into_owned());
+        if line().as_deref() != Some(CHECKPOINT) {
+            return Err(invalid(format!("{} isn't a checkpoint", filename)));
+        }
+        let size: Vec<usize> = line().unwrap_or_default().split(' ').filter_map(|n| n.parse().ok()).collect();
+        if size.len() != 3 || (size[0], size[1]) != (width, height) {
+            return Err(invalid(format!("{} is for another size of image", filename)));
+        }
+        if line().as_deref() != Some(key) {
+            return Err(invalid(format!("{} was rendered with other settings", filename)));
+        }
+        let body = lines.next().unwrap_or(&[]);
+        if body.len() != width * height * 7 * 8 {
+            return Err(invalid(format!("{} is cut short", filename)));
+        }
+
+        let mut words = body.chunks(8).map(|b| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]));
+        let mut acc = Accumulation::new(width, height);
+        acc.passes = size[2];
+        for p in acc.pixels.iter_mut().flatten() {
+            let mut next = || f64::from_bits(words.next().unwrap());
+            p.total = Colour::new(next(), next(), next());
+            p.weights = next();
+            p.mean = next();
+            p.squares = next();
+            p.samples = words.next().unwrap() as usize;
+        }
+        Ok(acc)
+    }
+
+    pub fn image(&self) -> Canvas {
+        let mut image = Canvas::new(self.pixels.len(), self.pixels.first().map_or(0, |c| c.len()));
+        for (x, column) in self.pixels.iter().enumerate() {
+            for (y, p) in column.iter().enumerate() {
+                image.write(x, y, p.colour());
+            }
+        }
+        image
+    }
+}
+
+// the first line of a checkpoint file
+const CHECKPOINT: &str = "ray_tracer checkpoint 1";
+
+// 64 bit FNV-1a, which unlike std's hasher is the same from one build to
+// the next
+fn hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+// Where the images written while rendering to filename go, so that the
+// unfinished image is never taken for the finished one: out/shot.png is
+// snapshotted to out/shot.snapshot.png.
+pub fn snapshot_filename(filename: &str) -> String {
+    let path = Path::new(filename);
+    let stem = path.file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+    let name = match path.extension() {
+        Some(ext) => format!("{}.snapshot.{}", stem, ext.to_string_lossy()),
+        None => format!("{}.snapshot", stem),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+// brightness below this counts as this for the noise threshold, so that
+// nearly black pixels needn't be sampled forever
+const DARK: f64 = 0.01;
+
+// Adds samples a pass at a time, one more in each pixel that's still noisy,
+// until there are max_samples in each, time_limit has passed, or every
+// pixel's standard error is under threshold times its brightness. Samples
+// are spread over the filter's footprint at random, whatever the pattern.
+#[derive(Debug, Clone, Copy)]
+pub struct Progressive {
+    pub max_samples: usize,
+    pub time_limit: Option<Duration>,
+    pub threshold: Option<f64>,
+    // pixels aren't judged by the threshold until they have this many samples
+    pub min_samples: usize,
+    // how often the image so far is handed back while rendering
+    pub snapshot_every: Option<Duration>,
+    // and how often the accumulation is, to be saved in case the render is
+    // stopped
+    pub checkpoint_every: Option<Duration>,
+}
+
+impl Progressive {
+    pub fn new() -> Progressive {
+        Progressive { max_samples: 1024, time_limit: None, threshold: None, min_samples: 16, snapshot_every: None, checkpoint_every: None }
+    }
+
+    fn converged(&self, p: &Pixel) -> bool {
+        match self.threshold {
+            Some(threshold) => p.samples >= self.min_samples && p.error() <= threshold * p.mean.max(DARK),
+            None => false,
+        }
+    }
+
+    // What a checkpoint has to be resumed with to carry on the same render:
+    // the settings that change the samples taken or where they stop, the
+    // camera, cropped and sized as it is, and the scene file's source.
+    pub fn checkpoint_key(&self, settings: &Settings, camera: &Camera, scene: &str) -> String {
+        format!("seed {} max-depth {} filter {:?} integrator {:?} samples {} min-samples {} threshold {:?} camera {:016x} scene {:016x}",
+                settings.seed, settings.max_depth, settings.filter, settings.integrator, self.max_samples, self.min_samples,
+                self.threshold, hash(&format!("{:?}", camera)), hash(scene))
+    }
+
+    // whether acc has all the samples it needs
+    pub fn finished(&self, acc: &Accumulation) -> bool {
+        acc.passes >= self.max_samples || acc.pixels.iter().flatten().all(|p| self.converged(p))
+    }
+
+    // adds one sample to each pixel of acc that isn't finished
+    pub fn pass(&self, acc: &mut Accumulation, camera: &Camera, world: &World, settings: &Settings) {
+        let radius = settings.filter.radius();
+        let pass = acc.passes;
+        let pixels = &acc.pixels;
+        let (ox, oy) = camera.offset();
+        let samples = render::parallel_pixels(camera.hsize, camera.vsize, settings.threads, |x, y| {
+            if self.converged(&pixels[x][y]) {
+                return None;
+            }
+            // the first sample goes through the centre of the pixel, keyed as
+            // render keys it, so the first pass is the image a single sample
+            // renders
+            let (cx, cy) = (x as f64 + 0.5, y as f64 + 0.5);
+            let (gx, gy) = ((x + ox) as u64, (y + oy) as u64);
+            if pass == 0 {
+                let mut rng = Rng::keyed(settings.seed, &[gx, gy]);
+                return Some((render::sample(camera, world, settings, cx, cy, &mut rng), 1.0));
+            }
+            let mut rng = Rng::keyed(settings.seed, &[gx, gy, pass as u64, 2]);
+            let (dx, dy) = ((2.0 * rng.next_f64() - 1.0) * radius, (2.0 * rng.next_f64() - 1.0) * radius);
+            Some((render::sample(camera, world, settings, cx + dx, cy + dy, &mut rng), settings.filter.weight(dx, dy)))
+        });
+        for (column, new) in acc.pixels.iter_mut().zip(samples) {
+            for (p, s) in column.iter_mut().zip(new) {
+                if let Some((c, w)) = s {
+                    p.add(c, w);
+                }
+            }
+        }
+        acc.passes += 1;
+    }
+
+    // Carries on rendering into acc until it's finished, calling snapshot
+    // with it every snapshot_every and checkpoint every checkpoint_every. A
+    // pass is always made into an empty acc, however short the time limit.
+    pub fn render<F, G>(&self, acc: &mut Accumulation, camera: &Camera, world: &World, settings: &Settings, mut snapshot: F, mut checkpoint: G)
+        where F: FnMut(&Accumulation), G: FnMut(&Accumulation) {
+        let start = Instant::now();
+        let (mut last_snapshot, mut last_checkpoint) = (start, start);
+        while acc.passes == 0 || !self.finished(acc) {
+            if acc.passes > 0 && self.time_limit.is_some_and(|limit| start.elapsed() >= limit) {
+                break;
+            }
+            self.pass(acc, camera, world, settings);
+            if self.snapshot_every.is_some_and(|every| last_snapshot.elapsed() >= every) {
+                snapshot(acc);
+                last_snapshot = Instant::now();
+            }
+            if self.checkpoint_every.is_some_and(|every| last_checkpoint.elapsed() >= every) {
+                checkpoint(acc);
+                last_checkpoint = Instant::now();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod progressive_tests {
+    use super::*;
+    use light::{Light, LightShape};
+    use plane::Plane;
+    use scene_object::Material;
+    use sphere::Sphere;
+    use transformation::{Transformation, Vector};
+
+    // a sphere on a floor, under a soft light whose shadow is noisy
+    fn scene() -> (Camera, World) {
+        let mut world = World::new();
+        world.objects.push(Box::new(Plane { material: Material::new(), trans: Transformation::new().translate(0.0, -1.0, 0.0), motion: None }));
+        world.objects.push(Box::new(Sphere::new()));
+        let mut light = Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(-3.0, 6.0, -3.0));
+        light.shape = LightShape::Sphere { radius: 2.0, samples: 1 };
+        world.lights.push(light);
+        let camera = Camera::new(12, 8, 1.0)
+                         .look_at(Vector::new(0.0, 1.0, -6.0), Vector::new(0.0, -0.5, 0.0), Vector::new(0.0, 1.0, 0.0));
+        (camera, world)
+    }
+
+    #[test]
+    fn snapshot_filenames() {
+        assert_eq!(snapshot_filename("out/shot_0007.png"), "out/shot_0007.snapshot.png");
+        assert_eq!(snapshot_filename("shot"), "shot.snapshot");
+    }
+
+    #[test]
+    fn accumulating() {
+        let mut p = Pixel::default();
+        for &l in &[0.2, 0.4, 0.6] {
+            p.add(Colour::new(l, l, l), 1.0);
+        }
+        p.add(Colour::new(2.0, 2.0, 2.0), 0.0);
+        assert!((p.colour().r - 0.4).abs() < 1e-12);
+        assert_eq!(p.samples, 4);
+        assert!((p.mean - 0.8).abs() < 1e-12);
+        // 0.2, 0.4, 0.6 and 2 are 0.6, 0.4, 0.2 and 1.2 from their mean
+        assert!((p.error() - (p.squares / 12.0).sqrt()).abs() < 1e-12 && (p.squares - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn sample_limit() {
+        let (camera, world) = scene();
+        let mut settings = Settings::new();
+        settings.threads = 2;
+        let progressive = Progressive { max_samples: 6, ..Progressive::new() };
+        let mut acc = Accumulation::new(camera.hsize, camera.vsize);
+        let mut snapshots = 0;
+        progressive.render(&mut acc, &camera, &world, &settings, |_| snapshots += 1, |_| {});
+        assert_eq!(acc.passes, 6);
+        assert!(acc.pixels.iter().flatten().all(|p| p.samples == 6));
+        assert_eq!(snapshots, 0);
+
+        // the first pass is the single sample render
+        let mut first = Accumulation::new(camera.hsize, camera.vsize);
+        progressive.pass(&mut first, &camera, &world, &settings);
+        assert_eq!(first.image().data, render::render(&camera, &world, &Settings::new()).data);
+    }
+
+    #[test]
+    fn noise_threshold() {
+        let (camera, world) = scene();
+        let progressive = Progressive { max_samples: 200, threshold: Some(0.2), min_samples: 8, ..Progressive::new() };
+        let mut acc = Accumulation::new(camera.hsize, camera.vsize);
+        progressive.render(&mut acc, &camera, &world, &Settings::new(), |_| {}, |_| {});
+        assert!(progressive.finished(&acc) && acc.passes < 200);
+        // smooth pixels stop early, those in the soft shadow keep going
+        let counts: Vec<usize> = acc.pixels.iter().flatten().map(|p| p.samples).collect();
+        assert!(counts.iter().all(|&n| n >= 8));
+        assert!(counts.contains(&8) && counts.iter().any(|&n| n > 8));
+        assert!(acc.pixels.iter().flatten().all(|p| p.samples == acc.passes || progressive.converged(p)));
+    }
+
+    #[test]
+    fn time_limit() {
+        let (camera, world) = scene();
+        let progressive = Progressive {
+            time_limit: Some(Duration::from_millis(0)),
+            snapshot_every: Some(Duration::from_millis(0)),
+            ..Progressive::new()
+        };
+        let mut acc = Accumulation::new(camera.hsize, camera.vsize);
+        let mut snapshots = vec!();
+        progressive.render(&mut acc, &camera, &world, &Settings::new(), |a| snapshots.push(a.passes), |_| {});
+        assert_eq!((acc.passes, snapshots), (1, vec!(1)));
+    }
+
+    #[test]
+    fn resuming() {
+        let (camera, world) = scene();
+        let mut settings = Settings::new();
+        settings.seed = 9;
+        let progressive = Progressive { max_samples: 20, threshold: Some(0.2), min_samples: 4, ..Progressive::new() };
+        let mut whole = Accumulation::new(camera.hsize, camera.vsize);
+        progressive.render(&mut whole, &camera, &world, &settings, |_| {}, |_| {});
+
+        // stopped after 5 passes, saved, and carried on from the file
+        let filename = std::env::temp_dir().join(format!("ray_tracer_checkpoint_{}", std::process::id()));
+        let filename = filename.to_str().unwrap();
+        let key = progressive.checkpoint_key(&settings, &camera, "[camera]");
+        let mut first = Accumulation::new(camera.hsize, camera.vsize);
+        Progressive { max_samples: 5, ..progressive }.render(&mut first, &camera, &world, &settings, |_| {}, |_| {});
+        first.save(filename, &key).unwrap();
+        let mut resumed = Accumulation::load(filename, camera.hsize, camera.vsize, &key).unwrap();
+        assert_eq!(resumed.passes, 5);
+        settings.threads = 3;
+        progressive.render(&mut resumed, &camera, &world, &settings, |_| {}, |_| {});
+        assert_eq!(resumed.passes, whole.passes);
+        for (a, b) in resumed.pixels.iter().flatten().zip(whole.pixels.iter().flatten()) {
+            assert_eq!((a.total.r.to_bits(), a.weights.to_bits(), a.squares.to_bits(), a.samples),
+                       (b.total.r.to_bits(), b.weights.to_bits(), b.squares.to_bits(), b.samples));
+        }
+
+        let error = |w: usize, h: usize, key: &str| Accumulation::load(filename, w, h, key).err().map(|e| e.to_string());
+        assert!(error(camera.hsize, camera.vsize + 1, &key).unwrap().ends_with("is for another size of image"));
+        let other = |key: String| error(camera.hsize, camera.vsize, &key).unwrap().ends_with("was rendered with other settings");
+        assert!(other(progressive.checkpoint_key(&settings, &camera, "[camera]\n")));
+        assert!(other(progressive.checkpoint_key(&settings, &camera.crop(0, 0, camera.hsize, camera.vsize - 1), "[camera]")));
+        assert!(other(Progressive { threshold: Some(0.1), ..progressive }.checkpoint_key(&settings, &camera, "[camera]")));
+        assert!(other(Progressive { max_samples: 40, ..progressive }.checkpoint_key(&settings, &camera, "[camera]")));
+        settings.seed = 10;
+        assert!(other(progressive.checkpoint_key(&settings, &camera, "[camera]")));
+        let data = fs::read(filename).unwrap();
+        fs::write(filename, &data[..data.len() - 1]).unwrap();
+        assert!(error(camera.hsize, camera.vsize, &key).unwrap().ends_with("is cut short"));
+        fs::remove_file(filename).unwrap();
+    }
+}
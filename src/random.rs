@@ -0,0 +1,65 @@
+// SplitMix64: tiny, fast and good enough for sampling. The whole state is one
+// u64 so generators are cheap to create per pixel, which keeps renders
+// deterministic regardless of how work is split between threads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    // a generator unique to a pixel (or any other set of keys) of a seeded render
+    pub fn keyed(seed: u64, keys: &[u64]) -> Rng {
+        let mut r = Rng::new(seed);
+        for &k in keys {
+            r.state ^= k;
+            r.state = r.next_u64();
+        }
+        r
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod random_tests {
+    use super::*;
+
+    #[test]
+    fn deterministic() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_eq!(Rng::keyed(1, &[3, 4]), Rng::keyed(1, &[3, 4]));
+        assert!(Rng::keyed(1, &[3, 4]) != Rng::keyed(1, &[4, 3]));
+        assert!(Rng::keyed(1, &[3, 4]) != Rng::keyed(2, &[3, 4]));
+    }
+
+    #[test]
+    fn unit_interval() {
+        let mut r = Rng::new(7);
+        let mut total = 0.0;
+        for _ in 0..10000 {
+            let f = r.next_f64();
+            assert!((0.0..1.0).contains(&f));
+            total += f;
+        }
+        assert!((total / 10000.0 - 0.5).abs() < 0.02);
+    }
+}
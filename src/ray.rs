@@ -1,12 +1,10 @@
 use super::scene_object::SceneObject;
 use super::transformation::Vector;
 
-use approx::relative_eq;
 
-
-pub struct Intersection {
+pub struct Intersection<'a> {
     pub t: f64,
-    pub object: Box<dyn SceneObject>,
+    pub object: &'a dyn SceneObject,
 }
 
 
@@ -14,21 +12,23 @@ pub struct Intersection {
 pub struct Ray {
     pub origin: Vector,
     pub dir: Vector,
+    // when the ray sets off, between the camera's shutter opening and closing
+    pub time: f64,
 }
 
 
 pub fn get_hit(intersections: Vec<Intersection>) -> Option<Intersection> {
-    for i in intersections {
-        if i.t >= 0.0 {
-            return Some(i);
-        }
-    }
-    None
+    intersections.into_iter().find(|i| i.t >= 0.0)
 }
 
 impl Ray {
     pub fn new(origin: Vector, dir: Vector) -> Ray {
-        Ray { origin: origin, dir: dir }
+        Ray { origin, dir, time: 0.0 }
+    }
+
+    // the same ray, setting off at time
+    pub fn with_time(self, time: f64) -> Ray {
+        Ray { time, ..self }
     }
 
     pub fn position(&self, t: f64) -> Vector { 
@@ -39,6 +39,7 @@ impl Ray {
 #[cfg(test)]
 mod ray_tests {
     use super::*;
+    use approx::relative_eq;
 
     #[test]
     fn construction() {
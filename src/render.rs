@@ -0,0 +1,217 @@
+use std::thread;
+
+use super::camera::Camera;
+use super::canvas::{Canvas, Colour};
+use super::path;
+use super::random::Rng;
+use super::ray::Ray;
+use super::sampling::{Filter, SamplePattern};
+use super::world::World;
+
+// how the light arriving along each camera ray is worked out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    // phong shading with shadows and mirror reflections
+    Whitted,
+    // unbiased monte carlo path tracing, which includes indirect light
+    Path,
+}
+
+impl Integrator {
+    pub fn from_name(name: &str) -> Option<Integrator> {
+        match name {
+            "whitted" => Some(Integrator::Whitted),
+            "path" => Some(Integrator::Path),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    pub samples: usize,
+    pub max_depth: usize,
+    pub threads: usize,
+    pub seed: u64,
+    pub pattern: SamplePattern,
+    pub filter: Filter,
+    pub integrator: Integrator,
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings {
+            samples: 1,
+            max_depth: 5,
+            threads: 1,
+            seed: 0,
+            pattern: SamplePattern::Jittered,
+            filter: Filter::Box,
+            integrator: Integrator::Whitted,
+        }
+    }
+}
+
+// the colour seen along r
+pub fn trace(world: &World, r: Ray, settings: &Settings, rng: &mut Rng) -> Colour {
+    match settings.integrator {
+        Integrator::Whitted => world.colour_at(r, settings.max_depth, rng),
+        Integrator::Path => path::radiance(world, r, settings.max_depth, rng),
+    }
+}
+
+// the colour seen through the camera at (px, py), black where it sees nothing
+pub fn sample(camera: &Camera, world: &World, settings: &Settings, px: f64, py: f64, rng: &mut Rng) -> Colour {
+    match camera.ray(px, py, rng) {
+        Some(r) => trace(world, r, settings, rng),
+        None => Colour::new(0.0, 0.0, 0.0),
+    }
+}
+
+fn render_pixel(camera: &Camera, world: &World, settings: &Settings, x: usize, y: usize) -> Colour {
+    // keyed by where the pixel is in the whole image, so crops of it take the
+    // same samples
+    let (ox, oy) = camera.offset();
+    let mut rng = Rng::keyed(settings.seed, &[(x + ox) as u64, (y + oy) as u64]);
+    let (cx, cy) = (x as f64 + 0.5, y as f64 + 0.5);
+    // a single sample goes through the centre of the pixel
+    if settings.samples <= 1 {
+        return sample(camera, world, settings, cx, cy, &mut rng);
+    }
+
+    // samples are spread over the whole footprint of the filter, which may
+    // reach into neighbouring pixels
+    let radius = settings.filter.radius();
+    let mut total = Colour::new(0.0, 0.0, 0.0);
+    let mut weights = 0.0;
+    for (u, v) in settings.pattern.offsets(settings.samples, &mut rng) {
+        let (dx, dy) = ((2.0 * u - 1.0) * radius, (2.0 * v - 1.0) * radius);
+        let w = settings.filter.weight(dx, dy);
+        if w == 0.0 {
+            continue;
+        }
+        total = total + sample(camera, world, settings, cx + dx, cy + dy, &mut rng) * w;
+        weights += w;
+    }
+    if weights.abs() < 1e-12 {
+        return sample(camera, world, settings, cx, cy, &mut rng);
+    }
+    total * (1.0 / weights)
+}
+
+// Calls f for every pixel of a width x height image across the given number
+// of threads, returning the results indexed by [x][y].
+pub fn parallel_pixels<T, F>(width: usize, height: usize, threads: usize, f: F) -> Vec<Vec<T>>
+    where T: Send + Clone + Default, F: Fn(usize, usize) -> T + Sync {
+    let threads = threads.max(1);
+    let f = &f;
+    let rows: Vec<(usize, Vec<T>)> = thread::scope(|s| {
+        let workers: Vec<_> = (0..threads).map(|t| {
+            s.spawn(move || {
+                // rows are interleaved between threads so expensive regions are shared out
+                (t..height).step_by(threads).map(|y| {
+                    (y, (0..width).map(|x| f(x, y)).collect())
+                }).collect::<Vec<_>>()
+            })
+        }).collect();
+        workers.into_iter().flat_map(|w| w.join().expect("render thread panicked")).collect()
+    });
+
+    let mut pixels = vec![vec![T::default(); height]; width];
+    for (y, row) in rows {
+        for (x, p) in row.into_iter().enumerate() {
+            pixels[x][y] = p;
+        }
+    }
+    pixels
+}
+
+pub fn render(camera: &Camera, world: &World, settings: &Settings) -> Canvas {
+    let mut image = Canvas::new(camera.hsize, camera.vsize);
+    image.data = parallel_pixels(camera.hsize, camera.vsize, settings.threads, |x, y| {
+        render_pixel(camera, world, settings, x, y)
+    });
+    image
+}
+
+#[cfg(test)]
+mod render_tests {
+    use super::*;
+    use light::Light;
+    use sphere::Sphere;
+    use transformation::Vector;
+
+    fn scene() -> (Camera, World) {
+        let mut world = World::new();
+        world.objects.push(Box::new(Sphere::new()));
+        world.lights.push(Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(-10.0, 10.0, -10.0)));
+        let camera = Camera::new(16, 12, 0.8)
+                         .look_at(Vector::new(0.0, 0.0, -5.0), Vector::new(0.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0));
+        (camera, world)
+    }
+
+    #[test]
+    fn threads_do_not_change_the_image() {
+        let (camera, world) = scene();
+        let mut settings = Settings::new();
+        settings.samples = 4;
+        let one = render(&camera, &world, &settings);
+        settings.threads = 3;
+        let three = render(&camera, &world, &settings);
+        assert_eq!(one.data, three.data);
+    }
+
+    #[test]
+    fn seed_changes_samples() {
+        let (camera, world) = scene();
+        let mut settings = Settings::new();
+        settings.samples = 4;
+        let a = render(&camera, &world, &settings);
+        settings.seed = 1;
+        let b = render(&camera, &world, &settings);
+        assert!(a.data != b.data);
+    }
+
+    #[test]
+    fn patterns_and_filters() {
+        let (camera, world) = scene();
+        let mut settings = Settings::new();
+        settings.samples = 16;
+        let reference = render(&camera, &world, &settings);
+        for &pattern in &[SamplePattern::Grid, SamplePattern::Halton, SamplePattern::Sobol] {
+            for &filter in &[Filter::Box, Filter::Tent, Filter::Gaussian, Filter::Mitchell] {
+                settings.pattern = pattern;
+                settings.filter = filter;
+                let image = render(&camera, &world, &settings);
+                // flat areas away from the sphere's edge agree whatever the sampling
+                assert_eq!(image.read(0, 0), reference.read(0, 0));
+                assert!((image.read(8, 6).r - reference.read(8, 6).r).abs() < 0.05);
+            }
+        }
+    }
+
+    #[test]
+    fn centre_pixel() {
+        let (camera, world) = scene();
+        let image = render(&camera, &world, &Settings::new());
+        let r = camera.ray_for_pixel(8.5, 6.5).unwrap();
+        assert_eq!(image.read(8, 6), world.colour_at(r, 5, &mut Rng::keyed(0, &[8, 6])));
+    }
+
+    #[test]
+    fn crops_match_the_whole_image() {
+        let (camera, world) = scene();
+        let mut settings = Settings::new();
+        settings.samples = 4;
+        let whole = render(&camera, &world, &settings);
+        let part = render(&camera.crop(3, 2, 11, 9), &world, &settings);
+        assert_eq!((part.width(), part.height()), (8, 7));
+        // the same samples, but for rounding
+        for x in 0..8 {
+            for y in 0..7 {
+                let d = part.read(x, y) - whole.read(x + 3, y + 2);
+                assert!(d.r.abs().max(d.g.abs()).max(d.b.abs()) < 1e-9);
+            }
+        }
+    }
+}
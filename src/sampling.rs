@@ -0,0 +1,319 @@
+use std::f64::consts::PI;
+
+use super::random::Rng;
+use super::transformation::Vector;
+
+// where samples are placed within a pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SamplePattern {
+    Grid,
+    Jittered,
+    Halton,
+    Sobol,
+}
+
+// how samples are weighted by their distance from the pixel centre
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
+
+fn radical_inverse(base: u64, mut i: u64) -> f64 {
+    let inv = 1.0 / base as f64;
+    let mut f = inv;
+    let mut r = 0.0;
+    while i > 0 {
+        r += f * (i % base) as f64;
+        i /= base;
+        f *= inv;
+    }
+    r
+}
+
+// the first two dimensions of the Sobol sequence, as 32 bit fractions
+fn sobol(mut i: u32) -> (u32, u32) {
+    let (mut x, mut y) = (0u32, 0u32);
+    let mut v = 1u32 << 31;
+    let mut bit = 0;
+    while i != 0 {
+        if i & 1 == 1 {
+            x ^= (1u32 << 31) >> bit;
+            y ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+        bit += 1;
+    }
+    (x, y)
+}
+
+fn fraction(bits: u32) -> f64 {
+    bits as f64 / 4_294_967_296.0
+}
+
+impl SamplePattern {
+    pub fn from_name(name: &str) -> Option<SamplePattern> {
+        match name {
+            "grid" => Some(SamplePattern::Grid),
+            "jittered" => Some(SamplePattern::Jittered),
+            "halton" => Some(SamplePattern::Halton),
+            "sobol" => Some(SamplePattern::Sobol),
+            _ => None,
+        }
+    }
+
+    // Sample positions in the unit square. Grid and jittered patterns round n
+    // up to fill whole rows; the low discrepancy sequences are randomised per
+    // pixel so neighbouring pixels don't share the same pattern.
+    pub fn offsets(&self, n: usize, rng: &mut Rng) -> Vec<(f64, f64)> {
+        let n = n.max(1);
+        match *self {
+            SamplePattern::Grid | SamplePattern::Jittered => {
+                let nx = (n as f64).sqrt().ceil() as usize;
+                let ny = n.div_ceil(nx);
+                let mut points = Vec::with_capacity(nx * ny);
+                for j in 0..ny {
+                    for i in 0..nx {
+                        let (du, dv) = if *self == SamplePattern::Grid {
+                            (0.5, 0.5)
+                        } else {
+                            (rng.next_f64(), rng.next_f64())
+                        };
+                        points.push(((i as f64 + du) / nx as f64, (j as f64 + dv) / ny as f64));
+                    }
+                }
+                points
+            },
+            SamplePattern::Halton => {
+                // Cranley-Patterson rotation
+                let (su, sv) = (rng.next_f64(), rng.next_f64());
+                (1..=n as u64).map(|i| {
+                    ((radical_inverse(2, i) + su).fract(), (radical_inverse(3, i) + sv).fract())
+                }).collect()
+            },
+            SamplePattern::Sobol => {
+                // a random digital shift keeps the sequence's stratification
+                let (sx, sy) = (rng.next_u64() as u32, rng.next_u64() as u32);
+                (0..n as u32).map(|i| {
+                    let (x, y) = sobol(i);
+                    (fraction(x ^ sx), fraction(y ^ sy))
+                }).collect()
+            },
+        }
+    }
+}
+
+fn mitchell_1d(x: f64) -> f64 {
+    // Mitchell-Netravali with B = C = 1/3, defined over [-2, 2]
+    let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+    let x = x.abs();
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+    } else if x < 2.0 {
+        ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+    } else {
+        0.0
+    }
+}
+
+impl Filter {
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name {
+            "box" => Some(Filter::Box),
+            "tent" => Some(Filter::Tent),
+            "gaussian" => Some(Filter::Gaussian),
+            "mitchell" => Some(Filter::Mitchell),
+            _ => None,
+        }
+    }
+
+    // how far from the pixel centre, in pixels, the filter reaches
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0,
+        }
+    }
+
+    // dx and dy are offsets from the pixel centre in pixels
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        let r = self.radius();
+        if dx.abs() > r || dy.abs() > r {
+            return 0.0;
+        }
+        match *self {
+            Filter::Box => 1.0,
+            Filter::Tent => (1.0 - dx.abs()) * (1.0 - dy.abs()),
+            Filter::Gaussian => {
+                // sigma of half a pixel, shifted so it reaches zero at the radius
+                let g = |x: f64| (-2.0 * x * x).exp() - (-2.0 * r * r).exp();
+                g(dx) * g(dy)
+            },
+            Filter::Mitchell => mitchell_1d(dx) * mitchell_1d(dy),
+        }
+    }
+}
+
+// A direction about n, more likely the closer it is to n: its probability
+// density is cos(theta) / pi. u and v are uniform in [0, 1).
+pub fn cosine_hemisphere(n: Vector, u: f64, v: f64) -> Vector {
+    let (a, b) = n.orthonormal_basis();
+    let (r, phi) = (u.sqrt(), 2.0 * PI * v);
+    (a * (r * phi.cos()) + b * (r * phi.sin()) + n * (1.0 - u).max(0.0).sqrt()).normalize()
+}
+
+// a uniformly distributed direction, with probability density 1 / (4 pi)
+pub fn uniform_sphere(u: f64, v: f64) -> Vector {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    Vector::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// A probability density over [0, 1) that is constant over each of n equal
+// pieces, in proportion to their weights. All zero weights give a uniform
+// density.
+#[derive(Debug, Clone)]
+pub struct Distribution {
+    // the chance of landing below each piece, ending at 1
+    cdf: Vec<f64>,
+}
+
+impl Distribution {
+    pub fn new(weights: &[f64]) -> Distribution {
+        let total: f64 = weights.iter().sum();
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        cdf.push(0.0);
+        for (i, w) in weights.iter().enumerate() {
+            let below = cdf[i];
+            cdf.push(if total > 0.0 { below + w / total } else { (i + 1) as f64 / weights.len() as f64 });
+        }
+        Distribution { cdf }
+    }
+
+    fn pieces(&self) -> usize {
+        self.cdf.len() - 1
+    }
+
+    // the point u in [0, 1) maps to, and the density there
+    pub fn sample(&self, u: f64) -> (f64, f64) {
+        let i = (self.cdf.partition_point(|&c| c <= u) - 1).min(self.pieces() - 1);
+        let chance = self.cdf[i + 1] - self.cdf[i];
+        let within = if chance > 0.0 { ((u - self.cdf[i]) / chance).clamp(0.0, 1.0) } else { 0.5 };
+        let x = ((i as f64 + within) / self.pieces() as f64).min(1.0 - f64::EPSILON);
+        (x, chance * self.pieces() as f64)
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        let i = ((x * self.pieces() as f64) as usize).min(self.pieces() - 1);
+        (self.cdf[i + 1] - self.cdf[i]) * self.pieces() as f64
+    }
+}
+
+#[cfg(test)]
+mod sampling_tests {
+    use super::*;
+
+    fn in_unit_square(points: &[(f64, f64)]) -> bool {
+        points.iter().all(|&(u, v)| (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v))
+    }
+
+    #[test]
+    fn grid() {
+        let mut rng = Rng::new(0);
+        assert_eq!(SamplePattern::Grid.offsets(4, &mut rng), vec!((0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)));
+        assert_eq!(SamplePattern::Grid.offsets(5, &mut rng).len(), 6);
+        assert_eq!(SamplePattern::Grid.offsets(1, &mut rng), vec!((0.5, 0.5)));
+    }
+
+    #[test]
+    fn jittered_is_stratified() {
+        let mut rng = Rng::new(1);
+        let points = SamplePattern::Jittered.offsets(9, &mut rng);
+        assert_eq!(points.len(), 9);
+        for (k, &(u, v)) in points.iter().enumerate() {
+            assert_eq!(((u * 3.0) as usize, (v * 3.0) as usize), (k % 3, k / 3));
+        }
+    }
+
+    #[test]
+    fn low_discrepancy() {
+        assert_eq!(radical_inverse(2, 1), 0.5);
+        assert_eq!(radical_inverse(2, 3), 0.75);
+        assert_eq!(radical_inverse(3, 1), 1.0 / 3.0);
+        assert_eq!(sobol(0), (0, 0));
+        assert_eq!(fraction(sobol(1).0), 0.5);
+        assert_eq!((fraction(sobol(2).0), fraction(sobol(2).1)), (0.25, 0.75));
+
+        let mut rng = Rng::new(2);
+        for pattern in &[SamplePattern::Halton, SamplePattern::Sobol] {
+            let points = pattern.offsets(16, &mut rng);
+            assert_eq!(points.len(), 16);
+            assert!(in_unit_square(&points));
+            // every quadrant gets its share
+            let top_left = points.iter().filter(|&&(u, v)| u < 0.5 && v < 0.5).count();
+            assert!((3..=5).contains(&top_left));
+        }
+    }
+
+    #[test]
+    fn filters() {
+        for f in &[Filter::Box, Filter::Tent, Filter::Gaussian, Filter::Mitchell] {
+            assert!(f.weight(0.0, 0.0) > 0.0);
+            assert_eq!(f.weight(f.radius() + 0.01, 0.0), 0.0);
+            assert!(f.weight(0.0, 0.0) >= f.weight(0.3, 0.2));
+        }
+        assert_eq!(Filter::Tent.weight(0.5, 0.0), 0.5);
+        assert!(Filter::Gaussian.weight(1.5, 0.0).abs() < 1e-12);
+        // the mitchell filter has negative lobes
+        assert!(Filter::Mitchell.weight(1.5, 0.0) < 0.0);
+        assert!((mitchell_1d(0.0) - 8.0 / 9.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn cosine_weighted() {
+        let n = Vector::new(0.0, 0.6, 0.8);
+        let mut rng = Rng::new(3);
+        let mut total = 0.0;
+        for _ in 0..10000 {
+            let d = cosine_hemisphere(n, rng.next_f64(), rng.next_f64());
+            assert!((d.norm() - 1.0).abs() < 1e-9 && d.dot(&n) >= 0.0);
+            total += d.dot(&n);
+        }
+        // the mean cosine of the distribution is 2/3
+        assert!((total / 10000.0 - 2.0 / 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn distributions() {
+        let d = Distribution::new(&[1.0, 0.0, 3.0]);
+        assert_eq!(d.sample(0.0), (0.0, 0.75));
+        assert_eq!(d.sample(0.125), (1.0 / 6.0, 0.75));
+        // never lands in the empty middle piece
+        let (x, pdf) = d.sample(0.625);
+        assert!((x - 5.0 / 6.0).abs() < 1e-12 && pdf == 2.25);
+        assert_eq!(d.pdf(0.5), 0.0);
+        assert_eq!(d.pdf(x), pdf);
+        assert!(d.sample(0.999999).0 < 1.0);
+
+        let flat = Distribution::new(&[0.0, 0.0]);
+        assert_eq!(flat.sample(0.75), (0.75, 1.0));
+    }
+
+    #[test]
+    fn uniform_sphere_is_unit() {
+        let mut rng = Rng::new(4);
+        let mut mean = Vector::new(0.0, 0.0, 0.0);
+        for _ in 0..10000 {
+            let d = uniform_sphere(rng.next_f64(), rng.next_f64());
+            assert!((d.norm() - 1.0).abs() < 1e-9);
+            mean = mean + d * 1e-4;
+        }
+        assert!(mean.norm() < 0.03);
+    }
+}
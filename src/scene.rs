@@ -0,0 +1,1518 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use toml::Spanned;
+
+use super::animation::{Curve, Interpolation};
+use super::bsdf::{Bsdf, Conductor, Dielectric, Lambert, Principled};
+use super::camera::{Aperture, Camera, Equirectangular, Fisheye, Orthographic, Perspective, Projection, Stereo, StereoLayout};
+use super::sky::{Sky, SUN_ANGLE};
+use super::environment::{Background, Environment, EnvironmentMap};
+use super::canvas::{Canvas, Colour};
+use super::light::{Attenuation, Light, LightKind, LightShape};
+use super::mesh;
+use super::noise::{Basis, Noise};
+use super::output;
+use super::pattern::{Blend, Checkers, Clouds, Gradient, Marble, Pattern, Perturbed, Ring, Stripe, Wood, solid};
+use super::plane::Plane;
+use super::scene_object::{Bump, Material};
+use super::sphere::Sphere;
+use super::texture::{Mapping, Texture, TextureFilter, TextureMap, Wrap};
+use super::transformation::{Motion, Transformation, Vector};
+use super::world::World;
+
+pub struct Scene {
+    pub camera: Camera,
+    pub world: World,
+}
+
+#[derive(Debug)]
+pub struct SceneError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDef {
+    camera: CameraDef,
+    #[serde(default)]
+    lights: Vec<Spanned<LightDef>>,
+    #[serde(default)]
+    materials: BTreeMap<String, Spanned<MaterialDef>>,
+    #[serde(default)]
+    transforms: BTreeMap<String, Spanned<Vec<Spanned<StepDef>>>>,
+    #[serde(default)]
+    objects: Vec<ObjectDef>,
+    environment: Option<Spanned<EnvironmentDef>>,
+    sky: Option<Spanned<SkyDef>>,
+    #[serde(default)]
+    animation: Vec<Spanned<AnimationDef>>,
+}
+
+// a curve changing the value at target, a dotted path into this file, from
+// frame to frame; interpolation is "step", "linear" (the default) or "bezier"
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnimationDef {
+    target: String,
+    interpolation: Option<String>,
+    keys: Vec<KeyDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyDef {
+    frame: f64,
+    value: toml::Value,
+}
+
+// an analytic daylight sky, in place of an environment, along with the sun
+// shining from it; sun is the direction towards it
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct SkyDef {
+    sun: [f64; 3],
+    turbidity: Option<f64>,
+    ground_albedo: Option<f64>,
+    sun_angle: Option<f64>,
+    sun_samples: Option<usize>,
+    intensity: Option<f64>,
+}
+
+// a solid colour, a gradient from bottom to top, or an equirectangular image,
+// turned by rotations
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentDef {
+    colour: Option<[f64; 3]>,
+    bottom: Option<[f64; 3]>,
+    top: Option<[f64; 3]>,
+    image: Option<String>,
+    intensity: Option<f64>,
+    #[serde(default)]
+    transform: Vec<Spanned<StepDef>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct CameraDef {
+    width: usize,
+    height: usize,
+    // projection is "perspective" (the default) or "fisheye", both
+    // field-of-view radians across, "orthographic", size across, or
+    // "equirectangular"
+    projection: Option<String>,
+    field_of_view: Option<f64>,
+    size: Option<f64>,
+    // "side-by-side" or "top-bottom" renders both eyes' views, eye-separation
+    // apart
+    stereo: Option<String>,
+    eye_separation: Option<f64>,
+    from: [f64; 3],
+    to: [f64; 3],
+    #[serde(default = "default_up")]
+    up: [f64; 3],
+    // the lens's radius, with things focal-distance away, by default the
+    // distance from from to to, in focus; blades make the lens a polygon
+    #[serde(default)]
+    aperture: f64,
+    focal_distance: Option<f64>,
+    blades: Option<usize>,
+    #[serde(default)]
+    blade_rotation: f64,
+    // when the shutter opens and closes, for motion blur
+    #[serde(default)]
+    shutter_open: f64,
+    #[serde(default)]
+    shutter_close: f64,
+}
+
+// about the distance between a person's eyes, in metres
+const DEFAULT_EYE_SEPARATION: f64 = 0.065;
+
+fn default_up() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+// kind is "point" (the default), "directional" or "spot"; area lights are
+// "rect" (at is a corner, spanning u and v, sampled on a usteps x vsteps grid)
+// or "sphere" (at is the centre) shaped, and directional lights may be "disc"
+// shaped, angle radians across
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LightDef {
+    at: Option<[f64; 3]>,
+    #[serde(default = "default_intensity")]
+    intensity: IntensityDef,
+    kind: Option<Spanned<String>>,
+    direction: Option<[f64; 3]>,
+    cone: Option<f64>,
+    falloff: Option<f64>,
+    attenuation: Option<Spanned<AttenuationDef>>,
+    shape: Option<Spanned<String>>,
+    u: Option<[f64; 3]>,
+    v: Option<[f64; 3]>,
+    usteps: Option<usize>,
+    vsteps: Option<usize>,
+    radius: Option<f64>,
+    samples: Option<usize>,
+    angle: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IntensityDef {
+    Grey(f64),
+    Rgb([f64; 3]),
+}
+
+fn default_intensity() -> IntensityDef {
+    IntensityDef::Grey(1.0)
+}
+
+// "none", "inverse-square" or [constant, linear, quadratic]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AttenuationDef {
+    Named(String),
+    Coefficients([f64; 3]),
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct MaterialDef {
+    extend: Option<String>,
+    colour: Option<[f64; 3]>,
+    ambient: Option<f64>,
+    diffuse: Option<f64>,
+    specular: Option<f64>,
+    shininess: Option<f64>,
+    pattern: Option<PatternDef>,
+    bump: Option<BumpDef>,
+    // a pattern whose colours are tangent space normals
+    #[serde(rename = "normal-map")]
+    normal_map: Option<PatternDef>,
+    bsdf: Option<BsdfDef>,
+    // makes the object a light, for the path tracer
+    emission: Option<[f64; 3]>,
+}
+
+// type is "lambert", "conductor" (roughness), "dielectric" (ior, roughness)
+// or "principled" (metallic, roughness, specular), all tinted by the
+// material's colour or pattern
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct BsdfDef {
+    #[serde(rename = "type")]
+    kind: String,
+    roughness: Option<f64>,
+    ior: Option<f64>,
+    metallic: Option<f64>,
+    specular: Option<f64>,
+}
+
+// the pattern's brightness is the height of the surface, scaled by amount
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct BumpDef {
+    pattern: PatternDef,
+    amount: Option<f64>,
+}
+
+// type is "stripes", "gradient", "rings", "checkers", "blend", "marble",
+// "wood" or "clouds", made from two colours or nested patterns, "perturbed",
+// which wobbles a single nested pattern, or "texture", an image file. The
+// noise driven ones take a noise basis ("perlin" or "simplex"), seed, amount
+// and octaves. Textures take a mapping ("object", the default, "spherical",
+// "planar", "cylindrical" or "cube"), filter ("nearest" or "bilinear") and
+// wrap ("repeat" or "clamp").
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct PatternDef {
+    #[serde(rename = "type")]
+    kind: String,
+    colours: Option<Vec<[f64; 3]>>,
+    patterns: Option<Vec<PatternDef>>,
+    #[serde(default)]
+    transform: Vec<StepDef>,
+    noise: Option<String>,
+    #[serde(default)]
+    seed: u64,
+    amount: Option<f64>,
+    octaves: Option<usize>,
+    image: Option<String>,
+    mapping: Option<String>,
+    filter: Option<String>,
+    wrap: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MaterialRef {
+    Named(String),
+    Inline(Box<MaterialDef>),
+}
+
+// a transform step is either the name of a defined transform or an operation
+// such as ["translate", 1, 0, 0]
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+enum StepDef {
+    Named(String),
+    Op(Vec<toml::Value>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObjectDef {
+    shape: Spanned<String>,
+    // the obj file of a mesh
+    file: Option<String>,
+    material: Option<Spanned<MaterialRef>>,
+    #[serde(default)]
+    transform: Vec<Spanned<StepDef>>,
+    // poses the object moves through, each applied after transform
+    #[serde(default)]
+    keyframes: Vec<KeyframeDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDef {
+    time: f64,
+    #[serde(default)]
+    transform: Vec<Spanned<StepDef>>,
+}
+
+fn vector(v: [f64; 3]) -> Vector {
+    Vector::new(v[0], v[1], v[2])
+}
+
+fn colour(c: [f64; 3]) -> Colour {
+    Colour::new(c[0], c[1], c[2])
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    def: SceneDef,
+    // files named in the scene are relative to this
+    base: PathBuf,
+    // each texture is only loaded once however many materials use it
+    images: RefCell<HashMap<PathBuf, Arc<Canvas>>>,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, offset: usize, message: String) -> Result<T, SceneError> {
+        let line = self.source[..offset.min(self.source.len())].matches('\n').count() + 1;
+        Err(SceneError { line: Some(line), message })
+    }
+
+    fn material_def(&self, def: &MaterialDef, offset: usize, seen: &mut HashSet<String>) -> Result<Material, SceneError> {
+        let mut m = match def.extend {
+            Some(ref name) => self.named_material(name, offset, seen)?,
+            None => Material::new(),
+        };
+        if let Some(c) = def.colour {
+            m.colour = colour(c);
+            m.pattern = None;
+        }
+        if let Some(ref p) = def.pattern {
+            m.pattern = Some(self.pattern(p, offset)?);
+        }
+        if let Some(ref b) = def.bump {
+            m.bump = Some(Bump { pattern: self.pattern(&b.pattern, offset)?, amount: b.amount.unwrap_or(1.0) });
+        }
+        if let Some(ref n) = def.normal_map {
+            m.normal_map = Some(self.pattern(n, offset)?);
+        }
+        if let Some(ref b) = def.bsdf {
+            m.bsdf = Some(self.bsdf(b, offset)?);
+        }
+        if let Some(e) = def.emission {
+            if e.iter().any(|&c| c < 0.0) {
+                return self.error(offset, format!("emission can't be negative, found {:?}", e));
+            }
+            m.emission = colour(e);
+        }
+        m.ambient = def.ambient.unwrap_or(m.ambient);
+        m.diffuse = def.diffuse.unwrap_or(m.diffuse);
+        m.specular = def.specular.unwrap_or(m.specular);
+        m.shininess = def.shininess.unwrap_or(m.shininess);
+        Ok(m)
+    }
+
+    fn bsdf(&self, def: &BsdfDef, offset: usize) -> Result<Arc<dyn Bsdf>, SceneError> {
+        let roughness = def.roughness.unwrap_or(0.0);
+        if !(0.0..=1.0).contains(&roughness) {
+            return self.error(offset, format!("roughness must be between 0 and 1, found {}", roughness));
+        }
+        Ok(match def.kind.as_str() {
+            "lambert" => Arc::new(Lambert),
+            "conductor" => Arc::new(Conductor { roughness, f0: None }),
+            "dielectric" => {
+                let ior = def.ior.unwrap_or(1.5);
+                if ior <= 0.0 {
+                    return self.error(offset, format!("ior must be greater than zero, found {}", ior));
+                }
+                Arc::new(Dielectric { ior, roughness })
+            },
+            "principled" => Arc::new(Principled {
+                metallic: def.metallic.unwrap_or(0.0).clamp(0.0, 1.0),
+                roughness: def.roughness.unwrap_or(0.5),
+                specular: def.specular.unwrap_or(0.5),
+            }),
+            _ => return self.error(offset, format!("unknown bsdf type `{}`", def.kind)),
+        })
+    }
+
+    fn named_material(&self, name: &str, offset: usize, seen: &mut HashSet<String>) -> Result<Material, SceneError> {
+        let def = match self.def.materials.get(name) {
+            Some(def) => def,
+            None => return self.error(offset, format!("unknown material `{}`", name)),
+        };
+        if !seen.insert(name.to_string()) {
+            return self.error(def.span().start, format!("material `{}` extends itself", name));
+        }
+        let m = self.material_def(def.get_ref(), def.span().start, seen);
+        seen.remove(name);
+        m
+    }
+
+    fn operation(&self, args: &[toml::Value], offset: usize) -> Result<Transformation, SceneError> {
+        let name = match args.first().and_then(|v| v.as_str()) {
+            Some(name) => name,
+            None => return self.error(offset, "transform operations start with their name".to_string()),
+        };
+        let mut n = vec!();
+        for a in &args[1..] {
+            match *a {
+                toml::Value::Integer(i) => n.push(i as f64),
+                toml::Value::Float(f) => n.push(f),
+                _ => return self.error(offset, format!("arguments to `{}` must be numbers", name)),
+            }
+        }
+        let expected = match name {
+            "translate" | "scale" => 3,
+            "rotate-x" | "rotate-y" | "rotate-z" => 1,
+            "shear" => 6,
+            _ => return self.error(offset, format!("unknown transform operation `{}`", name)),
+        };
+        if n.len() != expected {
+            return self.error(offset, format!("`{}` takes {} arguments, found {}", name, expected, n.len()));
+        }
+
+        let t = Transformation::new();
+        match name {
+            "translate" => Ok(t.translate(n[0], n[1], n[2])),
+            "scale" if n.contains(&0.0) => {
+                self.error(offset, "cannot scale by zero".to_string())
+            },
+            "scale" => Ok(t.scale(n[0], n[1], n[2])),
+            "rotate-x" => Ok(t.rotate(n[0], 0.0, 0.0)),
+            "rotate-y" => Ok(t.rotate(0.0, n[0], 0.0)),
+            "rotate-z" => Ok(t.rotate(0.0, 0.0, n[0])),
+            _ => {
+                let (xy, xz, yx, yz, zx, zy) = (n[0], n[1], n[2], n[3], n[4], n[5]);
+                let det = 1.0 - xy * yx - xz * zx - yz * zy + xy * yz * zx + xz * yx * zy;
+                if det.abs() < 1e-12 {
+                    return self.error(offset, "shear is not invertible".to_string());
+                }
+                Ok(t.shear(xy, xz, yx, yz, zx, zy))
+            },
+        }
+    }
+
+    fn step(&self, step: &StepDef, offset: usize, seen: &mut HashSet<String>) -> Result<Transformation, SceneError> {
+        match *step {
+            StepDef::Named(ref name) => {
+                let def = match self.def.transforms.get(name) {
+                    Some(def) => def,
+                    None => return self.error(offset, format!("unknown transform `{}`", name)),
+                };
+                if !seen.insert(name.clone()) {
+                    return self.error(offset, format!("transform `{}` refers to itself", name));
+                }
+                let s = self.transform(def.get_ref(), seen)?;
+                seen.remove(name);
+                Ok(s)
+            },
+            StepDef::Op(ref args) => self.operation(args, offset),
+        }
+    }
+
+    // steps are applied to the object in the order they are listed
+    fn transform(&self, steps: &[Spanned<StepDef>], seen: &mut HashSet<String>) -> Result<Transformation, SceneError> {
+        let mut t = Transformation::new();
+        for step in steps {
+            t = self.step(step.get_ref(), step.span().start, seen)? * t;
+        }
+        Ok(t)
+    }
+
+    // patterns sit inside materials, so their errors are reported at the
+    // material's line
+    fn pattern(&self, def: &PatternDef, offset: usize) -> Result<Arc<dyn Pattern>, SceneError> {
+        let mut trans = Transformation::new();
+        for step in &def.transform {
+            trans = self.step(step, offset, &mut HashSet::new())? * trans;
+        }
+        let basis = match def.noise.as_deref() {
+            None | Some("perlin") => Basis::Perlin,
+            Some("simplex") => Basis::Simplex,
+            Some(n) => return self.error(offset, format!("unknown noise `{}`", n)),
+        };
+        let noise = Noise::new(def.seed, basis);
+        let octaves = def.octaves.unwrap_or(4);
+        let amount = def.amount.unwrap_or(1.0);
+
+        let mut children = vec!();
+        if let Some(ref c) = def.colours {
+            children.extend(c.iter().map(|&c| solid(colour(c))));
+        }
+        for child in def.patterns.iter().flatten() {
+            children.push(self.pattern(child, offset)?);
+        }
+        let expected = match def.kind.as_str() {
+            "texture" => 0,
+            "perturbed" => 1,
+            _ => 2,
+        };
+        if children.len() != expected {
+            return self.error(offset, format!("{} patterns are made from {} colours or patterns, found {}",
+                                              def.kind, expected, children.len()));
+        }
+        if def.kind == "texture" {
+            return self.texture(def, trans, offset);
+        }
+        let b = children.pop().unwrap_or_else(|| solid(Colour::new(0.0, 0.0, 0.0)));
+        let a = children.pop().unwrap_or_else(|| b.clone());
+
+        let p: Arc<dyn Pattern> = match def.kind.as_str() {
+            "stripes" => Arc::new(Stripe { a, b, trans }),
+            "gradient" => Arc::new(Gradient { a, b, trans }),
+            "rings" => Arc::new(Ring { a, b, trans }),
+            "checkers" => Arc::new(Checkers { a, b, trans }),
+            "blend" => Arc::new(Blend { a, b, trans }),
+            "perturbed" => Arc::new(Perturbed { inner: b, noise, amount, octaves, trans }),
+            "marble" => Arc::new(Marble { a, b, noise, amount, octaves, trans }),
+            "wood" => Arc::new(Wood { a, b, noise, amount, octaves, trans }),
+            "clouds" => Arc::new(Clouds { a, b, noise, amount, octaves, trans }),
+            k => return self.error(offset, format!("unknown pattern `{}`", k)),
+        };
+        Ok(p)
+    }
+
+    fn image(&self, filename: &str, offset: usize) -> Result<Arc<Canvas>, SceneError> {
+        let path = self.base.join(filename);
+        if let Some(image) = self.images.borrow().get(&path) {
+            return Ok(image.clone());
+        }
+        let image = match output::load(&path.to_string_lossy()) {
+            Ok(image) => Arc::new(image),
+            Err(e) => return self.error(offset, format!("unable to load {}: {}", path.display(), e)),
+        };
+        self.images.borrow_mut().insert(path, image.clone());
+        Ok(image)
+    }
+
+    fn texture(&self, def: &PatternDef, trans: Transformation, offset: usize) -> Result<Arc<dyn Pattern>, SceneError> {
+        let image = match def.image {
+            Some(ref filename) => self.image(filename, offset)?,
+            None => return self.error(offset, "texture patterns need an image".to_string()),
+        };
+        let mapping = match def.mapping.as_deref().map_or(Some(Mapping::Object), Mapping::from_name) {
+            Some(m) => m,
+            None => return self.error(offset, format!("unknown mapping `{}`", def.mapping.as_deref().unwrap_or(""))),
+        };
+        let filter = match def.filter.as_deref() {
+            None | Some("bilinear") => TextureFilter::Bilinear,
+            Some("nearest") => TextureFilter::Nearest,
+            Some(f) => return self.error(offset, format!("unknown texture filter `{}`", f)),
+        };
+        let wrap = match def.wrap.as_deref() {
+            None | Some("repeat") => Wrap::Repeat,
+            Some("clamp") => Wrap::Clamp,
+            Some(w) => return self.error(offset, format!("unknown wrap mode `{}`", w)),
+        };
+        Ok(Arc::new(TextureMap { texture: Texture { filter, wrap, ..Texture::new(image) }, mapping, trans }))
+    }
+
+    fn mesh(&self, o: &ObjectDef, material: Material, trans: Transformation, motion: Option<Arc<Motion>>) -> Result<Vec<mesh::Triangle>, SceneError> {
+        let offset = o.shape.span().start;
+        let path = match o.file {
+            Some(ref file) => self.base.join(file),
+            None => return self.error(offset, "meshes need an obj file".to_string()),
+        };
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => return self.error(offset, format!("unable to read {}: {}", path.display(), e)),
+        };
+        let mut triangles = match mesh::parse_obj(&source) {
+            Ok(triangles) => triangles,
+            Err(e) => return self.error(offset, format!("{}: {}", path.display(), e)),
+        };
+        for t in &mut triangles {
+            t.material = material.clone();
+            t.trans = trans;
+            t.motion = motion.clone();
+        }
+        Ok(triangles)
+    }
+
+    fn attenuation(&self, l: &LightDef) -> Result<Attenuation, SceneError> {
+        let a = match l.attenuation {
+            Some(ref a) => a,
+            None => return Ok(Attenuation::none()),
+        };
+        match *a.get_ref() {
+            AttenuationDef::Named(ref name) if name == "none" => Ok(Attenuation::none()),
+            AttenuationDef::Named(ref name) if name == "inverse-square" => Ok(Attenuation::inverse_square()),
+            AttenuationDef::Named(ref name) => self.error(a.span().start, format!("unknown attenuation `{}`", name)),
+            AttenuationDef::Coefficients([constant, linear, quadratic]) => {
+                if constant < 0.0 || linear < 0.0 || quadratic < 0.0 || constant + linear + quadratic <= 0.0 {
+                    return self.error(a.span().start, "attenuation coefficients must be positive".to_string());
+                }
+                Ok(Attenuation { constant, linear, quadratic })
+            },
+        }
+    }
+
+    fn light(&self, spanned: &Spanned<LightDef>) -> Result<Light, SceneError> {
+        let l = spanned.get_ref();
+        let offset = spanned.span().start;
+        let intensity = match l.intensity {
+            IntensityDef::Grey(i) => Colour::new(i, i, i),
+            IntensityDef::Rgb(c) => colour(c),
+        };
+        let kind = l.kind.as_ref().map_or("point", |k| k.get_ref().as_str());
+        let direction = match l.direction {
+            Some(d) if vector(d).norm() > 0.0 => Some(vector(d)),
+            Some(_) => return self.error(offset, "light direction cannot be zero".to_string()),
+            None => None,
+        };
+        let at = match (l.at, kind) {
+            (Some(at), _) => vector(at),
+            (None, "directional") => Vector::new(0.0, 0.0, 0.0),
+            (None, _) => return self.error(offset, format!("{} lights need a position (at)", kind)),
+        };
+
+        let mut light = match (kind, direction) {
+            ("point", _) => Light::new(intensity, at),
+            ("directional", Some(dir)) => Light::directional(intensity, dir),
+            ("spot", Some(dir)) => {
+                let cone = l.cone.unwrap_or(0.5);
+                let falloff = l.falloff.unwrap_or(cone * 0.2);
+                if cone <= 0.0 || falloff < 0.0 {
+                    return self.error(offset, "spot lights need a positive cone angle and falloff".to_string());
+                }
+                Light::spot(intensity, at, dir, cone, falloff)
+            },
+            ("directional", None) | ("spot", None) => {
+                return self.error(offset, format!("{} lights need a direction", kind));
+            },
+            _ => {
+                let offset = l.kind.as_ref().map_or(offset, |k| k.span().start);
+                return self.error(offset, format!("unknown kind of light `{}`", kind));
+            },
+        };
+        let attenuation = self.attenuation(l)?;
+        light.kind = match light.kind {
+            LightKind::Point { .. } => LightKind::Point { attenuation },
+            LightKind::Spot { dir, cone, falloff, .. } => LightKind::Spot { dir, cone, falloff, attenuation },
+            k => k,
+        };
+
+        let shape = match l.shape {
+            Some(ref shape) => shape,
+            None => return Ok(light),
+        };
+        let offset = shape.span().start;
+        light.shape = match shape.get_ref().as_str() {
+            "point" => LightShape::Point,
+            "rect" => match (l.u, l.v) {
+                (Some(u), Some(v)) => LightShape::Rect {
+                    u: vector(u),
+                    v: vector(v),
+                    usteps: l.usteps.unwrap_or(4),
+                    vsteps: l.vsteps.unwrap_or(4),
+                },
+                _ => return self.error(offset, "rect lights need both u and v edges".to_string()),
+            },
+            "sphere" => match l.radius {
+                Some(radius) if radius > 0.0 => LightShape::Sphere { radius, samples: l.samples.unwrap_or(16) },
+                _ => return self.error(offset, "sphere lights need a positive radius".to_string()),
+            },
+            "disc" if kind != "directional" => return self.error(offset, "only directional lights can be disc shaped".to_string()),
+            "disc" => match l.angle {
+                Some(angle) if angle > 0.0 && angle < PI => LightShape::Disc { angle, samples: l.samples.unwrap_or(16) },
+                _ => return self.error(offset, "disc lights need an angle between 0 and pi".to_string()),
+            },
+            s => return self.error(offset, format!("unknown light shape `{}`", s)),
+        };
+        match light.shape {
+            LightShape::Rect { usteps: 0, .. } | LightShape::Rect { vsteps: 0, .. } |
+            LightShape::Sphere { samples: 0, .. } | LightShape::Disc { samples: 0, .. } => {
+                self.error(offset, "area lights need at least one sample".to_string())
+            },
+            _ => Ok(light),
+        }
+    }
+
+    // the scene's animation curves, each checked to have something in the
+    // scene to change
+    fn curves(&self) -> Result<Vec<Curve>, SceneError> {
+        let mut doc: toml::Value = toml::from_str(self.source).map_err(|e| SceneError { line: None, message: e.to_string() })?;
+        let mut curves = vec!();
+        for spanned in &self.def.animation {
+            let (a, offset) = (spanned.get_ref(), spanned.span().start);
+            let interpolation = match a.interpolation {
+                None => Interpolation::Linear,
+                Some(ref name) => match Interpolation::from_name(name) {
+                    Some(i) => i,
+                    None => return self.error(offset, format!("unknown interpolation `{}`", name)),
+                },
+            };
+            let keys: Vec<(f64, toml::Value)> = a.keys.iter().map(|k| (k.frame, k.value.clone())).collect();
+            let curve = match Curve::new(&a.target, &keys, interpolation) {
+                Ok(curve) => curve,
+                Err(e) => return self.error(offset, format!("animating `{}`: {}", a.target, e)),
+            };
+            if let Err(e) = curve.apply(&mut doc, keys[0].0) {
+                return self.error(offset, e);
+            }
+            curves.push(curve);
+        }
+        Ok(curves)
+    }
+
+    fn environment(&self, spanned: &Spanned<EnvironmentDef>) -> Result<Environment, SceneError> {
+        let e = spanned.get_ref();
+        let offset = spanned.span().start;
+        let background = match (e.colour, e.bottom, e.top, e.image.as_ref()) {
+            (Some(c), None, None, None) => Background::Solid(colour(c)),
+            (None, Some(bottom), Some(top), None) => Background::Gradient { bottom: colour(bottom), top: colour(top) },
+            (None, None, None, Some(filename)) => Background::Map(Arc::new(EnvironmentMap::new(self.image(filename, offset)?))),
+            _ => return self.error(offset, "environments need one of a colour, both bottom and top, or an image".to_string()),
+        };
+        let intensity = e.intensity.unwrap_or(1.0);
+        if intensity < 0.0 {
+            return self.error(offset, format!("environment intensity can't be negative, found {}", intensity));
+        }
+        let trans = self.transform(&e.transform, &mut HashSet::new())?;
+        // the axes must stay the same length and at right angles
+        let axes = [Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 1.0, 0.0), Vector::new(0.0, 0.0, 1.0)].map(|a| trans.direction(a));
+        let rotation = (0..3).all(|i| (0..3).all(|j| (axes[i].dot(&axes[j]) - if i == j { 1.0 } else { 0.0 }).abs() < 1e-9));
+        if !rotation {
+            return self.error(offset, "environments can only be rotated".to_string());
+        }
+        Ok(Environment { background, intensity, trans })
+    }
+
+    fn sky(&self, spanned: &Spanned<SkyDef>) -> Result<(Environment, Light), SceneError> {
+        let def = spanned.get_ref();
+        let offset = spanned.span().start;
+        let sun = vector(def.sun);
+        if sun.norm() == 0.0 || sun.normalize().y() <= 0.0 {
+            return self.error(offset, "the sun must be above the horizon".to_string());
+        }
+        let turbidity = def.turbidity.unwrap_or(3.0);
+        if !(2.0..=10.0).contains(&turbidity) {
+            return self.error(offset, format!("turbidity must be between 2 and 10, found {}", turbidity));
+        }
+        let ground_albedo = def.ground_albedo.unwrap_or(0.3);
+        if !(0.0..=1.0).contains(&ground_albedo) {
+            return self.error(offset, format!("ground albedo must be between 0 and 1, found {}", ground_albedo));
+        }
+        let angle = def.sun_angle.unwrap_or(SUN_ANGLE);
+        let samples = def.sun_samples.unwrap_or(16);
+        if angle <= 0.0 || samples == 0 {
+            return self.error(offset, "the sun needs a positive angle and at least one sample".to_string());
+        }
+        let intensity = def.intensity.unwrap_or(1.0);
+        if intensity < 0.0 {
+            return self.error(offset, format!("sky intensity can't be negative, found {}", intensity));
+        }
+
+        let sky = Sky::new(sun, turbidity, ground_albedo);
+        let mut light = sky.sun_light(angle, samples);
+        light.intensity = light.intensity * intensity;
+        Ok((Environment { background: Background::Sky(sky), intensity, ..Environment::new() }, light))
+    }
+
+    fn scene(&self) -> Result<Scene, SceneError> {
+        self.curves()?;
+        let c = &self.def.camera;
+        if c.width == 0 || c.height == 0 {
+            return Err(SceneError { line: None, message: "camera width and height must be positive".to_string() });
+        }
+        let camera_error = |message: &str| Err(SceneError { line: None, message: message.to_string() });
+        let field_of_view = |kind: &str| match c.field_of_view {
+            Some(angle) if angle > 0.0 => Ok(angle),
+            Some(_) => Err(SceneError { line: None, message: "the camera's field of view must be positive".to_string() }),
+            None => Err(SceneError { line: None, message: format!("{} cameras need a field-of-view", kind) }),
+        };
+        let mut projection: Arc<dyn Projection> = match c.projection.as_ref().map_or("perspective", |p| p.as_str()) {
+            // a pinhole sees less than half of everything around it; only
+            // fisheyes go wider
+            "perspective" => match field_of_view("perspective")? {
+                angle if angle < PI => Arc::new(Perspective { field_of_view: angle }),
+                _ => return camera_error("perspective cameras need a field of view under pi"),
+            },
+            "fisheye" => Arc::new(Fisheye { field_of_view: field_of_view("fisheye")? }),
+            "orthographic" => match c.size {
+                Some(size) if size > 0.0 => Arc::new(Orthographic { size }),
+                _ => return camera_error("orthographic cameras need a positive size"),
+            },
+            "equirectangular" => Arc::new(Equirectangular),
+            p => return camera_error(&format!("unknown projection `{}`", p)),
+        };
+        if let Some(ref stereo) = c.stereo {
+            let layout = match stereo.as_str() {
+                "side-by-side" => StereoLayout::SideBySide,
+                "top-bottom" => StereoLayout::TopBottom,
+                s => return camera_error(&format!("unknown stereo layout `{}`", s)),
+            };
+            let separation = c.eye_separation.unwrap_or(DEFAULT_EYE_SEPARATION);
+            if separation < 0.0 {
+                return camera_error("the camera's eye separation can't be negative");
+            }
+            projection = Arc::new(Stereo { eye: projection, separation, layout });
+        } else if c.eye_separation.is_some() {
+            return camera_error("eye-separation is only for stereo cameras");
+        }
+        let mut camera = Camera::new(c.width, c.height, 1.0)
+                             .look_at(vector(c.from), vector(c.to), vector(c.up));
+        camera.projection = projection;
+        if c.aperture < 0.0 {
+            return camera_error("the camera's aperture can't be negative");
+        }
+        camera.aperture_radius = c.aperture;
+        camera.focal_distance = c.focal_distance.unwrap_or_else(|| (vector(c.to) - vector(c.from)).norm());
+        if camera.focal_distance <= 0.0 {
+            return camera_error("the camera's focal distance must be positive");
+        }
+        camera.aperture = match c.blades {
+            None => Aperture::Disc,
+            Some(count) if count >= 3 => Aperture::Blades { count, rotation: c.blade_rotation },
+            Some(_) => return camera_error("apertures need at least 3 blades"),
+        };
+        if c.shutter_close < c.shutter_open {
+            return camera_error("the camera's shutter can't close before it opens");
+        }
+        camera.shutter_open = c.shutter_open;
+        camera.shutter_close = c.shutter_close;
+
+        let mut world = World::new();
+        for l in &self.def.lights {
+            world.lights.push(self.light(l)?);
+        }
+        match (&self.def.environment, &self.def.sky) {
+            (Some(_), Some(sky)) => return self.error(sky.span().start, "a scene can have an environment or a sky, not both".to_string()),
+            (Some(e), None) => world.environment = self.environment(e)?,
+            (None, Some(sky)) => {
+                let (environment, sun) = self.sky(sky)?;
+                world.environment = environment;
+                world.lights.push(sun);
+            },
+            (None, None) => {},
+        }
+
+        for (name, steps) in &self.def.transforms {
+            let mut seen = HashSet::new();
+            seen.insert(name.clone());
+            self.transform(steps.get_ref(), &mut seen)?;
+        }
+        for name in self.def.materials.keys() {
+            self.named_material(name, 0, &mut HashSet::new())?;
+        }
+
+        // named materials are numbered in order of name from 1, followed by
+        // those written out in objects, in order
+        let mut inline_ids = self.def.materials.len() + 1..;
+        for (id, o) in self.def.objects.iter().enumerate() {
+            let material = match o.material {
+                Some(ref m) => match *m.get_ref() {
+                    MaterialRef::Named(ref name) => Material {
+                        id: self.def.materials.keys().position(|n| n == name).unwrap_or(0) + 1,
+                        ..self.named_material(name, m.span().start, &mut HashSet::new())?
+                    },
+                    MaterialRef::Inline(ref def) => Material {
+                        id: inline_ids.next().unwrap(),
+                        ..self.material_def(def, m.span().start, &mut HashSet::new())?
+                    },
+                },
+                None => Material::new(),
+            };
+            let mut trans = self.transform(&o.transform, &mut HashSet::new())?;
+            let mut keys = vec!();
+            for k in &o.keyframes {
+                keys.push((k.time, self.transform(&k.transform, &mut HashSet::new())? * trans));
+            }
+            // objects are where their first keyframe puts them when still
+            let motion = if keys.is_empty() {
+                None
+            } else {
+                Some(Arc::new(Motion::new(&keys).or_else(|e| self.error(o.shape.span().start, e))?))
+            };
+            if let Some(ref m) = motion {
+                trans = m.at(f64::NEG_INFINITY);
+            }
+
+            match o.shape.get_ref().as_str() {
+                "sphere" => world.objects.push(Box::new(Sphere { material, trans, motion })),
+                "plane" => world.objects.push(Box::new(Plane { material, trans, motion })),
+                "mesh" => for t in self.mesh(o, material, trans, motion)? {
+                    world.objects.push(Box::new(t));
+                },
+                s => return self.error(o.shape.span().start, format!("unknown shape `{}`", s)),
+            }
+            // each object in the file has its own id, shared by a mesh's triangles
+            world.object_ids.resize(world.objects.len(), id + 1);
+        }
+
+        Ok(Scene { camera, world })
+    }
+}
+
+// files the scene refers to, such as textures and meshes, are found relative
+// to base
+fn parser<'a>(source: &'a str, base: &Path) -> Result<Parser<'a>, SceneError> {
+    let def: SceneDef = toml::from_str(source).map_err(|e| {
+        let line = e.span().map(|s| source[..s.start].matches('\n').count() + 1);
+        SceneError { line, message: e.message().to_string() }
+    })?;
+    Ok(Parser { source, def, base: base.to_path_buf(), images: RefCell::new(HashMap::new()) })
+}
+
+fn parse_in(source: &str, base: &Path) -> Result<Scene, SceneError> {
+    parser(source, base)?.scene()
+}
+
+// The scene at frame of its animation. Mistakes in the curves are found at
+// their line, but the scene is rebuilt from the animated values, so anything
+// else wrong is reported for the frame; load finds those at their line.
+fn parse_frame_in(source: &str, base: &Path, frame: f64) -> Result<Scene, SceneError> {
+    let p = parser(source, base)?;
+    let curves = p.curves()?;
+    if curves.is_empty() {
+        return p.scene();
+    }
+    let mut doc: toml::Value = toml::from_str(source).map_err(|e| SceneError { line: None, message: e.to_string() })?;
+    for c in &curves {
+        c.apply(&mut doc, frame).map_err(|message| SceneError { line: None, message })?;
+    }
+    let at_frame = |message: String| SceneError { line: None, message: format!("at frame {}: {}", frame, message) };
+    let still = toml::to_string(&doc).map_err(|e| at_frame(e.to_string()))?;
+    parse_in(&still, base).map_err(|e| at_frame(e.message))
+}
+
+fn read(filename: &str) -> Result<String, SceneError> {
+    fs::read_to_string(filename).map_err(|e| {
+        SceneError { line: None, message: format!("unable to read {}: {}", filename, e) }
+    })
+}
+
+// the scene as written, without any animation
+pub fn load(filename: &str) -> Result<Scene, SceneError> {
+    parse_in(&read(filename)?, Path::new(filename).parent().unwrap_or_else(|| Path::new("")))
+}
+
+pub fn load_frame(filename: &str, frame: f64) -> Result<Scene, SceneError> {
+    parse_frame_in(&read(filename)?, Path::new(filename).parent().unwrap_or_else(|| Path::new("")), frame)
+}
+
+#[cfg(test)]
+mod scene_tests {
+    use super::*;
+    use approx::abs_diff_eq;
+    use ray::{Intersection, Ray};
+
+    const CAMERA: &str = "
+[camera]
+width = 20
+height = 10
+field-of-view = 1.0
+from = [0, 0, -5]
+to = [0, 0, 0]
+";
+
+    fn parse(source: &str) -> Result<Scene, SceneError> {
+        parse_in(source, Path::new(""))
+    }
+
+    fn error_line(source: &str) -> Option<usize> {
+        match parse(source) {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.line,
+        }
+    }
+
+    #[test]
+    fn camera_and_lights() {
+        let source = format!("{}
+[[lights]]
+at = [-10, 10, -10]
+
+[[lights]]
+at = [10, 10, -10]
+intensity = 0.5
+", CAMERA);
+        let scene = parse(&source).unwrap();
+        assert_eq!(scene.camera.hsize, 20);
+        assert_eq!(scene.camera.vsize, 10);
+        assert!(abs_diff_eq!(scene.camera.from, Vector::new(0.0, 0.0, -5.0)));
+        assert_eq!(scene.world.lights.len(), 2);
+        assert_eq!(scene.world.lights[0].intensity, Colour::new(1.0, 1.0, 1.0));
+        assert_eq!(scene.world.lights[1].intensity, Colour::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn lenses() {
+        let scene = parse(CAMERA).unwrap();
+        assert_eq!((scene.camera.aperture_radius, scene.camera.focal_distance), (0.0, 5.0));
+        let lens = "[camera]\nwidth = 20\nheight = 10\nfield-of-view = 1.0\nfrom = [0, 0, -5]\nto = [0, 0, 0]\naperture = 0.1\n";
+        let scene = parse(&format!("{}focal-distance = 3\nblades = 6\nblade-rotation = 0.2\n", lens)).unwrap();
+        assert_eq!((scene.camera.aperture_radius, scene.camera.focal_distance), (0.1, 3.0));
+        assert_eq!(scene.camera.aperture, Aperture::Blades { count: 6, rotation: 0.2 });
+        assert_eq!(parse(&format!("{}blades = 2\n", lens)).err().map(|e| e.message), Some("apertures need at least 3 blades".to_string()));
+        assert!(parse(&format!("{}focal-distance = 0\n", lens)).is_err());
+    }
+
+    #[test]
+    fn projections() {
+        let camera = |keys: &str| parse(&format!("[camera]\nwidth = 20\nheight = 10\nfrom = [0, 0, -5]\nto = [0, 0, 0]\n{}", keys));
+        let message = |keys: &str| camera(keys).err().map(|e| e.message);
+        let projection = |keys: &str| format!("{:?}", camera(keys).unwrap().camera.projection);
+        assert_eq!(projection("field-of-view = 1.0\n"), "Perspective { field_of_view: 1.0 }");
+        assert_eq!(projection("projection = \"orthographic\"\nsize = 4\n"), "Orthographic { size: 4.0 }");
+        assert_eq!(projection("projection = \"fisheye\"\nfield-of-view = 3\n"), "Fisheye { field_of_view: 3.0 }");
+        assert_eq!(projection("projection = \"equirectangular\"\n"), "Equirectangular");
+
+        let stereo = camera("projection = \"equirectangular\"\nstereo = \"top-bottom\"\n").unwrap().camera;
+        let (top, bottom) = (stereo.ray_for_pixel(10.0, 2.5).unwrap(), stereo.ray_for_pixel(10.0, 7.5).unwrap());
+        assert!(abs_diff_eq!(top.origin - bottom.origin, Vector::new(-DEFAULT_EYE_SEPARATION, 0.0, 0.0), epsilon = 1e-12));
+        let stereo = camera("field-of-view = 1.0\nstereo = \"side-by-side\"\neye-separation = 0.5\n").unwrap().camera;
+        let (left, right) = (stereo.ray_for_pixel(5.0, 5.0).unwrap(), stereo.ray_for_pixel(15.0, 5.0).unwrap());
+        assert!(abs_diff_eq!(left.origin - right.origin, Vector::new(-0.5, 0.0, 0.0), epsilon = 1e-12));
+
+        assert_eq!(message(""), Some("perspective cameras need a field-of-view".to_string()));
+        assert_eq!(message("projection = \"fisheye\"\n"), Some("fisheye cameras need a field-of-view".to_string()));
+        assert_eq!(message("field-of-view = 0\n"), Some("the camera's field of view must be positive".to_string()));
+        assert_eq!(message("field-of-view = 3.1416\n"), Some("perspective cameras need a field of view under pi".to_string()));
+        assert!(camera("projection = \"fisheye\"\nfield-of-view = 4\n").is_ok());
+        assert_eq!(message("projection = \"orthographic\"\n"), Some("orthographic cameras need a positive size".to_string()));
+        assert_eq!(message("projection = \"orthographic\"\nsize = -1\n"), Some("orthographic cameras need a positive size".to_string()));
+        assert_eq!(message("projection = \"cylindrical\"\n"), Some("unknown projection `cylindrical`".to_string()));
+        assert_eq!(message("field-of-view = 1.0\nstereo = \"anaglyph\"\n"), Some("unknown stereo layout `anaglyph`".to_string()));
+        assert_eq!(message("field-of-view = 1.0\nstereo = \"top-bottom\"\neye-separation = -1\n"),
+                   Some("the camera's eye separation can't be negative".to_string()));
+        assert_eq!(message("field-of-view = 1.0\neye-separation = 0.1\n"), Some("eye-separation is only for stereo cameras".to_string()));
+    }
+
+    #[test]
+    fn light_kinds() {
+        let source = format!("{}
+[[lights]]
+kind = \"directional\"
+direction = [0, -1, 0]
+intensity = [1, 0.9, 0.8]
+
+[[lights]]
+kind = \"spot\"
+at = [0, 5, 0]
+direction = [0, -1, 0]
+cone = 0.4
+attenuation = \"inverse-square\"
+
+[[lights]]
+at = [0, 5, 0]
+attenuation = [1, 0.1, 0.01]
+", CAMERA);
+        let scene = parse(&source).unwrap();
+        assert_eq!(scene.world.lights[0].intensity, Colour::new(1.0, 0.9, 0.8));
+        match scene.world.lights[0].kind {
+            LightKind::Directional { dir } => assert_eq!(dir, Vector::new(0.0, -1.0, 0.0)),
+            k => panic!("unexpected kind {:?}", k),
+        }
+        match scene.world.lights[1].kind {
+            LightKind::Spot { cone, attenuation, .. } => {
+                assert_eq!(cone, 0.4);
+                assert_eq!(attenuation, Attenuation::inverse_square());
+            },
+            k => panic!("unexpected kind {:?}", k),
+        }
+        match scene.world.lights[2].kind {
+            LightKind::Point { attenuation } => assert_eq!(attenuation.quadratic, 0.01),
+            k => panic!("unexpected kind {:?}", k),
+        }
+
+        assert_eq!(error_line(&format!("{}\n[[lights]]\nkind = \"spot\"\nat = [0, 0, 0]\n", CAMERA)), Some(9));
+        assert_eq!(error_line(&format!("{}\n[[lights]]\nkind = \"laser\"\nat = [0, 0, 0]\n", CAMERA)), Some(10));
+        assert_eq!(error_line(&format!("{}\n[[lights]]\nat = [0, 0, 0]\nattenuation = \"cubic\"\n", CAMERA)), Some(11));
+    }
+
+    #[test]
+    fn area_lights() {
+        let source = format!("{}
+[[lights]]
+shape = \"rect\"
+at = [-1, 2, 4]
+u = [2, 0, 0]
+v = [0, 2, 0]
+usteps = 4
+vsteps = 2
+
+[[lights]]
+shape = \"sphere\"
+at = [0, 5, 0]
+radius = 0.5
+", CAMERA);
+        let scene = parse(&source).unwrap();
+        match scene.world.lights[0].shape {
+            LightShape::Rect { usteps: 4, vsteps: 2, .. } => {},
+            s => panic!("unexpected shape {:?}", s),
+        }
+        match scene.world.lights[1].shape {
+            LightShape::Sphere { radius, samples: 16 } => assert_eq!(radius, 0.5),
+            s => panic!("unexpected shape {:?}", s),
+        }
+
+        assert_eq!(error_line(&format!("{}\n[[lights]]\nat = [0, 0, 0]\nshape = \"rect\"\n", CAMERA)), Some(11));
+        assert_eq!(error_line(&format!("{}\n[[lights]]\nat = [0, 0, 0]\nshape = \"sphere\"\nradius = 1\nsamples = 0\n", CAMERA)), Some(11));
+    }
+
+    #[test]
+    fn materials_extend() {
+        let source = format!("{}
+[materials.base]
+colour = [1, 0, 0]
+diffuse = 0.5
+
+[materials.shiny]
+extend = \"base\"
+specular = 1.0
+
+[[objects]]
+shape = \"sphere\"
+material = \"shiny\"
+
+[[objects]]
+shape = \"sphere\"
+material = {{ extend = \"shiny\", ambient = 0.3 }}
+", CAMERA);
+        let scene = parse(&source).unwrap();
+        let m = scene.world.objects[0].material();
+        assert_eq!(m.colour, Colour::new(1.0, 0.0, 0.0));
+        assert_eq!(m.diffuse, 0.5);
+        assert_eq!(m.specular, 1.0);
+        assert_eq!(m.ambient, 0.1);
+        assert_eq!(scene.world.objects[1].material().ambient, 0.3);
+        assert_eq!(scene.world.objects[1].material().diffuse, 0.5);
+    }
+
+    #[test]
+    fn patterns() {
+        let source = format!("{}
+[materials.floor]
+pattern = {{ type = \"checkers\", colours = [[1, 1, 1], [0, 0, 0]], transform = [[\"scale\", 0.5, 0.5, 0.5]] }}
+
+[materials.plain]
+extend = \"floor\"
+colour = [0, 1, 0]
+
+[[objects]]
+shape = \"sphere\"
+material = \"floor\"
+
+[[objects]]
+shape = \"sphere\"
+material = \"plain\"
+
+[[objects]]
+shape = \"sphere\"
+material = {{ pattern = {{ type = \"blend\", patterns = [
+    {{ type = \"stripes\", colours = [[1, 0, 0], [0, 0, 1]] }},
+    {{ type = \"rings\", colours = [[1, 1, 1], [1, 1, 1]], transform = [[\"rotate-x\", 1.5708]] }},
+] }} }}
+", CAMERA);
+        let scene = parse(&source).unwrap();
+        let floor = scene.world.objects[0].material();
+        assert_eq!(floor.colour_at(Vector::new(0.25, 0.0, 0.0), (0.0, 0.0)), Colour::new(1.0, 1.0, 1.0));
+        assert_eq!(floor.colour_at(Vector::new(0.75, 0.0, 0.0), (0.0, 0.0)), Colour::new(0.0, 0.0, 0.0));
+        // setting a colour replaces an inherited pattern
+        assert_eq!(scene.world.objects[1].material().colour_at(Vector::new(0.75, 0.0, 0.0), (0.0, 0.0)), Colour::new(0.0, 1.0, 0.0));
+        let blend = scene.world.objects[2].material();
+        assert_eq!(blend.colour_at(Vector::new(0.5, 0.0, 0.0), (0.0, 0.0)), Colour::new(1.0, 0.5, 0.5));
+
+        assert_eq!(error_line(&format!("{}\n[materials.a]\npattern = {{ type = \"spots\", colours = [[1, 1, 1], [0, 0, 0]] }}\n", CAMERA)), Some(9));
+        assert_eq!(error_line(&format!("{}\n[materials.a]\npattern = {{ type = \"stripes\", colours = [[1, 1, 1]] }}\n", CAMERA)), Some(9));
+    }
+
+    #[test]
+    fn noise_patterns() {
+        let scene = |seed: u64| parse(&format!("{}
+[[objects]]
+shape = \"sphere\"
+material = {{ pattern = {{ type = \"perturbed\", seed = {}, amount = 0.4, patterns = [
+    {{ type = \"marble\", noise = \"simplex\", seed = 3, colours = [[1, 1, 1], [0.2, 0.2, 0.3]] }},
+] }} }}
+", CAMERA, seed)).unwrap();
+        let points: Vec<Vector> = (0..50).map(|i| Vector::new(i as f64 * 0.07, 0.2, 0.4)).collect();
+        let colours = |s: &Scene| -> Vec<Colour> {
+            points.iter().map(|&p| s.world.objects[0].material().colour_at(p, (0.0, 0.0))).collect()
+        };
+        assert_eq!(colours(&scene(1)), colours(&scene(1)));
+        assert!(colours(&scene(1)) != colours(&scene(2)));
+
+        assert_eq!(error_line(&format!("{}\n[materials.a]\npattern = {{ type = \"clouds\", noise = \"worley\", colours = [[1, 1, 1], [0, 0, 0]] }}\n", CAMERA)), Some(9));
+        assert_eq!(error_line(&format!("{}\n[materials.a]\npattern = {{ type = \"perturbed\", colours = [[1, 1, 1], [0, 0, 0]] }}\n", CAMERA)), Some(9));
+    }
+
+    #[test]
+    fn textures_and_meshes() {
+        let dir = std::env::temp_dir().join(format!("ray_tracer_scene_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("label.ppm"), "P3\n2 1\n255\n255 0 0  0 0 255\n").unwrap();
+        fs::write(dir.join("quad.obj"), "v -1 0 -1\nv 1 0 -1\nv 1 0 1\nv -1 0 1\n\
+                                        vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n").unwrap();
+        let source = format!("{}
+[materials.label]
+pattern = {{ type = \"texture\", image = \"label.ppm\", filter = \"nearest\" }}
+
+[[objects]]
+shape = \"plane\"
+material = {{ pattern = {{ type = \"texture\", image = \"label.ppm\", mapping = \"planar\", wrap = \"clamp\" }} }}
+
+[[objects]]
+shape = \"mesh\"
+file = \"quad.obj\"
+material = \"label\"
+transform = [[\"translate\", 0, 1, 0]]
+", CAMERA);
+        let scene = parse_in(&source, &dir).unwrap();
+        assert_eq!(scene.world.objects.len(), 3);
+        let down = |x: f64| Ray::new(Vector::new(x, 5.0, 0.5), Vector::new(0.0, -1.0, 0.0));
+        let hits = scene.world.intersect(down(-0.5));
+        assert_eq!(hits.iter().map(|i| i.t).collect::<Vec<f64>>(), vec!(4.0, 5.0));
+
+        // the mesh takes its colour from its vt coordinates
+        let colour = |i: &Intersection, p: Vector| {
+            let object_point = i.object.transformation().inverse().point(p);
+            i.object.material().colour_at(object_point, i.object.uv(object_point))
+        };
+        let left = &scene.world.intersect(down(-0.5))[0];
+        assert_eq!(colour(left, Vector::new(-0.5, 1.0, 0.5)), Colour::new(1.0, 0.0, 0.0));
+        let right = &scene.world.intersect(down(0.5))[0];
+        assert_eq!(colour(right, Vector::new(0.5, 1.0, 0.5)), Colour::new(0.0, 0.0, 1.0));
+        // the plane maps x across the image, clamped at the edges
+        let floor = &scene.world.intersect(down(0.1))[1];
+        assert_eq!(colour(floor, Vector::new(0.1, 0.0, 0.5)), Colour::new(1.0, 0.0, 0.0));
+        let floor = &scene.world.intersect(down(-0.1))[1];
+        assert_eq!(colour(floor, Vector::new(-0.1, 0.0, 0.5)), Colour::new(0.0, 0.0, 1.0));
+
+        // the mesh's triangles are one object, with the named material first
+        let ids: Vec<(usize, usize)> = scene.world.objects.iter().map(|o| (scene.world.object_id(&**o), o.material().id)).collect();
+        assert_eq!(ids, vec!((1, 2), (2, 1), (2, 1)));
+
+        let error = |s: &str| parse_in(&format!("{}\n{}", CAMERA, s), &dir).err().and_then(|e| e.line);
+        assert_eq!(error("[[objects]]\nshape = \"mesh\"\nfile = \"missing.obj\"\n"), Some(10));
+        assert_eq!(error("[materials.a]\npattern = { type = \"texture\", image = \"missing.png\" }\n"), Some(9));
+        assert_eq!(error("[materials.a]\npattern = { type = \"texture\", image = \"label.ppm\", mapping = \"toroidal\" }\n"), Some(9));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bsdfs() {
+        let source = format!("{}
+[materials.water]
+colour = [0.8, 0.9, 1.0]
+bsdf = {{ type = \"dielectric\", ior = 1.33 }}
+
+[materials.gold]
+colour = [1.0, 0.8, 0.3]
+bsdf = {{ type = \"principled\", metallic = 1, roughness = 0.2 }}
+
+[[objects]]
+shape = \"sphere\"
+material = \"water\"
+
+[[objects]]
+shape = \"sphere\"
+material = {{ extend = \"gold\", specular = 0.5 }}
+
+[[objects]]
+shape = \"sphere\"
+", CAMERA);
+        let scene = parse(&source).unwrap();
+        let bsdf = |i: usize| scene.world.objects[i].material().bsdf.as_ref().map(|b| format!("{:?}", b));
+        assert_eq!(bsdf(0), Some("Dielectric { ior: 1.33, roughness: 0.0 }".to_string()));
+        assert_eq!(bsdf(1), Some("Principled { metallic: 1.0, roughness: 0.2, specular: 0.5 }".to_string()));
+        assert_eq!(bsdf(2), None);
+
+        let error = |s: &str| parse(&format!("{}\n{}", CAMERA, s)).err().map(|e| (e.line, e.message));
+        assert_eq!(error("[materials.a]\nbsdf = { type = \"velvet\" }\n"), Some((Some(9), "unknown bsdf type `velvet`".to_string())));
+        assert_eq!(error("[materials.a]\nbsdf = { type = \"conductor\", roughness = 2 }\n"),
+                   Some((Some(9), "roughness must be between 0 and 1, found 2".to_string())));
+        assert!(error("[materials.a]\nbsdf = { type = \"dielectric\", ior = 0 }\n").is_some());
+    }
+
+    #[test]
+    fn environments() {
+        let parsed = |s: &str| parse(&format!("{}\n{}", CAMERA, s)).map(|scene| format!("{:?}", scene.world.environment.background));
+        assert_eq!(parsed("").unwrap(), "Solid(Colour { r: 0.0, g: 0.0, b: 0.0 })");
+        assert_eq!(parsed("[environment]\ncolour = [0.5, 0.5, 1]\n").unwrap(), "Solid(Colour { r: 0.5, g: 0.5, b: 1.0 })");
+
+        let scene = parse(&format!("{}\n[environment]\nbottom = [0, 0, 0]\ntop = [0, 0, 1]\nintensity = 2\ntransform = [[\"rotate-z\", 3.14159265358979]]\n", CAMERA)).unwrap();
+        // turned upside down
+        assert_eq!(scene.world.environment.radiance(Vector::new(0.0, 1.0, 0.0)), Colour::new(0.0, 0.0, 0.0));
+        assert!((scene.world.environment.radiance(Vector::new(0.0, -1.0, 0.0)).b - 2.0).abs() < 1e-9);
+
+        // images are found relative to the scene
+        let dir = std::env::temp_dir().join(format!("ray_tracer_environment_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut image = Canvas::new(2, 1);
+        image.write(0, 0, Colour::new(4.0, 4.0, 4.0));
+        fs::write(dir.join("sky.pfm"), output::pfm_bytes(&image)).unwrap();
+        let scene = parse_in(&format!("{}\n[environment]\nimage = \"sky.pfm\"\n", CAMERA), &dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(scene.unwrap().world.environment.radiance(Vector::new(-1.0, 0.0, 0.0)), Colour::new(4.0, 4.0, 4.0));
+
+        let error = |s: &str| parse(&format!("{}\n{}", CAMERA, s)).err().map(|e| (e.line, e.message));
+        assert_eq!(error("[environment]\ncolour = [1, 1, 1]\ntop = [1, 1, 1]\n"),
+                   Some((Some(9), "environments need one of a colour, both bottom and top, or an image".to_string())));
+        assert_eq!(error("[environment]\ncolour = [1, 1, 1]\ntransform = [[\"scale\", 2, 1, 1]]\n"),
+                   Some((Some(9), "environments can only be rotated".to_string())));
+        assert!(error("[environment]\nimage = \"missing.hdr\"\n").is_some());
+    }
+
+    #[test]
+    fn skies() {
+        let source = format!("{}
+[sky]
+sun = [1, 1, 0]
+turbidity = 4
+sun-samples = 4
+
+[[lights]]
+kind = \"directional\"
+direction = [0, -1, 0]
+shape = \"disc\"
+angle = 0.1
+", CAMERA);
+        let scene = parse(&source).unwrap();
+        // the sun comes after the scene's own lights
+        assert_eq!(scene.world.lights.len(), 2);
+        match scene.world.lights[0].shape {
+            LightShape::Disc { angle, samples } => assert_eq!((angle, samples), (0.1, 16)),
+            s => panic!("expected a disc light, found {:?}", s),
+        }
+        let sun = scene.world.lights[1];
+        let sky = Sky::new(Vector::new(1.0, 1.0, 0.0), 4.0, 0.3);
+        assert_eq!(sun.intensity, sky.sun_intensity());
+        let directions = sun.directions(None);
+        assert_eq!(directions.len(), 4);
+        assert!(directions.iter().all(|d| (*d - Vector::new(1.0, 1.0, 0.0).normalize()).norm() < SUN_ANGLE));
+        let up = Vector::new(0.0, 1.0, 0.0);
+        assert_eq!(scene.world.environment.radiance(up), sky.radiance(up));
+
+        let error = |s: &str| parse(&format!("{}\n{}", CAMERA, s)).err().map(|e| (e.line, e.message));
+        assert_eq!(error("[sky]\nsun = [0, -1, 1]\n"), Some((Some(9), "the sun must be above the horizon".to_string())));
+        assert_eq!(error("[sky]\nsun = [0, 1, 0]\nturbidity = 1\n"), Some((Some(9), "turbidity must be between 2 and 10, found 1".to_string())));
+        assert_eq!(error("[sky]\nsun = [0, 1, 0]\n[environment]\ncolour = [1, 1, 1]\n"),
+                   Some((Some(9), "a scene can have an environment or a sky, not both".to_string())));
+        assert_eq!(error("[[lights]]\nat = [0, 0, 0]\nshape = \"disc\"\nangle = 0.1\n"),
+                   Some((Some(11), "only directional lights can be disc shaped".to_string())));
+    }
+
+    #[test]
+    fn emission() {
+        let source = format!("{}
+[[objects]]
+shape = \"sphere\"
+material = {{ colour = [0, 0, 0], emission = [4, 4, 2] }}
+
+[[objects]]
+shape = \"plane\"
+", CAMERA);
+        let scene = parse(&source).unwrap();
+        assert_eq!(scene.world.objects[0].material().emission, Colour::new(4.0, 4.0, 2.0));
+        assert_eq!(scene.world.objects[1].material().emission, Colour::new(0.0, 0.0, 0.0));
+        // registered as the only light to sample
+        assert_eq!(scene.world.pick_emitter(0.9).map(|(_, chance)| chance), Some(1.0));
+
+        let error = parse(&format!("{}\n[materials.a]\nemission = [1, -1, 0]\n", CAMERA)).err().map(|e| (e.line, e.message));
+        assert_eq!(error, Some((Some(9), "emission can't be negative, found [1.0, -1.0, 0.0]".to_string())));
+    }
+
+    #[test]
+    fn keyframes() {
+        let source = format!("{}shutter-open = 0.2
+shutter-close = 0.6
+
+[[objects]]
+shape = \"sphere\"
+transform = [[\"scale\", 0.5, 0.5, 0.5]]
+[[objects.keyframes]]
+time = 1
+transform = [[\"translate\", 4, 0, 0]]
+[[objects.keyframes]]
+time = 0
+", CAMERA);
+        let scene = parse(&source).unwrap();
+        assert_eq!((scene.camera.shutter_open, scene.camera.shutter_close), (0.2, 0.6));
+        // the sphere starts at the origin, keeping its own transform in every
+        // keyframe, and moves along x
+        let across = |x: f64, time: f64| scene.world.intersect(Ray::new(Vector::new(x, 0.0, -5.0), Vector::new(0.0, 0.0, 1.0)).with_time(time));
+        assert_eq!(across(0.0, 0.0).iter().map(|i| i.t).collect::<Vec<f64>>(), vec!(4.5, 5.5));
+        assert!(across(0.7, 0.0).is_empty());
+        assert!(across(0.0, 0.5).is_empty());
+        assert_eq!(across(2.0, 0.5).len(), 2);
+        assert_eq!(across(4.0, 3.0).len(), 2);
+        assert!(abs_diff_eq!(scene.world.objects[0].transformation().point(Vector::new(1.0, 0.0, 0.0)), Vector::new(0.5, 0.0, 0.0), epsilon = 1e-9));
+
+        let error = parse(&format!("{}shutter-open = 1\nshutter-close = 0.5\n", CAMERA)).err().map(|e| e.message);
+        assert_eq!(error, Some("the camera's shutter can't close before it opens".to_string()));
+
+        let flipping = format!("{}\n[[objects]]\nshape = \"sphere\"\n[[objects.keyframes]]\ntime = 0\n[[objects.keyframes]]\ntime = 1\ntransform = [[\"scale\", -1, 1, 1]]\n", CAMERA);
+        let error = parse(&flipping).err().map(|e| (e.line, e.message));
+        assert_eq!(error, Some((Some(10), "the keyframes at 0 and 1 can't be blended, as only one of them is mirrored".to_string())));
+    }
+
+    #[test]
+    fn animation() {
+        let source = format!("{}
+[[lights]]
+at = [0, 10, 0]
+
+[materials.paint]
+colour = [1, 0, 0]
+
+[[objects]]
+shape = \"sphere\"
+material = \"paint\"
+transform = [[\"translate\", 0, 0, 0]]
+
+[[animation]]
+target = \"camera.from\"
+keys = [{{ frame = 1, value = [0, 0, -5] }}, {{ frame = 11, value = [0, 0, -10] }}]
+
+[[animation]]
+target = \"lights.0.at.0\"
+interpolation = \"step\"
+keys = [{{ frame = 1, value = 0 }}, {{ frame = 5, value = -4 }}]
+
+[[animation]]
+target = \"materials.paint.diffuse\"
+interpolation = \"bezier\"
+keys = [{{ frame = 1, value = 0.9 }}, {{ frame = 3, value = 0.5 }}]
+
+[[animation]]
+target = \"objects.0.transform.0\"
+keys = [{{ frame = 1, value = [\"translate\", 0, 0, 0] }}, {{ frame = 3, value = [\"translate\", 2, 0, 0] }}]
+", CAMERA);
+        // as written, nothing moves
+        let still = parse(&source).unwrap();
+        assert!(abs_diff_eq!(still.camera.from, Vector::new(0.0, 0.0, -5.0)));
+        assert_eq!(still.world.objects[0].material().diffuse, Material::new().diffuse);
+
+        let frame = |f: f64| parse_frame_in(&source, Path::new(""), f).unwrap();
+        let (first, middle, last) = (frame(1.0), frame(6.0), frame(20.0));
+        assert!(abs_diff_eq!(first.camera.from, Vector::new(0.0, 0.0, -5.0)));
+        assert!(abs_diff_eq!(middle.camera.from, Vector::new(0.0, 0.0, -7.5), epsilon = 1e-12));
+        assert!(abs_diff_eq!(last.camera.from, Vector::new(0.0, 0.0, -10.0)));
+        assert_eq!((first.world.lights[0].pos.x(), frame(4.9).world.lights[0].pos.x(), middle.world.lights[0].pos.x()), (0.0, 0.0, -4.0));
+        assert_eq!(first.world.objects[0].material().diffuse, 0.9);
+        assert!((frame(2.0).world.objects[0].material().diffuse - 0.7).abs() < 1e-12);
+        assert_eq!(last.world.objects[0].material().diffuse, 0.5);
+        let centre = |s: &Scene| s.world.objects[0].transformation().point(Vector::new(0.0, 0.0, 0.0));
+        assert!(abs_diff_eq!(centre(&frame(2.0)), Vector::new(1.0, 0.0, 0.0)));
+
+        // curves are checked against the scene at their line, and what they
+        // do at each frame as it's rendered
+        let error = |extra: &str| parse(&format!("{}{}", CAMERA, extra)).err().map(|e| (e.line, e.message));
+        assert_eq!(error("\n[[animation]]\ntarget = \"lights.0.at\"\nkeys = [{ frame = 1, value = [0, 0, 0] }]\n"),
+                   Some((Some(9), "the scene has nothing at `lights.0.at` to animate".to_string())));
+        assert_eq!(error("\n[[animation]]\ntarget = \"camera.from\"\ninterpolation = \"cubic\"\nkeys = [{ frame = 1, value = [0, 0, 0] }]\n"),
+                   Some((Some(9), "unknown interpolation `cubic`".to_string())));
+        assert_eq!(error("\n[[animation]]\ntarget = \"camera.from\"\nkeys = [{ frame = 1, value = [0, 0] }, { frame = 2, value = [0, 0, 0] }]\n"),
+                   Some((Some(9), "animating `camera.from`: keys are arrays of different lengths".to_string())));
+        let shrinking = format!("{}\n[[animation]]\ntarget = \"camera.width\"\nkeys = [{{ frame = 1, value = 20 }}, {{ frame = 3, value = 0 }}]\n", CAMERA);
+        assert!(parse(&shrinking).is_ok());
+        let error = parse_frame_in(&shrinking, Path::new(""), 3.0).err().map(|e| (e.line, e.message));
+        assert_eq!(error, Some((None, "at frame 3: camera width and height must be positive".to_string())));
+    }
+
+    #[test]
+    fn bump_and_normal_maps() {
+        let source = format!("{}
+[materials.ramp]
+bump = {{ pattern = {{ type = \"gradient\", colours = [[0, 0, 0], [1, 1, 1]] }}, amount = 0.5 }}
+
+[materials.tilted]
+extend = \"ramp\"
+normal-map = {{ type = \"stripes\", colours = [[1, 0.5, 0.5], [1, 0.5, 0.5]] }}
+
+[[objects]]
+shape = \"plane\"
+material = \"ramp\"
+
+[[objects]]
+shape = \"plane\"
+material = \"tilted\"
+transform = [[\"translate\", 0, -1, 0]]
+", CAMERA);
+        let scene = parse(&source).unwrap();
+        let close = |a: Vector, b: Vector| (a - b).norm() < 1e-6;
+        let ramp = &scene.world.objects[0];
+        assert!(close(ramp.normal(Vector::new(0.3, 0.0, 0.3), 0.0), Vector::new(-0.5, 1.0, 0.0).normalize()));
+        // the normal map turns the normal to +x before the inherited bump tips it
+        let tilted = &scene.world.objects[1];
+        assert!(close(tilted.normal(Vector::new(0.3, -1.0, 0.3), 0.0), Vector::new(1.0, 0.0, 0.0)));
+
+        let error = |s: &str| parse(&format!("{}\n{}", CAMERA, s)).err().map(|e| (e.line, e.message));
+        assert_eq!(error("[materials.a]\nbump = { pattern = { type = \"spots\" } }\n").map(|e| e.0), Some(Some(9)));
+        assert!(error("[materials.a]\nbump = { amount = 2 }\n").is_some());
+    }
+
+    #[test]
+    fn transforms_apply_in_order() {
+        let source = format!("{}
+[transforms]
+shrink = [[\"scale\", 0.5, 0.5, 0.5]]
+
+[[objects]]
+shape = \"sphere\"
+transform = [\"shrink\", [\"translate\", 0, 0, 2]]
+", CAMERA);
+        let scene = parse(&source).unwrap();
+        let r = Ray::new(Vector::new(0.0, 0.0, -5.0), Vector::new(0.0, 0.0, 1.0));
+        let ts: Vec<f64> = scene.world.intersect(r).iter().map(|i| i.t).collect();
+        assert_eq!(ts, vec!(6.5, 7.5));
+    }
+
+    #[test]
+    fn errors_report_lines() {
+        assert_eq!(error_line("[camera]\nwidth = \"wide\"\n"), Some(2));
+        assert_eq!(error_line(&format!("{}\n[[objects]]\nshape = \"cube\"\n", CAMERA)), Some(10));
+        assert_eq!(error_line(&format!("{}\n[[objects]]\nshape = \"sphere\"\nmaterial = \"missing\"\n", CAMERA)), Some(11));
+        assert_eq!(error_line(&format!("{}\n[[objects]]\nshape = \"sphere\"\ntransform = [[\"spin\", 1]]\n", CAMERA)), Some(11));
+        assert_eq!(error_line(&format!("{}\n[[objects]]\nshape = \"sphere\"\ntransform = [[\"scale\", 1, 0, 1]]\n", CAMERA)), Some(11));
+        assert!(error_line(&format!("{}\n[materials.a]\nextend = \"b\"\n[materials.b]\nextend = \"a\"\n[[objects]]\nshape = \"sphere\"\nmaterial = \"a\"\n", CAMERA)).is_some());
+        assert_eq!(error_line(&format!("{}\n[transforms]\nloop = [\"loop\"]\n", CAMERA)), Some(10));
+    }
+}
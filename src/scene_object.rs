@@ -1,28 +1,212 @@
-use super::transformation::Vector;
+use std::sync::Arc;
 
+use super::transformation::{Motion, Transformation, Vector};
+
+use super::bsdf::Bsdf;
 use super::canvas::Colour;
+use super::pattern::Pattern;
 use super::ray;
 
-pub trait SceneObject {
-    fn intersect(&self,r: ray::Ray) -> Vec<ray::Intersection>;
-    fn normal(&self, p: Vector) -> Vector;
+// how far apart, in object space, bump heights are compared
+const BUMP_STEP: f64 = 1e-4;
+
+pub trait SceneObject: Sync {
+    fn intersect(&self,r: ray::Ray) -> Vec<ray::Intersection<'_>>;
+    // the geometric normal at p, both in object space
+    fn local_normal(&self, p: Vector) -> Vector;
+    // dp/du and dp/dv at p in object space, the directions in which the
+    // texture coordinates increase
+    fn local_tangents(&self, p: Vector) -> (Vector, Vector);
+    fn material(&self) -> &Material;
+    // maps object space to world space, when the object isn't moving
+    fn transformation(&self) -> &Transformation;
+    // the texture coordinate of p, in object space, on the surface
+    fn uv(&self, p: Vector) -> (f64, f64);
+
+    // keyframes that take the place of the transformation, for moving objects
+    fn motion(&self) -> Option<&Motion> {
+        None
+    }
+
+    // maps object space to world space at time
+    fn transformation_at(&self, time: f64) -> Transformation {
+        match self.motion() {
+            Some(motion) => motion.at(time),
+            None => *self.transformation(),
+        }
+    }
+
+    // For objects that can be sampled as lights: a point in world space spread
+    // evenly over the surface, where it is at time, as u and v range over
+    // [0, 1), or None for unbounded surfaces such as planes.
+    fn sample_surface(&self, _u: f64, _v: f64, _time: f64) -> Option<Vector> {
+        None
+    }
+
+    // the probability density, per unit of world space area, of sample_surface
+    // choosing p at time
+    fn surface_pdf(&self, _p: Vector, _time: f64) -> f64 {
+        0.0
+    }
+
+    // the material's colour at p, in world space at time
+    fn colour_at(&self, p: Vector, time: f64) -> Colour {
+        let object_point = self.transformation_at(time).inverse().point(p);
+        self.material().colour_at(object_point, self.uv(object_point))
+    }
+
+    // The shading normal at p, both in world space at time: the geometric
+    // normal bent by the material's normal map and bump map, in that order.
+    fn normal(&self, p: Vector, time: f64) -> Vector {
+        let trans = self.transformation_at(time);
+        let object_point = trans.inverse().point(p);
+        let mut n = self.local_normal(object_point);
+        let m = self.material();
+        if m.normal_map.is_none() && m.bump.is_none() {
+            return trans.normal(n);
+        }
+
+        let (t, b) = tangent_frame(n, self.local_tangents(object_point));
+        if let Some(ref map) = m.normal_map {
+            // colours 0 to 1 stand for components -1 to 1 along t, b and n
+            let c = map.colour_at(object_point, self.uv(object_point));
+            n = (t * (2.0 * c.r - 1.0) + b * (2.0 * c.g - 1.0) + n * (2.0 * c.b - 1.0)).normalize();
+        }
+        if let Some(ref bump) = m.bump {
+            let height = |q: Vector| bump.pattern.colour_at(q, self.uv(q)).luminance();
+            let h = height(object_point);
+            let dt = (height(object_point + t * BUMP_STEP) - h) / BUMP_STEP;
+            let db = (height(object_point + b * BUMP_STEP) - h) / BUMP_STEP;
+            n = (n - (t * dt + b * db) * bump.amount).normalize();
+        }
+        trans.normal(n)
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+// Unit tangent and bitangent perpendicular to n, following dp/du and dp/dv as
+// closely as possible; any frame will do where the surface has no tangents.
+pub fn tangent_frame(n: Vector, (dpdu, dpdv): (Vector, Vector)) -> (Vector, Vector) {
+    let t = dpdu - n * n.dot(&dpdu);
+    if t.norm() < 1e-12 {
+        return n.orthonormal_basis();
+    }
+    let t = t.normalize();
+    let b = n.cross(&t);
+    if b.dot(&dpdv) < 0.0 { (t, -b) } else { (t, b) }
+}
+
+// the luminance of pattern is used as the height of the surface, scaled by
+// amount
+#[derive(Debug, Clone)]
+pub struct Bump {
+    pub pattern: Arc<dyn Pattern>,
+    pub amount: f64,
+}
+
+#[derive(Debug, Clone)]
 pub struct Material {
    pub colour: Colour,
+   // replaces colour when set
+   pub pattern: Option<Arc<dyn Pattern>>,
    pub ambient: f64,
    pub diffuse: f64,
    pub specular: f64,
    pub shininess: f64,
+   pub bump: Option<Bump>,
+   // a tangent space normal map
+   pub normal_map: Option<Arc<dyn Pattern>>,
+   // replaces the phong model when set
+   pub bsdf: Option<Arc<dyn Bsdf>>,
+   // light given off from both sides of the surface, as seen head on
+   pub emission: Colour,
+   // written to the material id output, 0 for materials the scene didn't number
+   pub id: usize,
 }
 
 impl Material {
     pub fn new() -> Material {
         Material{colour: Colour::new(1.0, 1.0, 1.0), 
+                 pattern: None,
                  ambient: 0.1, 
                  diffuse: 0.9, 
                  specular: 0.9, 
-                 shininess: 200.0}
+                 shininess: 200.0,
+                 bump: None,
+                 normal_map: None,
+                 bsdf: None,
+                 emission: Colour::new(0.0, 0.0, 0.0),
+                 id: 0}
+    }
+
+    // p is in object space, uv is the object's texture coordinate there
+    pub fn colour_at(&self, p: Vector, uv: (f64, f64)) -> Colour {
+        match self.pattern {
+            Some(ref pattern) => pattern.colour_at(p, uv),
+            None => self.colour,
+        }
+    }
+}
+
+#[cfg(test)]
+mod scene_object_tests {
+    use super::*;
+    use pattern::{Gradient, solid};
+    use plane::Plane;
+    use sphere::Sphere;
+
+    fn plane() -> Plane {
+        Plane { material: Material::new(), trans: Transformation::new(), motion: None }
+    }
+
+    fn close(a: Vector, b: Vector) -> bool {
+        (a - b).norm() < 1e-6
+    }
+
+    #[test]
+    fn tangent_frames() {
+        let n = Vector::new(0.0, 0.0, -1.0);
+        let (t, b) = tangent_frame(n, (Vector::new(2.0, 0.0, 1.0), Vector::new(0.0, 3.0, 0.0)));
+        assert!(close(t, Vector::new(1.0, 0.0, 0.0)) && close(b, Vector::new(0.0, 1.0, 0.0)));
+        // the bitangent follows dp/dv even when the uvs are mirrored
+        let (_, b) = tangent_frame(n, (Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, -1.0, 0.0)));
+        assert!(close(b, Vector::new(0.0, -1.0, 0.0)));
+        // no tangents still gives a frame
+        let (t, b) = tangent_frame(n, (Vector::new(0.0, 0.0, 0.0), Vector::new(0.0, 0.0, 0.0)));
+        assert!(t.dot(&n).abs() < 1e-12 && b.dot(&n).abs() < 1e-12 && t.dot(&b).abs() < 1e-12);
+    }
+
+    #[test]
+    fn normal_maps() {
+        let mut p = plane();
+        let up = Vector::new(0.0, 1.0, 0.0);
+        p.material.normal_map = Some(solid(Colour::new(0.5, 0.5, 1.0)));
+        assert!(close(p.normal(Vector::new(3.0, 0.0, 1.0), 0.0), up));
+        // pointing along the plane's u direction, which is x
+        p.material.normal_map = Some(solid(Colour::new(1.0, 0.5, 0.5)));
+        assert!(close(p.normal(Vector::new(3.0, 0.0, 1.0), 0.0), Vector::new(1.0, 0.0, 0.0)));
+
+        let mut s = Sphere::new();
+        s.material.normal_map = Some(solid(Colour::new(0.5, 1.0, 0.5)));
+        // up the sphere, towards its north pole
+        assert!(close(s.normal(Vector::new(0.0, 0.0, -1.0), 0.0), up));
+    }
+
+    #[test]
+    fn bumps() {
+        let mut p = plane();
+        let flat = p.normal(Vector::new(0.3, 0.0, 0.3), 0.0);
+        p.material.bump = Some(Bump { pattern: solid(Colour::new(0.5, 0.5, 0.5)), amount: 1.0 });
+        assert!(close(p.normal(Vector::new(0.3, 0.0, 0.3), 0.0), flat));
+
+        // rising along x tips the normal back towards -x
+        let ramp = Gradient { a: solid(Colour::new(0.0, 0.0, 0.0)), b: solid(Colour::new(1.0, 1.0, 1.0)), trans: Transformation::new() };
+        p.material.bump = Some(Bump { pattern: Arc::new(ramp), amount: 1.0 });
+        let n = p.normal(Vector::new(0.3, 0.0, 0.3), 0.0);
+        assert!(close(n, Vector::new(-1.0, 1.0, 0.0).normalize()));
+
+        // follows the object's transformation
+        p.trans = Transformation::new().rotate(0.0, 0.0, std::f64::consts::FRAC_PI_2);
+        let n = p.normal(Vector::new(0.0, 0.3, 0.3), 0.0);
+        assert!(close(n, Vector::new(-1.0, -1.0, 0.0).normalize()));
     }
 }
@@ -0,0 +1,192 @@
+use std::f64::consts::PI;
+
+use super::canvas::Colour;
+use super::light::{Light, LightShape};
+use super::transformation::Vector;
+
+// Preetham, Shirley and Smits' analytic model of a clear daytime sky, "A
+// Practical Analytic Model for Daylight" (1999). Sky luminance is scaled so
+// that 100,000 lux of sunlight, about that of a high summer sun, matches a
+// directional light of intensity 1.
+
+// steps in each direction when adding up the light the sky sheds on the ground
+const GROUND_STEPS: usize = 32;
+
+// wavelengths, in micrometres, standing in for red, green and blue when
+// working out how much of the sun's light gets through the atmosphere
+const WAVELENGTHS: [f64; 3] = [0.68, 0.55, 0.44];
+
+// the sun's illuminance above the atmosphere, in units of 100,000 lux
+const SOLAR_CONSTANT: f64 = 1.28;
+
+// the angle across the sun's disc, in radians
+pub const SUN_ANGLE: f64 = 0.0093;
+
+// Perez et al's formula for how sky luminance varies with theta, the angle
+// from the zenith, and gamma, the angle from the sun
+#[derive(Debug, Clone, Copy)]
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl Perez {
+    fn f(&self, cos_theta: f64, gamma: f64) -> f64 {
+        (1.0 + self.a * (self.b / cos_theta.max(1e-3)).exp()) *
+            (1.0 + self.c * (self.d * gamma).exp() + self.e * gamma.cos() * gamma.cos())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Sky {
+    // towards the sun
+    sun: Vector,
+    turbidity: f64,
+    // luminance and chromaticity for the three channels of the model
+    perez: [Perez; 3],
+    zenith: [f64; 3],
+    ground: Colour,
+}
+
+impl Sky {
+    // Turbidity is how hazy the air is, from 2 for a very clear sky to 10 for
+    // a hazy one, and the ground below the horizon reflects ground_albedo of
+    // the light falling on it. The sun must be above the horizon.
+    pub fn new(sun: Vector, turbidity: f64, ground_albedo: f64) -> Sky {
+        let sun = sun.normalize();
+        let t = turbidity;
+        let theta_s = sun.y().clamp(0.0, 1.0).acos();
+        let perez = [
+            Perez { a: 0.1787 * t - 1.4630, b: -0.3554 * t + 0.4275, c: -0.0227 * t + 5.3251, d: 0.1206 * t - 2.5771, e: -0.0670 * t + 0.3703 },
+            Perez { a: -0.0193 * t - 0.2592, b: -0.0665 * t + 0.0008, c: -0.0004 * t + 0.2125, d: -0.0641 * t - 0.8989, e: -0.0033 * t + 0.0452 },
+            Perez { a: -0.0167 * t - 0.2608, b: -0.0950 * t + 0.0092, c: -0.0079 * t + 0.2102, d: -0.0441 * t - 1.6537, e: -0.0109 * t + 0.0529 },
+        ];
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |c: [f64; 4]| c[0] * theta_s.powi(3) + c[1] * theta_s * theta_s + c[2] * theta_s + c[3];
+        let x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0]) +
+                t * cubic([-0.02903, 0.06377, -0.03202, 0.00394]) +
+                cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0]) +
+                t * cubic([-0.04214, 0.08970, -0.04153, 0.00516]) +
+                cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let mut sky = Sky { sun, turbidity, perez, zenith: [luminance, x, y], ground: Colour::new(0.0, 0.0, 0.0) };
+        // the ground is lit by the sun and the sky above it
+        let mut irradiance = Colour::new(0.0, 0.0, 0.0);
+        for i in 0..GROUND_STEPS {
+            let cos_theta = (i as f64 + 0.5) / GROUND_STEPS as f64;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..GROUND_STEPS {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / GROUND_STEPS as f64;
+                let d = Vector::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                // each step covers an equal solid angle of the hemisphere
+                irradiance = irradiance + sky.above(d) * (cos_theta * 2.0 * PI / (GROUND_STEPS * GROUND_STEPS) as f64);
+            }
+        }
+        sky.ground = (irradiance * (1.0 / PI) + sky.sun_intensity() * sun.y()) * ground_albedo;
+        sky
+    }
+
+    // the sky's radiance along d, a unit vector above the horizon
+    fn above(&self, d: Vector) -> Colour {
+        let cos_theta = d.y();
+        let gamma = d.dot(&self.sun).clamp(-1.0, 1.0).acos();
+        let theta_s = self.sun.y().clamp(0.0, 1.0).acos();
+        let channel = |i: usize| {
+            let p = &self.perez[i];
+            self.zenith[i] * p.f(cos_theta, gamma) / p.f(1.0, theta_s)
+        };
+        // from xyY, with Y in thousands of candelas per square metre, through
+        // XYZ to linear sRGB
+        let (luminance, x, y) = (channel(0).max(0.0) * PI / 100.0, channel(1), channel(2));
+        let big_x = x * luminance / y;
+        let big_z = (1.0 - x - y) * luminance / y;
+        Colour::new((3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+                    (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+                    (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0))
+    }
+
+    // The light arriving from direction -d: the sky above the horizon, and
+    // evenly lit ground below it. The sun's disc isn't included, its light
+    // comes from sun_light instead.
+    pub fn radiance(&self, d: Vector) -> Colour {
+        if d.y() < 0.0 { self.ground } else { self.above(d) }
+    }
+
+    // Sunlight after passing through the atmosphere, which scatters away more
+    // blue than red, and more of everything the lower the sun is, following
+    // the Rayleigh and aerosol terms of Preetham's model.
+    pub fn sun_intensity(&self) -> Colour {
+        let elevation = 90.0 - self.sun.y().clamp(0.0, 1.0).acos().to_degrees();
+        // the relative length of the sunlight's path through the air
+        let mass = 1.0 / (self.sun.y().max(0.0) + 0.15 * (elevation + 3.885).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = |lambda: f64| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+            SOLAR_CONSTANT * rayleigh * aerosol
+        };
+        Colour::new(transmittance(WAVELENGTHS[0]), transmittance(WAVELENGTHS[1]), transmittance(WAVELENGTHS[2]))
+    }
+
+    // the sun as a directional light whose disc is angle radians across,
+    // giving shadows that soften with distance, sampled in as many directions
+    pub fn sun_light(&self, angle: f64, samples: usize) -> Light {
+        Light { shape: LightShape::Disc { angle, samples }, ..Light::directional(self.sun_intensity(), -self.sun) }
+    }
+}
+
+#[cfg(test)]
+mod sky_tests {
+    use super::*;
+
+    fn sky(elevation: f64, turbidity: f64) -> Sky {
+        Sky::new(Vector::new(elevation.cos(), elevation.sin(), 0.0), turbidity, 0.3)
+    }
+
+    #[test]
+    fn clear_skies_are_blue() {
+        let s = sky(0.8, 2.5);
+        let zenith = s.radiance(Vector::new(0.0, 1.0, 0.0));
+        assert!(zenith.b > zenith.g && zenith.g > zenith.r);
+        // overhead, a clear sky is a few percent as bright as full sunlight
+        assert!(zenith.luminance() > 0.05 && zenith.luminance() < 0.5);
+        // brighter around the sun than away from it
+        let near = s.radiance(Vector::new(0.8_f64.cos(), 0.8_f64.sin() + 0.2, 0.0).normalize());
+        let away = s.radiance(Vector::new(-0.6, 0.7, 0.0).normalize());
+        assert!(near.luminance() > 2.0 * away.luminance());
+        // and hazier skies are whiter
+        let hazy = sky(0.8, 8.0).radiance(Vector::new(0.0, 1.0, 0.0));
+        assert!(hazy.b / hazy.r < zenith.b / zenith.r);
+    }
+
+    #[test]
+    fn ground() {
+        let s = sky(0.8, 3.0);
+        let down = s.radiance(Vector::new(0.0, -1.0, 0.0));
+        assert_eq!(down, s.radiance(Vector::new(0.3, -0.1, 0.8).normalize()));
+        // the ground reflects part of the sunlight falling on it, and the sky's
+        let sun = s.sun_intensity() * (0.8_f64.sin() * 0.3);
+        assert!(down.g > sun.g && down.g < sun.g * 1.5);
+        assert_eq!(Sky::new(Vector::new(0.8_f64.cos(), 0.8_f64.sin(), 0.0), 3.0, 0.0).radiance(Vector::new(0.0, -1.0, 0.0)), Colour::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn sunlight() {
+        let noon = sky(1.4, 3.0).sun_intensity();
+        assert!(noon.g > 0.8 && noon.g < SOLAR_CONSTANT);
+        // redder and dimmer as it sets, or through haze
+        let evening = sky(0.1, 3.0).sun_intensity();
+        assert!(evening.g < noon.g && evening.b / evening.r < noon.b / noon.r);
+        assert!(sky(1.4, 8.0).sun_intensity().g < noon.g);
+
+        let light = sky(1.0, 3.0).sun_light(SUN_ANGLE, 8);
+        assert_eq!(light.intensity, sky(1.0, 3.0).sun_intensity());
+        let (towards, _) = light.incident(Vector::new(0.0, 0.0, 0.0), light.pos);
+        assert!((towards - Vector::new(1.0_f64.cos(), 1.0_f64.sin(), 0.0)).norm() < 1e-9);
+    }
+}
@@ -1,18 +1,27 @@
 //use na::{Vector3, dot};
 
+use std::f64::consts::PI;
+use std::sync::Arc;
+
 use super::scene_object::SceneObject;
 use super::scene_object::Material;
 use super::ray;
-use super::transformation::{Transformation, Vector};
+use super::sampling;
+use super::texture;
+use super::transformation::{Motion, Transformation, Vector};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Sphere {
     pub material: Material,
     pub trans: Transformation,
+    // when set, the object moves and trans is ignored
+    pub motion: Option<Arc<Motion>>,
 }
 
 impl SceneObject for Sphere {
-    fn intersect(&self, r: ray::Ray) -> Vec<ray::Intersection> {
+    fn intersect(&self, r: ray::Ray) -> Vec<ray::Intersection<'_>> {
+        let inv = self.transformation_at(r.time).inverse();
+        let r = ray::Ray::new(inv.point(r.origin), inv.direction(r.dir));
 
         let sphere_to_ray = r.origin - Vector::new(0.0, 0.0, 0.0);
         let a = r.dir.dot(&r.dir);
@@ -26,11 +35,11 @@ impl SceneObject for Sphere {
         if discriminant >= 0.0 { 
             let t1 = ray::Intersection {
                 t: (-b - discriminant.sqrt()) / (2.0 * a),
-                object: Box::new(*self),
+                object: self,
             };
             let t2 = ray::Intersection { 
                 t: (-b + discriminant.sqrt()) / (2.0 * a),
-                object: Box::new(*self),
+                object: self,
             };
             if t1.t < t2.t { 
                 hits.push(t1);
@@ -43,10 +52,47 @@ impl SceneObject for Sphere {
         hits
     }
 
-    fn normal(&self, p: Vector) -> Vector {
-        let object_normal = (p - Vector::new(0.0, 0.0, 0.0)).normalize();
-        //self.transm.try_inverse().unwrap().transpose() * object_normal
-        object_normal
+    fn local_normal(&self, p: Vector) -> Vector {
+        (p - Vector::new(0.0, 0.0, 0.0)).normalize()
+    }
+
+    // around the y axis and up towards the north pole, following the
+    // spherical uv mapping
+    fn local_tangents(&self, p: Vector) -> (Vector, Vector) {
+        let rho = (p.x() * p.x() + p.z() * p.z()).sqrt();
+        if rho < 1e-12 {
+            return (Vector::new(0.0, 0.0, 0.0), Vector::new(0.0, 0.0, 0.0));
+        }
+        (Vector::new(-p.z(), 0.0, p.x()), Vector::new(-p.y() * p.x() / rho, rho, -p.y() * p.z() / rho))
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn transformation(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn motion(&self) -> Option<&Motion> {
+        self.motion.as_deref()
+    }
+
+    fn uv(&self, p: Vector) -> (f64, f64) {
+        texture::spherical(p)
+    }
+
+    // evenly over the unit sphere, then stretched by the transformation
+    fn sample_surface(&self, u: f64, v: f64, time: f64) -> Option<Vector> {
+        Some(self.transformation_at(time).point(sampling::uniform_sphere(u, v)))
+    }
+
+    fn surface_pdf(&self, p: Vector, time: f64) -> f64 {
+        // how much the transformation stretches the surface around p
+        let trans = self.transformation_at(time);
+        let (a, b) = trans.inverse().point(p).normalize().orthonormal_basis();
+        let stretch = trans.direction(a).cross(&trans.direction(b)).norm();
+        1.0 / (4.0 * PI * stretch)
     }
 }
 
@@ -54,12 +100,15 @@ impl Sphere {
     pub fn new() -> Sphere {
         let m = Material::new();
         Sphere {material: m,
-                trans: Transformation::new()}
+                trans: Transformation::new(),
+                motion: None}
     }
 }
 
+#[cfg(test)]
 mod sphere_tests {
     use super::*;
+    use random::Rng;
     #[test]
     fn intersection_tangent() {
         let r = ray::Ray::new(Vector::new(0.0, 1.0, -5.0), Vector::new(0.0, 0.0, 1.0)); 
@@ -122,13 +171,66 @@ mod sphere_tests {
     #[test]
     fn normals() {
         let s = Sphere::new();
-        let s3o3 = (3.0 as f64).sqrt() / 3.0;
-        assert_eq!(s.normal(Vector::new(1.0, 0.0, 0.0)), Vector::new(1.0, 0.0, 0.0));
-        assert_eq!(s.normal(Vector::new(0.0, 1.0, 0.0)), Vector::new(0.0, 1.0, 0.0));
-        assert_eq!(s.normal(Vector::new(0.0, 0.0, 1.0)), Vector::new(0.0, 0.0, 1.0));
-        assert_eq!(s.normal(Vector::new(s3o3, s3o3, s3o3)), Vector::new(s3o3, s3o3, s3o3));
+        let s3o3 = 3.0_f64.sqrt() / 3.0;
+        assert_eq!(s.normal(Vector::new(1.0, 0.0, 0.0), 0.0), Vector::new(1.0, 0.0, 0.0));
+        assert_eq!(s.normal(Vector::new(0.0, 1.0, 0.0), 0.0), Vector::new(0.0, 1.0, 0.0));
+        assert_eq!(s.normal(Vector::new(0.0, 0.0, 1.0), 0.0), Vector::new(0.0, 0.0, 1.0));
+        assert_eq!(s.normal(Vector::new(s3o3, s3o3, s3o3), 0.0), Vector::new(s3o3, s3o3, s3o3));
 
-        assert_eq!(s.normal(Vector::new(s3o3, s3o3, s3o3)), Vector::new(s3o3, s3o3, s3o3).normalize());
+        assert_eq!(s.normal(Vector::new(s3o3, s3o3, s3o3), 0.0), Vector::new(s3o3, s3o3, s3o3).normalize());
+    }
+
+    #[test]
+    fn tangents() {
+        let s = Sphere::new();
+        let (dpdu, dpdv) = s.local_tangents(Vector::new(0.0, 0.0, -1.0));
+        assert_eq!(dpdu, Vector::new(1.0, 0.0, 0.0));
+        assert_eq!(dpdv, Vector::new(0.0, 1.0, 0.0));
+        let p = Vector::new(0.48, 0.6, 0.64);
+        let (dpdu, dpdv) = s.local_tangents(p);
+        assert!(dpdu.dot(&p).abs() < 1e-12 && dpdv.dot(&p).abs() < 1e-12);
+        // u and v increase along them
+        let (u, v) = s.uv(p);
+        let (u2, v2) = s.uv(p + dpdu * 1e-4);
+        let (u3, v3) = s.uv(p + dpdv * 1e-4);
+        assert!(u2 > u && (v2 - v).abs() < 1e-6);
+        assert!(v3 > v && (u3 - u).abs() < 1e-6);
     }
-}
 
+    #[test]
+    fn surface_sampling() {
+        let mut s = Sphere::new();
+        s.trans = Transformation::new().translate(1.0, 0.0, 0.0).scale(2.0, 2.0, 2.0);
+        let p = s.sample_surface(0.3, 0.7, 0.0).unwrap();
+        assert!(((p - Vector::new(1.0, 0.0, 0.0)).norm() - 2.0).abs() < 1e-12);
+        assert!((s.surface_pdf(p, 0.0) - 1.0 / (16.0 * PI)).abs() < 1e-12);
+
+        // a stretched sphere is sampled unevenly, which its pdf accounts for:
+        // averaging 1 / pdf gives the area of the spheroid
+        s.trans = Transformation::new().scale(2.0, 1.0, 1.0);
+        let mut rng = Rng::new(1);
+        let n = 20000;
+        let area = (0..n).map(|_| 1.0 / s.surface_pdf(s.sample_surface(rng.next_f64(), rng.next_f64(), 0.0).unwrap(), 0.0)).sum::<f64>() / n as f64;
+        let e = 0.75_f64.sqrt();
+        let expected = 2.0 * PI * (1.0 + 2.0 / e * e.asin());
+        assert!((area - expected).abs() < 0.01 * expected);
+    }
+
+    #[test]
+    fn moving() {
+        let mut s = Sphere::new();
+        let start = Transformation::new();
+        let end = Transformation::new().translate(0.0, 4.0, 0.0);
+        s.motion = Some(Arc::new(Motion::new(&[(0.0, start), (1.0, end)]).unwrap()));
+        let r = ray::Ray::new(Vector::new(0.0, 2.0, -5.0), Vector::new(0.0, 0.0, 1.0));
+        // the ray hits the sphere halfway through its move, and nowhere else
+        assert!(s.intersect(r).is_empty());
+        assert_eq!(s.intersect(r.with_time(0.5)).len(), 2);
+        assert!(s.intersect(r.with_time(1.0)).is_empty());
+        // and its surface is found where it is at the time
+        assert_eq!(s.normal(Vector::new(0.0, 3.0, 0.0), 0.5), Vector::new(0.0, 1.0, 0.0));
+        assert_eq!(s.normal(Vector::new(0.0, 5.0, 0.0), 1.0), Vector::new(0.0, 1.0, 0.0));
+        let p = s.sample_surface(0.3, 0.7, 1.0).unwrap();
+        assert!(((p - Vector::new(0.0, 4.0, 0.0)).norm() - 1.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,243 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use super::canvas::{Canvas, Colour};
+use super::pattern::Pattern;
+use super::transformation::{Transformation, Vector};
+
+// All mappings take a point in pattern space and return (u, v) in [0, 1],
+// with v = 0 at the bottom of the image.
+
+// latitude and longitude on a sphere around the origin
+pub fn spherical(p: Vector) -> (f64, f64) {
+    let theta = p.x().atan2(p.z());
+    let r = p.norm();
+    let phi = if r > 0.0 { (p.y() / r).clamp(-1.0, 1.0).acos() } else { 0.0 };
+    (1.0 - (theta / (2.0 * PI) + 0.5), 1.0 - phi / PI)
+}
+
+// the xz plane, repeating every unit
+pub fn planar(p: Vector) -> (f64, f64) {
+    (p.x().rem_euclid(1.0), p.z().rem_euclid(1.0))
+}
+
+// around the y axis, repeating every unit up it
+pub fn cylindrical(p: Vector) -> (f64, f64) {
+    let theta = p.x().atan2(p.z());
+    (1.0 - (theta / (2.0 * PI) + 0.5), p.y().rem_euclid(1.0))
+}
+
+// The faces of the cube from -1 to 1, laid out as a horizontal cross four
+// faces wide and three high:
+//          up
+//   left front right back
+//         down
+pub fn cube(p: Vector) -> (f64, f64) {
+    let (x, y, z) = (p.x(), p.y(), p.z());
+    let m = x.abs().max(y.abs()).max(z.abs());
+    let face = |a: f64, b: f64| ((a + 1.0).rem_euclid(2.0) / 2.0, (b + 1.0).rem_euclid(2.0) / 2.0);
+    let (column, row, (u, v)) = if m == x {
+        (2.0, 1.0, face(-z, y))
+    } else if m == -x {
+        (0.0, 1.0, face(z, y))
+    } else if m == y {
+        (1.0, 2.0, face(x, -z))
+    } else if m == -y {
+        (1.0, 0.0, face(x, z))
+    } else if m == z {
+        (1.0, 1.0, face(x, y))
+    } else {
+        (3.0, 1.0, face(-x, y))
+    };
+    ((column + u) / 4.0, (row + v) / 3.0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mapping {
+    // the object's own coordinates, such as the vt of an obj mesh
+    Object,
+    Spherical,
+    Planar,
+    Cylindrical,
+    Cube,
+}
+
+impl Mapping {
+    pub fn from_name(name: &str) -> Option<Mapping> {
+        match name {
+            "object" => Some(Mapping::Object),
+            "spherical" => Some(Mapping::Spherical),
+            "planar" => Some(Mapping::Planar),
+            "cylindrical" => Some(Mapping::Cylindrical),
+            "cube" => Some(Mapping::Cube),
+            _ => None,
+        }
+    }
+
+    pub fn uv(&self, p: Vector, object_uv: (f64, f64)) -> (f64, f64) {
+        match *self {
+            Mapping::Object => object_uv,
+            Mapping::Spherical => spherical(p),
+            Mapping::Planar => planar(p),
+            Mapping::Cylindrical => cylindrical(p),
+            Mapping::Cube => cube(p),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+}
+
+// what happens to coordinates outside [0, 1]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+}
+
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub image: Arc<Canvas>,
+    pub filter: TextureFilter,
+    pub wrap: Wrap,
+}
+
+impl Texture {
+    pub fn new(image: Arc<Canvas>) -> Texture {
+        Texture { image, filter: TextureFilter::Bilinear, wrap: Wrap::Repeat }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Colour {
+        let (w, h) = (self.image.width() as i64, self.image.height() as i64);
+        let (x, y) = match self.wrap {
+            Wrap::Repeat => (x.rem_euclid(w), y.rem_euclid(h)),
+            Wrap::Clamp => (x.clamp(0, w - 1), y.clamp(0, h - 1)),
+        };
+        self.image.read(x as usize, y as usize)
+    }
+
+    pub fn sample(&self, u: f64, v: f64) -> Colour {
+        if self.image.width() == 0 || self.image.height() == 0 {
+            return Colour::new(0.0, 0.0, 0.0);
+        }
+        // texel centres sit at half pixel positions
+        let x = u * self.image.width() as f64 - 0.5;
+        let y = (1.0 - v) * self.image.height() as f64 - 0.5;
+        match self.filter {
+            TextureFilter::Nearest => self.texel(x.round() as i64, y.round() as i64),
+            TextureFilter::Bilinear => {
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let top = self.texel(x0, y0) * (1.0 - tx) + self.texel(x0 + 1, y0) * tx;
+                let bottom = self.texel(x0, y0 + 1) * (1.0 - tx) + self.texel(x0 + 1, y0 + 1) * tx;
+                top * (1.0 - ty) + bottom * ty
+            },
+        }
+    }
+}
+
+// an image wrapped onto the surface through a uv mapping
+#[derive(Debug)]
+pub struct TextureMap {
+    pub texture: Texture,
+    pub mapping: Mapping,
+    pub trans: Transformation,
+}
+
+impl Pattern for TextureMap {
+    fn transform(&self) -> &Transformation {
+        &self.trans
+    }
+
+    fn local_colour_at(&self, p: Vector, uv: (f64, f64)) -> Colour {
+        let (u, v) = self.mapping.uv(p, uv);
+        self.texture.sample(u, v)
+    }
+}
+
+#[cfg(test)]
+mod texture_tests {
+    use super::*;
+
+    fn close(a: (f64, f64), b: (f64, f64)) -> bool {
+        (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9
+    }
+
+    #[test]
+    fn mappings() {
+        assert!(close(spherical(Vector::new(0.0, 0.0, -1.0)), (0.0, 0.5)));
+        assert!(close(spherical(Vector::new(1.0, 0.0, 0.0)), (0.25, 0.5)));
+        assert!(close(spherical(Vector::new(0.0, 1.0, 0.0)), (0.5, 1.0)));
+        assert!(close(spherical(Vector::new(0.0, -1.0, 0.0)), (0.5, 0.0)));
+
+        assert!(close(planar(Vector::new(0.25, 0.0, 0.5)), (0.25, 0.5)));
+        assert!(close(planar(Vector::new(-0.25, 0.5, -1.75)), (0.75, 0.25)));
+
+        assert!(close(cylindrical(Vector::new(0.0, 0.0, -1.0)), (0.0, 0.0)));
+        assert!(close(cylindrical(Vector::new(1.0, 0.5, 0.0)), (0.25, 0.5)));
+    }
+
+    #[test]
+    fn cube_faces() {
+        // the centre of each face lands in the middle of its cell of the cross
+        assert!(close(cube(Vector::new(0.0, 0.0, 1.0)), (0.375, 0.5)));
+        assert!(close(cube(Vector::new(1.0, 0.0, 0.0)), (0.625, 0.5)));
+        assert!(close(cube(Vector::new(-1.0, 0.0, 0.0)), (0.125, 0.5)));
+        assert!(close(cube(Vector::new(0.0, 0.0, -1.0)), (0.875, 0.5)));
+        assert!(close(cube(Vector::new(0.0, 1.0, 0.0)), (0.375, 2.5 / 3.0)));
+        assert!(close(cube(Vector::new(0.0, -1.0, 0.0)), (0.375, 0.5 / 3.0)));
+        // corners of the front face
+        assert!(close(cube(Vector::new(-0.9, 0.9, 1.0)), (0.2625, 1.95 / 3.0)));
+    }
+
+    fn checker_image() -> Arc<Canvas> {
+        let mut image = Canvas::new(2, 2);
+        image.write(0, 0, Colour::new(1.0, 0.0, 0.0));
+        image.write(1, 0, Colour::new(0.0, 1.0, 0.0));
+        image.write(0, 1, Colour::new(0.0, 0.0, 1.0));
+        image.write(1, 1, Colour::new(1.0, 1.0, 1.0));
+        Arc::new(image)
+    }
+
+    #[test]
+    fn nearest() {
+        let t = Texture { filter: TextureFilter::Nearest, ..Texture::new(checker_image()) };
+        // v = 1 is the top row of the image
+        assert_eq!(t.sample(0.25, 0.75), Colour::new(1.0, 0.0, 0.0));
+        assert_eq!(t.sample(0.75, 0.75), Colour::new(0.0, 1.0, 0.0));
+        assert_eq!(t.sample(0.25, 0.25), Colour::new(0.0, 0.0, 1.0));
+        assert_eq!(t.sample(1.25, 0.75), Colour::new(1.0, 0.0, 0.0));
+        let clamped = Texture { wrap: Wrap::Clamp, ..t.clone() };
+        assert_eq!(clamped.sample(1.25, 0.75), Colour::new(0.0, 1.0, 0.0));
+        assert_eq!(clamped.sample(-3.0, 0.75), Colour::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn bilinear() {
+        let t = Texture::new(checker_image());
+        // texel centres are exact, half way between them is the average
+        assert_eq!(t.sample(0.25, 0.75), Colour::new(1.0, 0.0, 0.0));
+        assert_eq!(t.sample(0.5, 0.75), Colour::new(0.5, 0.5, 0.0));
+        assert_eq!(t.sample(0.5, 0.5), Colour::new(0.5, 0.5, 0.5));
+        // repeating blends across the seam, clamping doesn't
+        assert_eq!(t.sample(0.0, 0.75), Colour::new(0.5, 0.5, 0.0));
+        let clamped = Texture { wrap: Wrap::Clamp, ..t.clone() };
+        assert_eq!(clamped.sample(0.0, 0.75), Colour::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn texture_map() {
+        let map = TextureMap {
+            texture: Texture { filter: TextureFilter::Nearest, ..Texture::new(checker_image()) },
+            mapping: Mapping::Planar,
+            trans: Transformation::new(),
+        };
+        assert_eq!(map.colour_at(Vector::new(0.25, 0.0, 0.75), (0.0, 0.0)), Colour::new(1.0, 0.0, 0.0));
+        let object = TextureMap { mapping: Mapping::Object, ..map };
+        assert_eq!(object.colour_at(Vector::new(0.25, 0.0, 0.75), (0.75, 0.25)), Colour::new(1.0, 1.0, 1.0));
+    }
+}
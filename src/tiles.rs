@@ -0,0 +1,128 @@
+use std::path::Path;
+
+use super::canvas::Canvas;
+
+// Parts of an image rendered apart, perhaps on other machines, and put back
+// together. Each part's file is named after where its top left pixel goes and
+// the size of the whole image, so the part of a 1920x1080 out/shot.png from
+// (0, 540) is out/shot_tile_0_540_1920x1080.png.
+
+pub fn tile_filename(filename: &str, x: usize, y: usize, width: usize, height: usize) -> String {
+    let path = Path::new(filename);
+    let stem = path.file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+    let name = match path.extension() {
+        Some(ext) => format!("{}_tile_{}_{}_{}x{}.{}", stem, x, y, width, height, ext.to_string_lossy()),
+        None => format!("{}_tile_{}_{}_{}x{}", stem, x, y, width, height),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+// where the part tile_filename named goes, and the size of the image it's
+// part of, as x, y, width, height
+pub fn tile_position(filename: &str) -> Option<(usize, usize, usize, usize)> {
+    let stem = Path::new(filename).file_stem()?.to_string_lossy().into_owned();
+    let mut parts = stem.rsplitn(5, '_');
+    let mut size = parts.next()?.splitn(2, 'x');
+    let (width, height) = (size.next()?.parse().ok()?, size.next()?.parse().ok()?);
+    let (y, x) = (parts.next()?.parse().ok()?, parts.next()?.parse().ok()?);
+    if parts.next() != Some("tile") || parts.next().is_none_or(|s| s.is_empty()) {
+        return None;
+    }
+    Some((x, y, width, height))
+}
+
+// The rows from y0 up to y1 of the index'th of count bands, counting from 1,
+// that an image height pixels tall is split into. Bands differ in height by
+// at most a row.
+pub fn band(index: usize, count: usize, height: usize) -> (usize, usize) {
+    ((index - 1) * height / count, index * height / count)
+}
+
+// The width by height image the parts make up, each at its (x, y), which has
+// to be covered by them exactly once.
+pub fn merge(width: usize, height: usize, parts: &[(usize, usize, Canvas)]) -> Result<Canvas, String> {
+    let mut image = Canvas::new(width, height);
+    let mut covered = vec![vec![false; height]; width];
+    for &(x0, y0, ref part) in parts {
+        if x0 + part.width() > width || y0 + part.height() > height {
+            return Err(format!("the tile at ({}, {}) goes past the edge of the {}x{} image", x0, y0, width, height));
+        }
+        for y in 0..part.height() {
+            for x in 0..part.width() {
+                let (px, py) = (x0 + x, y0 + y);
+                if covered[px][py] {
+                    return Err(format!("tiles overlap at pixel ({}, {})", px, py));
+                }
+                covered[px][py] = true;
+                image.write(px, py, part.read(x, y));
+            }
+        }
+    }
+    // the corners of the smallest rectangle holding every pixel left out
+    let missing: Vec<(usize, usize)> = (0..width).flat_map(|x| (0..height).map(move |y| (x, y)))
+                                                 .filter(|&(x, y)| !covered[x][y])
+                                                 .collect();
+    if !missing.is_empty() {
+        let (x0, y0) = (missing.iter().map(|p| p.0).min().unwrap(), missing.iter().map(|p| p.1).min().unwrap());
+        let (x1, y1) = (missing.iter().map(|p| p.0).max().unwrap(), missing.iter().map(|p| p.1).max().unwrap());
+        return Err(format!("no tile covers {} pixels, between ({}, {}) and ({}, {})", missing.len(), x0, y0, x1, y1));
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tiles_tests {
+    use super::*;
+    use canvas::Colour;
+
+    // a part of image, from (x0, y0) up to but not including (x1, y1)
+    fn cut(image: &Canvas, x0: usize, y0: usize, x1: usize, y1: usize) -> Canvas {
+        let mut part = Canvas::new(x1 - x0, y1 - y0);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                part.write(x - x0, y - y0, image.read(x, y));
+            }
+        }
+        part
+    }
+
+    #[test]
+    fn filenames() {
+        assert_eq!(tile_filename("out/shot.png", 0, 540, 1920, 1080), "out/shot_tile_0_540_1920x1080.png");
+        assert_eq!(tile_filename("shot", 12, 3, 40, 20), "shot_tile_12_3_40x20");
+        assert_eq!(tile_position("out/shot_tile_0_540_1920x1080.png"), Some((0, 540, 1920, 1080)));
+        assert_eq!(tile_position("shot_0012_tile_12_3_40x20"), Some((12, 3, 40, 20)));
+        assert_eq!(tile_position("shot_0012.png"), None);
+        assert_eq!(tile_position("shot_tile_0_540.png"), None);
+        assert_eq!(tile_position("shot_tile_0_540_1920.png"), None);
+        assert_eq!(tile_position("shot_tiles_1_2_3x4.png"), None);
+        assert_eq!(tile_position("_tile_1_2_3x4.png"), None);
+    }
+
+    #[test]
+    fn bands() {
+        let bands: Vec<(usize, usize)> = (1..=3).map(|i| band(i, 3, 10)).collect();
+        assert_eq!(bands, vec!((0, 3), (3, 6), (6, 10)));
+        assert_eq!(band(1, 1, 7), (0, 7));
+    }
+
+    #[test]
+    fn merging() {
+        let mut image = Canvas::new(5, 4);
+        for x in 0..5 {
+            for y in 0..4 {
+                image.write(x, y, Colour::new(x as f64, y as f64, 0.0));
+            }
+        }
+        let parts = vec!((0, 0, cut(&image, 0, 0, 5, 2)), (0, 2, cut(&image, 0, 2, 3, 4)), (3, 2, cut(&image, 3, 2, 5, 4)));
+        assert_eq!(merge(5, 4, &parts).unwrap().data, image.data);
+
+        assert_eq!(merge(5, 4, &parts[..2]).err(), Some("no tile covers 4 pixels, between (3, 2) and (4, 3)".to_string()));
+        // missing the right or bottom edge is noticed too
+        assert_eq!(merge(6, 4, &parts).err(), Some("no tile covers 4 pixels, between (5, 0) and (5, 3)".to_string()));
+        assert_eq!(merge(5, 5, &parts).err(), Some("no tile covers 5 pixels, between (0, 4) and (4, 4)".to_string()));
+        assert_eq!(merge(4, 4, &parts).err(), Some("the tile at (0, 0) goes past the edge of the 4x4 image".to_string()));
+        let overlapping = vec!((0, 0, cut(&image, 0, 0, 5, 3)), (0, 2, cut(&image, 0, 2, 5, 4)));
+        assert_eq!(merge(5, 4, &overlapping).err(), Some("tiles overlap at pixel (0, 2)".to_string()));
+    }
+}
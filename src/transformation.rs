@@ -1,4 +1,4 @@
-use na::{Vector3, Vector4, Matrix4};
+use na::{Vector3, Vector4, Matrix3, Matrix4, Rotation3, Quaternion, UnitQuaternion, U1, U3};
 use std::ops::{Add, Sub, Mul, Neg};
 use std::f64;
 use approx::{abs_diff_eq, relative_eq};
@@ -38,11 +38,28 @@ impl Vector {
         Vector3::dot(&v, &u)
     }
 
+    pub fn cross(&self, other: &Vector) -> Vector {
+        let v = Vector3::new(self.v.x, self.v.y, self.v.z);
+        let u = Vector3::new(other.v.x, other.v.y, other.v.z);
+        let c = v.cross(&u);
+        Vector::new(c.x, c.y, c.z)
+    }
+
     pub fn normalize(&self) -> Vector {
         let v = Vector3::new(self.v.x, self.v.y, self.v.z).normalize();
         Vector::new(v.x, v.y, v.z)
     }
 
+    // two unit vectors perpendicular to this (unit) vector and each other
+    pub fn orthonormal_basis(&self) -> (Vector, Vector) {
+        // Duff et al, "Building an Orthonormal Basis, Revisited"
+        let sign = 1.0_f64.copysign(self.z());
+        let a = -1.0 / (sign + self.z());
+        let b = self.x() * self.y() * a;
+        (Vector::new(1.0 + sign * self.x() * self.x() * a, sign * b, -sign * self.x()),
+         Vector::new(b, sign + self.y() * self.y() * a, -self.y()))
+    }
+
     pub fn reflect(self, normal: &Vector) -> Vector {
         self - *normal * 2.0 * self.dot(normal)
     }
@@ -92,7 +109,7 @@ impl approx::AbsDiffEq for Vector {
     }
 
     fn abs_diff_eq(&self, other: &Vector, epsilon: f64) -> bool {
-        abs_diff_eq!(self.v, other.v)
+        abs_diff_eq!(self.v, other.v, epsilon = epsilon)
     }
 }
 
@@ -102,7 +119,7 @@ impl approx::RelativeEq for Vector {
     }
 
     fn relative_eq(&self, other: &Vector, epsilon: f64, max_relative: f64) -> bool {
-        relative_eq!(self.v, other.v)
+        relative_eq!(self.v, other.v, epsilon = epsilon, max_relative = max_relative)
     }
 }
 
@@ -124,12 +141,21 @@ mod vector_tests {
     #[test]
     fn reflection() {
         let v = Vector::new(0.0, -1.0, 0.0);
-        let n = Vector::new((2.0 as f64).sqrt()/2.0, (2.0 as f64).sqrt()/2.0, 0.0);
+        let n = Vector::new(2.0_f64.sqrt()/2.0, 2.0_f64.sqrt()/2.0, 0.0);
         
         let reflected = v.reflect(&n);
         assert!(relative_eq!(reflected, Vector::new(1.0, 0.0, 0.0)));
     }
 
+    #[test]
+    fn basis() {
+        for n in &[Vector::new(0.0, 0.0, 1.0), Vector::new(0.0, 0.0, -1.0), Vector::new(1.0, 2.0, -3.0).normalize()] {
+            let (a, b) = n.orthonormal_basis();
+            assert!(a.dot(n).abs() < 1e-12 && b.dot(n).abs() < 1e-12 && a.dot(&b).abs() < 1e-12);
+            assert!((a.norm() - 1.0).abs() < 1e-12 && (b.norm() - 1.0).abs() < 1e-12);
+        }
+    }
+
     #[test]
     fn norm() {
         assert!(relative_eq!(Vector::new(1.0, 0.0, 0.0).norm(), 1.0));
@@ -144,6 +170,7 @@ mod vector_tests {
         assert!(relative_eq!(Vector::new(4.0, 0.0, 0.0).normalize(), Vector::new(1.0, 0.0, 0.0)));
     }
 
+    #[test]
     fn dot() {
         let u = Vector::new(1.0, 2.0, 3.0);
         let v = Vector::new(2.0, 3.0, 4.0);
@@ -164,36 +191,162 @@ impl Transformation {
             invm: Matrix4::identity(), }
     }
 
+    pub fn inverse(&self) -> Transformation {
+        Transformation { transm: self.invm, invm: self.transm }
+    }
+
+    pub fn point(&self, p: Vector) -> Vector {
+        let v = self.transm * p.v;
+        Vector::new(v.x, v.y, v.z)
+    }
+
+    pub fn direction(&self, d: Vector) -> Vector {
+        let v = self.transm * Vector4::new(d.x(), d.y(), d.z(), 0.0);
+        Vector::new(v.x, v.y, v.z)
+    }
+
+    pub fn normal(&self, n: Vector) -> Vector {
+        let v = self.invm.transpose() * Vector4::new(n.x(), n.y(), n.z(), 0.0);
+        Vector::new(v.x, v.y, v.z).normalize()
+    }
+
+    fn from_matrix(transm: Matrix4<f64>) -> Transformation {
+        Transformation {
+            transm,
+            invm: transm.try_inverse().expect("transformation is not invertible"),
+        }
+    }
+
+    // m is applied to points before the existing transformation
+    fn append(&self, m: Matrix4<f64>) -> Transformation {
+        Transformation::from_matrix(self.transm * m)
+    }
+
     pub fn scale(&self, x: f64, y: f64, z: f64) -> Transformation {
         let t = Vector3::new(x, y, z);
-        let m = Matrix4::new_nonuniform_scaling(&t);
-        Transformation { 
-            transm: self.transm * m,
-            invm: (self.transm * m).try_inverse().unwrap(),
-        }
+        self.append(Matrix4::new_nonuniform_scaling(&t))
     }
 
-    pub fn shear(&self, x: f64, y: f64, z: f64) {
+    pub fn shear(&self, xy: f64, xz: f64, yx: f64, yz: f64, zx: f64, zy: f64) -> Transformation {
+        let m = Matrix4::new(1.0, xy,  xz,  0.0,
+                             yx,  1.0, yz,  0.0,
+                             zx,  zy,  1.0, 0.0,
+                             0.0, 0.0, 0.0, 1.0);
+        self.append(m)
     }
 
-    pub fn rotate(&self, x: f64, y: f64, z: f64) {
+    // rotates about x, then y, then z (radians)
+    pub fn rotate(&self, x: f64, y: f64, z: f64) -> Transformation {
+        let rx = Rotation3::from_axis_angle(&Vector3::x_axis(), x);
+        let ry = Rotation3::from_axis_angle(&Vector3::y_axis(), y);
+        let rz = Rotation3::from_axis_angle(&Vector3::z_axis(), z);
+        self.append((rz * ry * rx).to_homogeneous())
     }
 
-    pub fn translate(&mut self, x: f64, y: f64, z: f64) -> Transformation {
+    pub fn translate(&self, x: f64, y: f64, z: f64) -> Transformation {
         let t = Vector3::new(x, y, z);
-        let m = Matrix4::new_translation(&t);
-        Transformation { 
-            transm: self.transm * m,
-            invm: (self.transm * m).try_inverse().unwrap(),
+        self.append(Matrix4::new_translation(&t))
+    }
+}
+
+// A transformation split into a stretch, then a rotation, then a translation,
+// so that blending two of them turns objects rather than squashing them. The
+// stretch is symmetric, and takes any scaling and shearing.
+#[derive(Debug, Clone, Copy)]
+struct Pose {
+    translation: Vector3<f64>,
+    rotation: UnitQuaternion<f64>,
+    stretch: Matrix3<f64>,
+}
+
+impl Pose {
+    // the polar decomposition of t, from its singular value decomposition
+    fn new(t: &Transformation) -> Pose {
+        let m: Matrix3<f64> = t.transm.fixed_slice::<U3, U3>(0, 0).into_owned();
+        let svd = m.svd(true, true);
+        let (u, v_t) = (svd.u.unwrap(), svd.v_t.unwrap());
+        let mut rotation = u * v_t;
+        let mut stretch = v_t.transpose() * Matrix3::from_diagonal(&svd.singular_values) * v_t;
+        // mirror images are stretched by -1 rather than rotated inside out
+        if rotation.determinant() < 0.0 {
+            rotation = -rotation;
+            stretch = -stretch;
+        }
+        Pose {
+            translation: Vector3::new(t.transm[(0, 3)], t.transm[(1, 3)], t.transm[(2, 3)]),
+            rotation: UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(rotation)),
+            stretch,
+        }
+    }
+
+    fn mirrored(&self) -> bool {
+        self.stretch.determinant() < 0.0
+    }
+
+    // f of the way from self to other, turning the shorter way round
+    fn blend(&self, other: &Pose, f: f64) -> Pose {
+        let mut to = other.rotation;
+        if self.rotation.coords.dot(&to.coords) < 0.0 {
+            to = UnitQuaternion::new_unchecked(Quaternion::from(-to.coords));
+        }
+        Pose {
+            translation: self.translation.lerp(&other.translation, f),
+            rotation: self.rotation.try_slerp(&to, f, 1e-9).unwrap_or(self.rotation),
+            stretch: self.stretch * (1.0 - f) + other.stretch * f,
         }
     }
+
+    fn transformation(&self) -> Transformation {
+        let mut m = Matrix4::identity();
+        m.fixed_slice_mut::<U3, U3>(0, 0).copy_from(&(self.rotation.to_rotation_matrix().matrix() * self.stretch));
+        m.fixed_slice_mut::<U3, U1>(0, 3).copy_from(&self.translation);
+        Transformation::from_matrix(m)
+    }
+}
+
+// A transformation that changes over time, passing through each keyframe at
+// its time and holding still before the first and after the last.
+#[derive(Debug, Clone)]
+pub struct Motion {
+    keys: Vec<(f64, Pose)>,
+}
+
+impl Motion {
+    // Keys are (time, transformation) pairs in any order, at least one of
+    // them. A mirrored keyframe can't follow an unmirrored one, or the other
+    // way round, since anything blended between them is squashed flat on the
+    // way.
+    pub fn new(keys: &[(f64, Transformation)]) -> Result<Motion, String> {
+        assert!(!keys.is_empty(), "motion needs a keyframe");
+        let mut keys: Vec<(f64, Pose)> = keys.iter().map(|&(time, ref t)| (time, Pose::new(t))).collect();
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        for pair in keys.windows(2) {
+            let ((t0, ref a), (t1, ref b)) = (pair[0], pair[1]);
+            if a.mirrored() != b.mirrored() {
+                return Err(format!("the keyframes at {} and {} can't be blended, as only one of them is mirrored", t0, t1));
+            }
+        }
+        Ok(Motion { keys })
+    }
+
+    pub fn at(&self, time: f64) -> Transformation {
+        let next = self.keys.partition_point(|&(t, _)| t <= time);
+        if next == 0 {
+            return self.keys[0].1.transformation();
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1.transformation();
+        }
+        let ((t0, ref a), (t1, ref b)) = (self.keys[next - 1], self.keys[next]);
+        a.blend(b, (time - t0) / (t1 - t0)).transformation()
+    }
 }
 
 impl Mul for Transformation {
     type Output = Transformation;
     fn mul(self, other: Transformation) -> Transformation {
         Transformation {transm: self.transm * other.transm,
-                        invm: self.invm * other.invm}
+                        invm: other.invm * self.invm}
     }
 }
 
@@ -215,14 +368,14 @@ impl Mul<Vector3<f64>> for Transformation {
 
 impl Mul<Vector> for Transformation {
     type Output = Transformation;
-    fn mul(self, other: Vector) -> Transformation {
+    fn mul(self, _other: Vector) -> Transformation {
         Transformation::new()
     }
 }
 
 impl Mul<Transformation> for Vector {
     type Output = Transformation;
-    fn mul(self, other: Transformation) -> Transformation {
+    fn mul(self, _other: Transformation) -> Transformation {
         Transformation::new()
     }
 }
@@ -254,6 +407,102 @@ mod transformation_tests {
         let m = Transformation::new();
         let n = Transformation::new();
         assert!(relative_eq!( (m*n).transm, Matrix4::identity()));
+
+        let a = Transformation::new().translate(1.0, 2.0, 3.0);
+        let b = Transformation::new().scale(2.0, 2.0, 2.0);
+        assert!(relative_eq!((a*b).invm, (a*b).transm.try_inverse().unwrap()));
+    }
+
+    #[test]
+    fn points_and_directions() {
+        let t = Transformation::new().translate(5.0, -3.0, 2.0);
+        let p = Vector::new(-3.0, 4.0, 5.0);
+        assert!(relative_eq!(t.point(p), Vector::new(2.0, 1.0, 7.0)));
+        assert!(relative_eq!(t.inverse().point(p), Vector::new(-8.0, 7.0, 3.0)));
+        assert!(relative_eq!(t.direction(p), p));
+
+        let s = Transformation::new().scale(2.0, 3.0, 4.0);
+        assert!(relative_eq!(s.point(Vector::new(-4.0, 6.0, 8.0)), Vector::new(-8.0, 18.0, 32.0)));
+        assert!(relative_eq!(s.direction(Vector::new(-4.0, 6.0, 8.0)), Vector::new(-8.0, 18.0, 32.0)));
+    }
+
+    #[test]
+    fn rotation() {
+        let h = 2.0_f64.sqrt() / 2.0;
+        let p = Vector::new(0.0, 1.0, 0.0);
+        let half_quarter = Transformation::new().rotate(f64::consts::FRAC_PI_4, 0.0, 0.0);
+        assert!(abs_diff_eq!(half_quarter.point(p), Vector::new(0.0, h, h), epsilon = 1e-10));
+
+        let z = Vector::new(0.0, 0.0, 1.0);
+        let y_quarter = Transformation::new().rotate(0.0, f64::consts::FRAC_PI_2, 0.0);
+        assert!(abs_diff_eq!(y_quarter.point(z), Vector::new(1.0, 0.0, 0.0), epsilon = 1e-10));
+
+        let z_quarter = Transformation::new().rotate(0.0, 0.0, f64::consts::FRAC_PI_2);
+        assert!(abs_diff_eq!(z_quarter.point(p), Vector::new(-1.0, 0.0, 0.0), epsilon = 1e-10));
+    }
+
+    #[test]
+    fn shear() {
+        let p = Vector::new(2.0, 3.0, 4.0);
+        let t = Transformation::new().shear(1.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+        assert!(relative_eq!(t.point(p), Vector::new(5.0, 3.0, 4.0)));
+        let t = Transformation::new().shear(0.0, 0.0, 0.0, 0.0, 0.0, 1.0);
+        assert!(relative_eq!(t.point(p), Vector::new(2.0, 3.0, 7.0)));
     }
 
+    #[test]
+    fn chaining() {
+        // the last call in a chain is applied to points first
+        let p = Vector::new(1.0, 0.0, 1.0);
+        let t = Transformation::new().translate(10.0, 5.0, 7.0)
+                                     .scale(5.0, 5.0, 5.0)
+                                     .rotate(f64::consts::FRAC_PI_2, 0.0, 0.0);
+        assert!(abs_diff_eq!(t.point(p), Vector::new(15.0, 0.0, 7.0), epsilon = 1e-10));
+    }
+
+
+    #[test]
+    fn motion() {
+        let start = Transformation::new().translate(0.0, 0.0, 0.0);
+        let end = Transformation::new().translate(2.0, 4.0, 0.0).rotate(0.0, f64::consts::FRAC_PI_2, 0.0).scale(3.0, 3.0, 3.0);
+        let m = Motion::new(&[(1.0, end), (0.0, start)]).unwrap();
+        let p = Vector::new(1.0, 0.0, 0.0);
+        // held still outside the keyframes, and passing through them
+        assert!(relative_eq!(m.at(-1.0).point(p), p, epsilon = 1e-9));
+        assert!(relative_eq!(m.at(0.0).point(p), p, epsilon = 1e-9));
+        assert!(relative_eq!(m.at(1.0).point(p), end.point(p), epsilon = 1e-9));
+        assert!(relative_eq!(m.at(2.0).point(p), end.point(p), epsilon = 1e-9));
+
+        // halfway it has turned by half as much, rather than cut the corner,
+        // and grown and moved halfway
+        let half = m.at(0.5);
+        let turned = Vector::new(std::f64::consts::FRAC_PI_4.cos(), 0.0, -std::f64::consts::FRAC_PI_4.sin()) * 2.0;
+        assert!(relative_eq!(half.point(p), turned + Vector::new(1.0, 2.0, 0.0), epsilon = 1e-9));
+        assert!(relative_eq!(half.inverse().point(half.point(p)), p, epsilon = 1e-9));
+    }
+
+    #[test]
+    fn motion_keeps_shears_and_mirrors() {
+        let sheared = Transformation::new().shear(1.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+        let m = Motion::new(&[(0.0, Transformation::new()), (1.0, sheared)]).unwrap();
+        let p = Vector::new(0.5, 2.0, -1.0);
+        assert!(relative_eq!(m.at(1.0).point(p), sheared.point(p), epsilon = 1e-9));
+        let half = m.at(0.5);
+        assert!(relative_eq!(half.inverse().point(half.point(p)), p, epsilon = 1e-9));
+
+        // a mirror image turns as itself, and stays the same size
+        let mirrored = Transformation::new().scale(-1.0, 1.0, 1.0);
+        let turned = Transformation::new().rotate(0.0, f64::consts::FRAC_PI_2, 0.0).scale(-1.0, 1.0, 1.0);
+        let m = Motion::new(&[(0.0, mirrored), (1.0, turned)]).unwrap();
+        let q = Vector::new(1.0, 0.0, 0.0);
+        assert!(relative_eq!(m.at(0.0).point(q), mirrored.point(q), epsilon = 1e-9));
+        assert!(relative_eq!(m.at(1.0).point(q), turned.point(q), epsilon = 1e-9));
+        let halfway = Transformation::new().rotate(0.0, f64::consts::FRAC_PI_4, 0.0).scale(-1.0, 1.0, 1.0);
+        assert!(relative_eq!(m.at(0.5).point(q), halfway.point(q), epsilon = 1e-9));
+        assert!(relative_eq!(m.at(0.5).point(p).norm(), p.norm(), epsilon = 1e-9));
+
+        // but can't be blended with something that isn't mirrored
+        let flipping = Motion::new(&[(0.0, Transformation::new()), (2.0, sheared), (1.0, mirrored)]);
+        assert_eq!(flipping.err(), Some("the keyframes at 0 and 1 can't be blended, as only one of them is mirrored".to_string()));
+    }
 }
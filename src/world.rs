@@ -0,0 +1,381 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use super::canvas::Colour;
+use super::environment::Environment;
+use super::light::{self, Light, LightKind};
+use super::transformation::Vector;
+use super::random::Rng;
+use super::ray::{self, Intersection, Ray};
+use super::scene_object::SceneObject;
+
+// how far to push secondary ray origins off a surface so they don't hit it again
+pub const EPSILON: f64 = 1e-6;
+
+// p nudged off the surface with normal n to the side dir leaves from
+pub fn offset(p: Vector, n: Vector, dir: Vector) -> Vector {
+    if dir.dot(&n) < 0.0 { p - n * EPSILON } else { p + n * EPSILON }
+}
+
+// samples per side of the grid used to measure an emitter's area
+const AREA_STEPS: usize = 4;
+
+// where an object is in memory, which identifies it among the world's objects
+fn address(object: &dyn SceneObject) -> usize {
+    object as *const dyn SceneObject as *const u8 as usize
+}
+
+// The objects with an emissive material, picked in proportion to the light
+// they give off, which is their emission times their area.
+struct Emitters {
+    objects: Vec<usize>,
+    // the running total of the chances of picking each object, ending at 1
+    cdf: Vec<f64>,
+    chances: HashMap<usize, f64>,
+}
+
+impl Emitters {
+    fn new(objects: &[Box<dyn SceneObject>]) -> Emitters {
+        let mut emitters = Emitters { objects: vec!(), cdf: vec!(), chances: HashMap::new() };
+        let mut powers = vec!();
+        for (i, o) in objects.iter().enumerate() {
+            let power = o.material().emission.luminance() * area(&**o);
+            if power > 0.0 {
+                emitters.objects.push(i);
+                powers.push(power);
+            }
+        }
+        let total: f64 = powers.iter().sum();
+        let mut sum = 0.0;
+        for (&i, power) in emitters.objects.iter().zip(powers) {
+            sum += power / total;
+            emitters.cdf.push(sum);
+            emitters.chances.insert(address(&*objects[i]), power / total);
+        }
+        emitters
+    }
+}
+
+// the area of the object's surface, as it is at time 0, or 0 when it can't be
+// sampled
+fn area(object: &dyn SceneObject) -> f64 {
+    let mut total = 0.0;
+    for i in 0..AREA_STEPS * AREA_STEPS {
+        let u = ((i / AREA_STEPS) as f64 + 0.5) / AREA_STEPS as f64;
+        let v = ((i % AREA_STEPS) as f64 + 0.5) / AREA_STEPS as f64;
+        match object.sample_surface(u, v, 0.0) {
+            Some(p) if object.surface_pdf(p, 0.0) > 0.0 => total += 1.0 / object.surface_pdf(p, 0.0),
+            _ => return 0.0,
+        }
+    }
+    total / (AREA_STEPS * AREA_STEPS) as f64
+}
+
+pub struct World {
+    pub objects: Vec<Box<dyn SceneObject>>,
+    pub lights: Vec<Light>,
+    // seen by rays that miss every object
+    pub environment: Environment,
+    // the id each object is written with in the object id output, so that a
+    // mesh's triangles can share one; objects past the end of it are numbered
+    // one more than their index
+    pub object_ids: Vec<usize>,
+    // found the first time they're needed, so objects shouldn't be changed
+    // once rendering has started
+    emitters: OnceLock<Emitters>,
+    // each object's index, by address
+    indices: OnceLock<HashMap<usize, usize>>,
+}
+
+impl World {
+    pub fn new() -> World {
+        World {
+            objects: vec!(),
+            lights: vec!(),
+            environment: Environment::new(),
+            object_ids: vec!(),
+            emitters: OnceLock::new(),
+            indices: OnceLock::new(),
+        }
+    }
+
+    fn emitters(&self) -> &Emitters {
+        self.emitters.get_or_init(|| Emitters::new(&self.objects))
+    }
+
+    // An emissive object chosen with u in [0, 1), and the chance of it being
+    // chosen, or None when nothing in the world glows.
+    pub fn pick_emitter(&self, u: f64) -> Option<(&dyn SceneObject, f64)> {
+        let emitters = self.emitters();
+        if emitters.objects.is_empty() {
+            return None;
+        }
+        let i = emitters.cdf.partition_point(|&c| c <= u).min(emitters.objects.len() - 1);
+        let object = &*self.objects[emitters.objects[i]];
+        Some((object, self.emitter_chance(object)))
+    }
+
+    // the chance of pick_emitter choosing object
+    pub fn emitter_chance(&self, object: &dyn SceneObject) -> f64 {
+        self.emitters().chances.get(&address(object)).cloned().unwrap_or(0.0)
+    }
+
+    // the id object, one of the world's, is written with in the object id output
+    pub fn object_id(&self, object: &dyn SceneObject) -> usize {
+        let indices = self.indices.get_or_init(|| self.objects.iter().enumerate().map(|(i, o)| (address(&**o), i)).collect());
+        let i = indices[&address(object)];
+        self.object_ids.get(i).cloned().unwrap_or(i + 1)
+    }
+
+    pub fn intersect(&self, r: Ray) -> Vec<Intersection<'_>> {
+        let mut hits: Vec<Intersection> = self.objects.iter()
+                                                      .flat_map(|o| o.intersect(r))
+                                                      .collect();
+        hits.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap_or(Ordering::Equal));
+        hits
+    }
+
+    // whether anything is hit along r before distance
+    pub fn occluded(&self, r: Ray, distance: f64) -> bool {
+        self.objects.iter().any(|o| o.intersect(r).iter().any(|i| i.t > 0.0 && i.t < distance))
+    }
+
+    // whether anything is in the way between p and light_pos at time
+    pub fn is_shadowed(&self, p: Vector, light_pos: Vector, time: f64) -> bool {
+        let v = light_pos - p;
+        self.occluded(Ray::new(p, v.normalize()).with_time(time), v.norm())
+    }
+
+    // Jittered points on the light as seen from p at time, None where
+    // something is in the way. Directional lights have no points, so each of
+    // their directions gives the light's position, which shading ignores.
+    pub fn light_samples(&self, p: Vector, l: &Light, time: f64, rng: &mut Rng) -> Vec<Option<Vector>> {
+        if let LightKind::Directional { .. } = l.kind {
+            return l.directions(Some(rng)).into_iter()
+                .map(|d| if self.occluded(Ray::new(p, d).with_time(time), f64::INFINITY) { None } else { Some(l.pos) })
+                .collect();
+        }
+        l.sample_points(p, Some(rng)).into_iter()
+            .map(|s| if self.is_shadowed(p, s, time) { None } else { Some(s) })
+            .collect()
+    }
+
+    // the fraction of the light's sample points that can be seen from p at time
+    pub fn visibility(&self, p: Vector, l: &Light, time: f64, rng: &mut Rng) -> f64 {
+        let samples = self.light_samples(p, l, time, rng);
+        samples.iter().filter(|s| s.is_some()).count() as f64 / samples.len() as f64
+    }
+
+    // remaining is how many more bounces a reflected or refracted ray may take
+    pub fn colour_at(&self, r: Ray, remaining: usize, rng: &mut Rng) -> Colour {
+        match ray::get_hit(self.intersect(r)) {
+            Some(hit) => {
+                let m = hit.object.material();
+                let point = r.position(hit.t);
+                let eye = -r.dir;
+                let outward = hit.object.normal(point, r.time);
+                let colour = hit.object.colour_at(point, r.time);
+                let normal = if outward.dot(&eye) < 0.0 { -outward } else { outward };
+
+                let over_point = point + normal * EPSILON;
+                // emissive objects glow, but only the path tracer lets them
+                // light anything else
+                let mut surface = m.emission;
+                for l in &self.lights {
+                    // shading uses the same points the shadow test picked
+                    let samples = self.light_samples(over_point, l, r.time, rng);
+                    surface = surface + match m.bsdf {
+                        Some(_) => light::bsdf_lighting(m, colour, point, *l, eye, outward, &samples),
+                        None => light::lighting(m, colour, point, *l, eye, normal, &samples),
+                    };
+                }
+                if remaining == 0 {
+                    return surface;
+                }
+                match m.bsdf {
+                    // smooth lobes are followed like mirrors, picking one at
+                    // random where there are several, such as glass
+                    Some(ref bsdf) => match bsdf.sample(eye, outward, colour, rng) {
+                        Some(s) if s.specular => {
+                            let next = Ray::new(offset(point, normal, s.wi), s.wi).with_time(r.time);
+                            surface + self.colour_at(next, remaining - 1, rng) * s.weight
+                        },
+                        _ => surface,
+                    },
+                    None => surface,
+                }
+            },
+            None => self.environment.radiance(r.dir),
+        }
+    }
+}
+
+#[cfg(test)]
+mod world_tests {
+    use super::*;
+    use bsdf::Conductor;
+    use environment::Background;
+    use scene_object::Material;
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+    use light::LightShape;
+    use sphere::Sphere;
+    use transformation::{Motion, Transformation};
+
+    fn two_spheres() -> World {
+        let outer = Sphere::new();
+        let mut inner = Sphere::new();
+        inner.trans = Transformation::new().scale(0.5, 0.5, 0.5);
+        let mut world = World::new();
+        world.objects = vec!(Box::new(outer), Box::new(inner));
+        world.lights = vec!(Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(-10.0, 10.0, -10.0)));
+        world
+    }
+
+    #[test]
+    fn intersections_are_sorted() {
+        let w = two_spheres();
+        let r = Ray::new(Vector::new(0.0, 0.0, -5.0), Vector::new(0.0, 0.0, 1.0));
+        let ts: Vec<f64> = w.intersect(r).iter().map(|i| i.t).collect();
+        assert_eq!(ts, vec!(4.0, 4.5, 5.5, 6.0));
+    }
+
+    #[test]
+    fn miss_is_black() {
+        let w = two_spheres();
+        let r = Ray::new(Vector::new(0.0, 0.0, -5.0), Vector::new(0.0, 1.0, 0.0));
+        assert_eq!(w.colour_at(r, 5, &mut Rng::new(0)), Colour::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn misses_see_the_environment() {
+        let mut w = two_spheres();
+        w.environment.background = Background::Gradient { bottom: Colour::new(0.0, 0.0, 0.0), top: Colour::new(0.0, 0.0, 1.0) };
+        let r = Ray::new(Vector::new(0.0, 0.0, -5.0), Vector::new(0.0, 1.0, 0.0));
+        assert_eq!(w.colour_at(r, 5, &mut Rng::new(0)), Colour::new(0.0, 0.0, 1.0));
+        // but isn't lit by it
+        let r = Ray::new(Vector::new(0.0, 0.0, -5.0), Vector::new(0.0, 0.0, 1.0));
+        assert_eq!(w.colour_at(r, 5, &mut Rng::new(0)), two_spheres().colour_at(r, 5, &mut Rng::new(0)));
+    }
+
+    #[test]
+    fn lights_are_summed() {
+        let mut w = two_spheres();
+        let r = Ray::new(Vector::new(0.0, 0.0, -5.0), Vector::new(0.0, 0.0, 1.0));
+        let one = w.colour_at(r, 5, &mut Rng::new(0));
+        w.lights.push(Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(-10.0, 10.0, -10.0)));
+        assert_eq!(w.colour_at(r, 5, &mut Rng::new(0)), one * 2.0);
+    }
+
+    #[test]
+    fn smooth_bsdfs_are_followed() {
+        // a ray bounces back and forth between two mirrors until it runs out
+        // of depth, picking up the light between them each time
+        let mut m = Material::new();
+        m.bsdf = Some(Arc::new(Conductor { roughness: 0.0, f0: Some(Colour::new(1.0, 1.0, 1.0)) }));
+        let mut a = Sphere::new();
+        a.material = m.clone();
+        a.trans = Transformation::new().translate(0.0, 0.0, 3.0);
+        let mut b = Sphere::new();
+        b.material = m;
+        b.trans = Transformation::new().translate(0.0, 0.0, -3.0);
+        let mut w = World::new();
+        w.objects = vec!(Box::new(a), Box::new(b));
+        w.lights = vec!(Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, 0.0, 0.0)));
+        let r = Ray::new(Vector::new(0.0, 0.0, 0.0), Vector::new(0.0, 0.0, 1.0));
+        let direct = w.colour_at(r, 0, &mut Rng::new(0));
+        assert_eq!(direct, Colour::new(0.1, 0.1, 0.1));
+        assert_eq!(w.colour_at(r, 3, &mut Rng::new(0)), direct * 4.0);
+    }
+
+    #[test]
+    fn emitters_are_picked_by_power() {
+        assert!(two_spheres().pick_emitter(0.5).is_none());
+
+        // the same glow over four times the area gives off four times the light
+        let mut w = two_spheres();
+        let mut small = Sphere::new();
+        small.material.emission = Colour::new(1.0, 1.0, 1.0);
+        let mut large = small.clone();
+        large.trans = Transformation::new().translate(5.0, 0.0, 0.0).scale(2.0, 2.0, 2.0);
+        w.objects.push(Box::new(small));
+        w.objects.push(Box::new(large));
+        let (picked, chance) = w.pick_emitter(0.1).unwrap();
+        assert!((picked.surface_pdf(picked.sample_surface(0.5, 0.5, 0.0).unwrap(), 0.0) - 1.0 / (4.0 * PI)).abs() < 1e-12);
+        assert!((chance - 0.2).abs() < 1e-12);
+        let (picked, chance) = w.pick_emitter(0.9).unwrap();
+        assert!((chance - 0.8).abs() < 1e-12);
+        assert!((w.emitter_chance(picked) - 0.8).abs() < 1e-12);
+        assert_eq!(w.emitter_chance(&*w.objects[0]), 0.0);
+    }
+
+    #[test]
+    fn shadows() {
+        let w = two_spheres();
+        let light = w.lights[0].pos;
+        assert!(!w.is_shadowed(Vector::new(0.0, 10.0, 0.0), light, 0.0));
+        assert!(w.is_shadowed(Vector::new(10.0, -10.0, 10.0), light, 0.0));
+        assert!(!w.is_shadowed(Vector::new(-20.0, 20.0, -20.0), light, 0.0));
+        assert!(!w.is_shadowed(Vector::new(-2.0, 2.0, -2.0), light, 0.0));
+    }
+
+    #[test]
+    fn moving_shadows() {
+        // a sphere that crosses between the point and the light halfway
+        // through the shutter casts its shadow only then
+        let mut w = two_spheres();
+        let mut s = Sphere::new();
+        let keys = [(0.0, Transformation::new().translate(-5.0, 5.0, 0.0)), (1.0, Transformation::new().translate(5.0, 5.0, 0.0))];
+        s.motion = Some(Arc::new(Motion::new(&keys).unwrap()));
+        w.objects = vec!(Box::new(s));
+        let (p, light) = (Vector::new(0.0, 0.0, 0.0), Vector::new(0.0, 10.0, 0.0));
+        assert!(!w.is_shadowed(p, light, 0.0));
+        assert!(w.is_shadowed(p, light, 0.5));
+        assert!(!w.is_shadowed(p, light, 1.0));
+        // and rays only see it where it is at their own time
+        let r = Ray::new(Vector::new(0.0, 5.0, -5.0), Vector::new(0.0, 0.0, 1.0));
+        assert!(w.colour_at(r, 1, &mut Rng::new(1)) == w.environment.radiance(r.dir));
+        assert!(w.colour_at(r.with_time(0.5), 1, &mut Rng::new(1)) != w.environment.radiance(r.dir));
+    }
+
+    #[test]
+    fn soft_shadows() {
+        let mut w = two_spheres();
+        w.lights[0] = Light {
+            shape: LightShape::Rect { u: Vector::new(1.0, 0.0, 0.0), v: Vector::new(0.0, 1.0, 0.0), usteps: 8, vsteps: 8 },
+            ..Light::new(Colour::new(1.0, 1.0, 1.0), Vector::new(-0.5, -0.5, -5.0))
+        };
+        let mut rng = Rng::new(0);
+        // in the middle of the shadow, at its edge, and in full light
+        assert_eq!(w.visibility(Vector::new(0.0, 0.0, 2.0), &w.lights[0], 0.0, &mut rng), 0.0);
+        let edge = w.visibility(Vector::new(1.45, 0.0, 2.0), &w.lights[0], 0.0, &mut rng);
+        assert!(edge > 0.0 && edge < 1.0);
+        assert_eq!(w.visibility(Vector::new(0.0, 3.0, 2.0), &w.lights[0], 0.0, &mut rng), 1.0);
+
+        // the points shaded from are the jittered ones the shadow test used,
+        // with only the hidden ones left out
+        let p = Vector::new(1.45, 0.0, 2.0);
+        let samples = w.light_samples(p, &w.lights[0], 0.0, &mut Rng::new(4));
+        let points = w.lights[0].sample_points(p, Some(&mut Rng::new(4)));
+        assert!(points.iter().zip(w.lights[0].sample_points(p, None)).any(|(j, c)| *j != c));
+        for (s, q) in samples.iter().zip(points.iter()) {
+            assert_eq!(*s, if w.is_shadowed(p, *q, 0.0) { None } else { Some(*q) });
+        }
+    }
+
+    #[test]
+    fn directional_shadows() {
+        let mut w = two_spheres();
+        w.lights[0] = Light::directional(Colour::new(1.0, 1.0, 1.0), Vector::new(0.0, -1.0, 0.0));
+        let mut rng = Rng::new(0);
+        assert_eq!(w.visibility(Vector::new(0.0, -100.0, 0.0), &w.lights[0], 0.0, &mut rng), 0.0);
+        assert_eq!(w.visibility(Vector::new(1.5, -100.0, 0.0), &w.lights[0], 0.0, &mut rng), 1.0);
+
+        // a directional light with some size casts soft shadows
+        w.lights[0].shape = LightShape::Disc { angle: 0.2, samples: 64 };
+        assert_eq!(w.visibility(Vector::new(0.0, -2.0, 0.0), &w.lights[0], 0.0, &mut rng), 0.0);
+        let edge = w.visibility(Vector::new(1.0, -2.0, 0.0), &w.lights[0], 0.0, &mut rng);
+        assert!(edge > 0.2 && edge < 0.8);
+    }
+}